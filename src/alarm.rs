@@ -0,0 +1,296 @@
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Local, NaiveTime, Timelike, Utc};
+use cron::Schedule;
+use tokio::time::sleep;
+
+use crate::api::{get_playback_state, set_shuffle, set_volume, start_playback, start_playing, transfer_playback, DeviceResolver, PlaybackRequest};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::Device;
+use crate::timestamp::parse_timestamp;
+
+/// Devices such as speakers on standby may take a moment to show up in the device list.
+const DEVICE_ATTEMPTS: u32 = 3;
+const DEVICE_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+const RAMP_STEPS: u32 = 20;
+
+#[derive(Debug)]
+pub enum AlarmSchedule {
+    Once(DateTime<Utc>),
+    /// Evaluated in the local time zone.
+    Cron(Box<Schedule>),
+}
+
+impl AlarmSchedule {
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            AlarmSchedule::Once(at) => Some(*at).filter(|at| *at > after),
+            AlarmSchedule::Cron(schedule) => schedule.after(&after.with_timezone(&Local))
+                .next()
+                .map(|at| at.with_timezone(&Utc)),
+        }
+    }
+}
+
+impl FromStr for AlarmSchedule {
+    type Err = anyhow::Error;
+
+    /// Accepts a cron expression with five (minute first) or six and seven (second first) fields,
+    /// "HH:MM" for every day at that time, or a single timestamp.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let fields = s.split_whitespace().count();
+        let expression = match fields {
+            5 => Some(format!("0 {}", crontab_weekdays(s))),
+            6 | 7 => Some(s.to_owned()),
+            _ => NaiveTime::parse_from_str(s, "%H:%M").ok()
+                .map(|time| format!("0 {} {} * * *", time.minute(), time.hour())),
+        };
+
+        match expression {
+            Some(expression) => Schedule::from_str(&expression)
+                .map(|schedule| AlarmSchedule::Cron(Box::new(schedule)))
+                .map_err(|e| anyhow!("Invalid cron expression \"{}\": {}", s, e)),
+            None => parse_timestamp(s).map(AlarmSchedule::Once),
+        }
+    }
+}
+
+const WEEKDAYS: [&str; 8] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Crontab numbers weekdays from 0 for Sunday, while the cron crate starts at 1 for Sunday,
+/// so numeric weekdays in a five-field expression are spelled out by name.
+fn crontab_weekdays(expression: &str) -> String {
+    let mut fields = expression.split_whitespace().map(|field| field.to_owned()).collect::<Vec<_>>();
+    let mut weekdays = String::new();
+    let mut number = String::new();
+    for c in fields[4].chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        // Numbers after a step such as "*/2" are intervals, not weekdays.
+        let is_step = weekdays.ends_with('/');
+        match number.parse::<usize>().ok().and_then(|day| WEEKDAYS.get(day)) {
+            Some(name) if !is_step => weekdays.push_str(name),
+            _ => weekdays.push_str(&number),
+        }
+        number.clear();
+        weekdays.push(c);
+    }
+    fields[4] = weekdays.trim_end().to_owned();
+    fields.join(" ")
+}
+
+#[derive(Debug, Default)]
+pub struct AlarmSettings {
+    /// Device selector as accepted by `DeviceResolver`; the configured default when unset.
+    pub device: Option<String>,
+    /// Context to play; playback resumes where it left off when unset.
+    pub context_uri: Option<String>,
+    pub volume: Option<u32>,
+    /// Raises the volume from silence to `volume` over this long.
+    pub ramp: Option<Duration>,
+    pub shuffle: Option<bool>,
+}
+
+pub struct Alarm {
+    tokens: TokenKeeper,
+    settings: AlarmSettings,
+}
+
+impl Alarm {
+    pub fn new(config: &Rc<SpotifyConfig>, settings: AlarmSettings) -> Self {
+        Self {
+            tokens: TokenKeeper::new(config),
+            settings,
+        }
+    }
+
+    /// Starts playback right away and returns the device it plays on.
+    pub async fn ring(&self) -> Result<Device> {
+        self.ring_with(&self.tokens.config().await).await
+    }
+
+    /// Rings at every time in the schedule, returning once it has no more times left.
+    pub async fn run(&self, schedule: &AlarmSchedule) -> Result<()> {
+        if let AlarmSchedule::Once(at) = schedule {
+            if *at <= Utc::now() {
+                bail!("The alarm time {} has already passed", at.with_timezone(&Local).format("%Y-%m-%d %H:%M"));
+            }
+        }
+        while let Some(at) = schedule.next_after(Utc::now()) {
+            let wait = (at - Utc::now()).to_std().unwrap_or_default();
+            sleep(wait).await;
+
+            // The access token expires long before a morning alarm. Refreshing through the
+            // keeper carries a rotated refresh token over to the next ring.
+            let result = match self.tokens.refresh().await {
+                Ok(()) => self.ring_with(&self.tokens.config().await).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(device) => println!("{}: playing on {}", Local::now().format("%Y-%m-%d %H:%M"), device.name),
+                Err(e) => eprintln!("{}: {:#}", Local::now().format("%Y-%m-%d %H:%M"), e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn ring_with(&self, config: &Rc<SpotifyConfig>) -> Result<Device> {
+        let device = self.find_device(config).await?;
+        let device_id = device.id.clone()
+            .ok_or_else(|| anyhow!("Device \"{}\" has no id", device.name))?;
+        let volume = match (self.settings.volume, self.settings.ramp) {
+            (Some(volume), _) => Some(volume.min(100)),
+            (None, Some(_)) => Some(device.volume_percent
+                .ok_or_else(|| anyhow!("Device \"{}\" does not report its volume; give a volume to ramp to", device.name))?),
+            (None, None) => None,
+        };
+
+        transfer_playback(config, &device_id, false).await?;
+        // A ramp starts from silence, so nothing blares out before it begins.
+        if volume.is_some() && self.settings.ramp.is_some() {
+            set_volume(config, &device_id, 0).await?;
+        }
+        match &self.settings.context_uri {
+            Some(uri) => start_playback(config, &device_id, PlaybackRequest::context(uri)).await?,
+            None => start_playing(config, &device_id).await?,
+        }
+        // Devices that were idle may drop settings made before playback starts on them.
+        if let Some(shuffle) = self.settings.shuffle {
+            set_shuffle(config, &device_id, shuffle).await?;
+        }
+        match (volume, self.settings.ramp) {
+            (Some(volume), Some(ramp)) => ramp_up(config, &device_id, volume, ramp).await?,
+            (Some(volume), None) => set_volume(config, &device_id, volume).await?,
+            (None, _) => {}
+        }
+
+        Ok(device)
+    }
+
+    async fn find_device(&self, config: &Rc<SpotifyConfig>) -> Result<Device> {
+        let mut attempt = 1;
+        loop {
+            let resolver = DeviceResolver::fetch(config).await?;
+            match resolver.resolve(self.settings.device.as_deref()) {
+                Ok(device) => return Ok(device.clone()),
+                Err(e) if attempt >= DEVICE_ATTEMPTS => return Err(e),
+                Err(_) => {}
+            }
+            attempt += 1;
+            sleep(DEVICE_RETRY_DELAY).await;
+        }
+    }
+}
+
+/// Raises the volume step by step, stopping early if someone sets the volume by hand.
+async fn ramp_up(config: &Rc<SpotifyConfig>, device_id: &str, volume: u32, ramp: Duration) -> Result<()> {
+    let step = ramp / RAMP_STEPS;
+    let mut previous = 0;
+    let mut current = 0;
+    for index in 1..=RAMP_STEPS {
+        sleep(step).await;
+        let state = get_playback_state(config).await?;
+        let reported = state.as_ref()
+            .filter(|state| state.device.id.as_deref() == Some(device_id))
+            .and_then(|state| state.device.volume_percent);
+        match reported {
+            Some(reported) if set_by_hand(reported, current, previous) => return Ok(()),
+            Some(_) => {}
+            None => bail!("Playback left the device while raising the volume"),
+        }
+
+        previous = current;
+        current = volume * index / RAMP_STEPS;
+        set_volume(config, device_id, current).await?;
+    }
+    Ok(())
+}
+
+/// Whether a reported volume matches neither of the last two steps. The player may still report
+/// the step before the last one, so that alone does not count as someone changing the volume.
+fn set_by_hand(reported: u32, current: u32, previous: u32) -> bool {
+    reported.abs_diff(current) > 1 && reported.abs_diff(previous) > 1
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn crontab_weekday_numbers() {
+        let cases = [
+            ("30 7 * * 1-5", "30 7 * * Mon-Fri"),
+            ("30 7 * * 0,6", "30 7 * * Sun,Sat"),
+            ("30 7 * * 7", "30 7 * * Sun"),
+            ("30 7 * * 1-5/2", "30 7 * * Mon-Fri/2"),
+            ("30 7 * * */2", "30 7 * * */2"),
+            ("30 7 * * Mon-Fri", "30 7 * * Mon-Fri"),
+            ("30 7 1 * *", "30 7 1 * *"),
+            ("30 7 * * 8", "30 7 * * 8"),
+        ];
+        for (expression, expected) in cases.iter() {
+            assert_eq!(crontab_weekdays(expression), *expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn schedules() {
+        // Saturday 2024-06-01, local time.
+        let saturday = Local.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap().with_timezone(&Utc);
+        let next = |schedule: &str| {
+            let schedule = schedule.parse::<AlarmSchedule>().unwrap();
+            schedule.next_after(saturday).unwrap().with_timezone(&Local).format("%a %H:%M:%S").to_string()
+        };
+        let cases = [
+            ("30 7 * * 1-5", "Mon 07:30:00"),
+            ("30 7 * * 0", "Sun 07:30:00"),
+            ("30 7 * * 0,3", "Sun 07:30:00"),
+            ("30 7 * * 7", "Sun 07:30:00"),
+            ("30 7 * * Wed", "Wed 07:30:00"),
+            ("15 30 7 * * Tue", "Tue 07:30:15"),
+            ("06:45", "Sun 06:45:00"),
+            ("13:00", "Sat 13:00:00"),
+        ];
+        for (schedule, expected) in cases.iter() {
+            assert_eq!(next(schedule), *expected, "{}", schedule);
+        }
+    }
+
+    #[test]
+    fn one_time_schedules() {
+        let schedule = "2024-06-01T07:30:00Z".parse::<AlarmSchedule>().unwrap();
+        let at = Utc.with_ymd_and_hms(2024, 6, 1, 7, 30, 0).unwrap();
+        assert!(matches!(schedule, AlarmSchedule::Once(time) if time == at));
+        assert_eq!(schedule.next_after(at - chrono::Duration::seconds(1)), Some(at));
+        assert_eq!(schedule.next_after(at), None);
+    }
+
+    #[test]
+    fn invalid_schedules() {
+        for schedule in ["30 7 * * 8", "61 7 * * *", "30 7 * * Funday", "25:00", "tomorrow-ish", ""].iter() {
+            assert!(schedule.parse::<AlarmSchedule>().is_err(), "{}", schedule);
+        }
+        let error = "30 7 * * 8".parse::<AlarmSchedule>().unwrap_err().to_string();
+        assert!(error.starts_with("Invalid cron expression \"30 7 * * 8\""), "{}", error);
+    }
+
+    #[test]
+    fn stale_volume_reports_are_not_manual_changes() {
+        // Steps of 0, 5, 10: the player may still report the step before.
+        assert!(!set_by_hand(10, 10, 5));
+        assert!(!set_by_hand(5, 10, 5));
+        assert!(!set_by_hand(11, 10, 5));
+        assert!(!set_by_hand(4, 10, 5));
+        assert!(set_by_hand(0, 10, 5));
+        assert!(set_by_hand(30, 10, 5));
+    }
+}
@@ -1,11 +1,23 @@
+mod artists;
+pub use self::artists::{get_artists};
+
+mod devices;
+pub use self::devices::{format_device, resolve_device, resolve_device_id, DeviceNotResolved, DeviceResolver};
+
+mod error;
+pub use self::error::{RequestFailed};
+
 mod player;
-pub use self::player::{get_currently_playing_track, enqueue_tracks, is_playing, pause, playback, skip_to_next, start_playing, ListDevices};
+pub use self::player::{enqueue_tracks, expand_uris, get_currently_playing_track, get_playback_state, get_queue, is_playing, pause, playback, recently_played_between, seek, set_repeat, set_shuffle, set_volume, skip_to_next, skip_to_previous, start_playback, start_playing, transfer_playback, EnqueueReport, GetPlaybackState, GetQueue, GetRecentlyPlayed, ListDevices, Playback, PlaybackOffset, PlaybackRequest, RecentlyPlayedCursor};
+
+mod play_now;
+pub use self::play_now::{play_now, PlayMode, PlayNowReport, PlayOutcome};
 
 mod playlists;
-pub use self::playlists::{get_playlists};
+pub use self::playlists::{get_playlists, playlist_track_uris};
 
 mod search;
-pub use self::search::{SearchAlbums, SearchArtists};
+pub use self::search::{RateLimited, Search, SearchAlbums, SearchArtists, SearchTracks, SearchType};
 
 mod tracks;
-pub use self::tracks::{list_tracks};
+pub use self::tracks::{album_track_uris, get_tracks, list_tracks};
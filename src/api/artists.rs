@@ -0,0 +1,55 @@
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+
+use crate::config::SpotifyConfig;
+use crate::objects::{Artist, ErrorResponse, GetArtistsResponse};
+use super::error::RequestFailed;
+
+const MAX_IDS_PER_REQUEST: usize = 50;
+
+struct GetArtists {
+    config: Rc<SpotifyConfig>,
+    ids: Vec<String>,
+}
+
+impl GetArtists {
+    fn new(config: &Rc<SpotifyConfig>, ids: &[String]) -> Self {
+        Self {
+            config: config.clone(),
+            ids: ids.to_vec(),
+        }
+    }
+
+    async fn execute(&self) -> Result<GetArtistsResponse> {
+        let client = Client::new();
+        let ids = self.ids.join(",");
+        let parameters = [
+            ("ids", &ids),
+        ];
+        let response = client.get("https://api.spotify.com/v1/artists")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            response.json::<GetArtistsResponse>().await
+                .with_context(|| "Failed to parse response")
+        } else {
+            let e = response.json::<ErrorResponse>().await?;
+            Err(RequestFailed::from(e).into())
+        }
+    }
+}
+
+/// Fetches full artist objects, including genres, in batches of 50.
+pub async fn get_artists(config: &Rc<SpotifyConfig>, ids: &[String]) -> Result<Vec<Artist>> {
+    let mut artists = vec![];
+    for chunk in ids.chunks(MAX_IDS_PER_REQUEST) {
+        let response = GetArtists::new(config, chunk).execute().await?;
+        artists.extend(response.artists.into_iter().flatten());
+    }
+    Ok(artists)
+}
@@ -0,0 +1,261 @@
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+use anyhow::{Result, anyhow};
+
+use crate::config::SpotifyConfig;
+use crate::objects::Device;
+use super::player::ListDevices;
+
+const DEVICE_TYPES: [&str; 13] = [
+    "Computer", "Tablet", "Smartphone", "Speaker", "TV", "AVR", "STB",
+    "AudioDongle", "GameConsole", "CastVideo", "CastAudio", "Automobile", "Unknown",
+];
+
+/// No single controllable device fits the selection.
+#[derive(Debug)]
+pub struct DeviceNotResolved(pub String);
+
+impl fmt::Display for DeviceNotResolved {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for DeviceNotResolved {}
+
+pub struct DeviceResolver {
+    devices: Vec<Device>,
+    default_device: Option<String>,
+}
+
+impl DeviceResolver {
+    pub fn new(devices: Vec<Device>, default_device: Option<&str>) -> Self {
+        Self {
+            devices,
+            default_device: default_device.map(|name| name.to_owned()),
+        }
+    }
+
+    pub async fn fetch(config: &Rc<SpotifyConfig>) -> Result<Self> {
+        let response = ListDevices::new(config).execute().await?;
+        Ok(Self::new(response.devices, config.default_device.as_deref()))
+    }
+
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    pub fn resolve(&self, selector: Option<&str>) -> Result<&Device> {
+        let device = match (selector, self.default_device.as_deref()) {
+            (Some(selector), _) => self.find(selector)?,
+            (None, Some(default_device)) => self.find(default_device)?,
+            (None, None) => self.find_fallback()?,
+        };
+
+        if device.id.is_none() {
+            return Err(DeviceNotResolved(format!("Device \"{}\" cannot be controlled through the Web API", device.name)).into());
+        }
+        Ok(device)
+    }
+
+    fn find(&self, selector: &str) -> Result<&Device> {
+        let selector = selector.trim();
+        if selector.eq_ignore_ascii_case("active") {
+            return self.find_active();
+        }
+
+        let candidates = [
+            self.filter(|device| device.id.as_deref() == Some(selector)),
+            self.filter(|device| device.name.eq_ignore_ascii_case(selector)),
+            self.filter(|device| is_device_type(selector) && device.device_type.eq_ignore_ascii_case(selector)),
+            self.filter(|device| fuzzy_match(&device.name, selector)),
+        ];
+        for matches in candidates.iter() {
+            match matches.as_slice() {
+                [] => continue,
+                [device] => return Ok(device),
+                _ => return Err(DeviceNotResolved(self.describe(&format!("Device \"{}\" is ambiguous; matching devices:", selector), matches)).into()),
+            }
+        }
+
+        let all = self.devices.iter().collect::<Vec<_>>();
+        Err(DeviceNotResolved(self.describe(&format!("No device matches \"{}\"; available devices:", selector), &all)).into())
+    }
+
+    fn find_active(&self) -> Result<&Device> {
+        let active = self.filter(|device| device.is_active);
+        match active.as_slice() {
+            [device] => Ok(device),
+            [] => {
+                let all = self.devices.iter().collect::<Vec<_>>();
+                Err(DeviceNotResolved(self.describe("No active device; available devices:", &all)).into())
+            }
+            _ => Err(DeviceNotResolved(self.describe("Several devices are active, so \"active\" is ambiguous; active devices:", &active)).into()),
+        }
+    }
+
+    fn find_fallback(&self) -> Result<&Device> {
+        if self.devices.iter().any(|device| device.is_active) {
+            return self.find_active();
+        }
+        match self.devices.as_slice() {
+            [device] => Ok(device),
+            [] => Err(DeviceNotResolved(String::from("No devices available; open Spotify on a device first")).into()),
+            _ => {
+                let all = self.devices.iter().collect::<Vec<_>>();
+                Err(DeviceNotResolved(self.describe("No device given and none is active; available devices:", &all)).into())
+            }
+        }
+    }
+
+    fn filter<F>(&self, predicate: F) -> Vec<&Device>
+        where F: Fn(&Device) -> bool
+    {
+        self.devices.iter()
+            .filter(|device| predicate(device))
+            .collect()
+    }
+
+    fn describe(&self, headline: &str, devices: &[&Device]) -> String {
+        let mut message = headline.to_owned();
+        for device in devices.iter() {
+            let _ = write!(message, "\n  {}", format_device(device));
+        }
+        message
+    }
+}
+
+pub fn format_device(device: &Device) -> String {
+    let volume = device.volume_percent
+        .map_or("-".to_owned(), |volume| format!("{}%", volume));
+    format!("{} {} [{}] {} volume:{}",
+        if device.is_active { "*" } else { " " },
+        device.id.as_deref().unwrap_or("(no id)"),
+        device.device_type,
+        device.name,
+        volume)
+}
+
+fn is_device_type(selector: &str) -> bool {
+    DEVICE_TYPES.iter().any(|device_type| device_type.eq_ignore_ascii_case(selector))
+}
+
+fn fuzzy_match(name: &str, selector: &str) -> bool {
+    let name = name.to_lowercase();
+    selector.to_lowercase()
+        .split_whitespace()
+        .all(|word| name.contains(word))
+}
+
+pub async fn resolve_device(config: &Rc<SpotifyConfig>, selector: Option<&str>) -> Result<Device> {
+    let resolver = DeviceResolver::fetch(config).await?;
+    let device = resolver.resolve(selector)?;
+    Ok(device.clone())
+}
+
+pub async fn resolve_device_id(config: &Rc<SpotifyConfig>, selector: Option<&str>) -> Result<String> {
+    let Device { id, name, .. } = resolve_device(config, selector).await?;
+    id.ok_or_else(|| anyhow!("Device \"{}\" has no id", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, name: &str, device_type: &str, is_active: bool) -> Device {
+        Device {
+            id: Some(id.to_owned()),
+            is_active,
+            is_private_session: false,
+            is_restricted: false,
+            name: name.to_owned(),
+            device_type: device_type.to_owned(),
+            volume_percent: Some(50),
+        }
+    }
+
+    fn resolver(default_device: Option<&str>) -> DeviceResolver {
+        DeviceResolver::new(vec![
+            device("abc123", "Kitchen Speaker", "Speaker", false),
+            device("def456", "Living Room TV", "TV", true),
+            device("ghi789", "Work Laptop", "Computer", false),
+            device("jkl012", "Smartphone", "Smartphone", false),
+            device("Kitchen", "Bedroom Speaker", "Speaker", false),
+        ], default_device)
+    }
+
+    fn resolve_id(resolver: &DeviceResolver, selector: Option<&str>) -> Result<String> {
+        resolver.resolve(selector).map(|device| device.id.clone().unwrap())
+    }
+
+    #[test]
+    fn id_wins_over_other_matches() {
+        assert_eq!(resolve_id(&resolver(None), Some("Kitchen")).unwrap(), "Kitchen");
+        assert_eq!(resolve_id(&resolver(None), Some("ghi789")).unwrap(), "ghi789");
+    }
+
+    #[test]
+    fn exact_name_wins_over_type() {
+        assert_eq!(resolve_id(&resolver(None), Some("smartphone")).unwrap(), "jkl012");
+    }
+
+    #[test]
+    fn type_wins_over_fuzzy_name() {
+        assert_eq!(resolve_id(&resolver(None), Some("tv")).unwrap(), "def456");
+        assert_eq!(resolve_id(&resolver(None), Some("computer")).unwrap(), "ghi789");
+    }
+
+    #[test]
+    fn fuzzy_match_needs_every_word() {
+        assert_eq!(resolve_id(&resolver(None), Some("room tv")).unwrap(), "def456");
+        assert_eq!(resolve_id(&resolver(None), Some("laptop WORK")).unwrap(), "ghi789");
+        let error = resolve_id(&resolver(None), Some("work tv")).unwrap_err();
+        assert!(error.to_string().starts_with("No device matches \"work tv\""));
+    }
+
+    #[test]
+    fn several_matches_are_ambiguous() {
+        let error = resolve_id(&resolver(None), Some("speaker")).unwrap_err().to_string();
+        assert!(error.starts_with("Device \"speaker\" is ambiguous"));
+        assert!(error.contains("Kitchen Speaker") && error.contains("Bedroom Speaker"));
+        assert!(!error.contains("Work Laptop"));
+    }
+
+    #[test]
+    fn active_and_default_device() {
+        assert_eq!(resolve_id(&resolver(None), Some("active")).unwrap(), "def456");
+        assert_eq!(resolve_id(&resolver(None), None).unwrap(), "def456");
+        assert_eq!(resolve_id(&resolver(Some("laptop")), None).unwrap(), "ghi789");
+    }
+
+    #[test]
+    fn several_active_devices_are_ambiguous() {
+        let resolver = DeviceResolver::new(vec![
+            device("abc123", "Kitchen Speaker", "Speaker", true),
+            device("def456", "Living Room TV", "TV", true),
+        ], None);
+        for selector in [Some("active"), None].iter() {
+            let error = resolve_id(&resolver, *selector).unwrap_err().to_string();
+            assert!(error.contains("is ambiguous"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn no_active_device() {
+        let resolver = DeviceResolver::new(vec![
+            device("abc123", "Kitchen Speaker", "Speaker", false),
+            device("def456", "Living Room TV", "TV", false),
+        ], None);
+        assert!(resolve_id(&resolver, Some("active")).unwrap_err().to_string().starts_with("No active device"));
+        assert!(resolve_id(&resolver, None).unwrap_err().to_string().starts_with("No device given and none is active"));
+    }
+
+    #[test]
+    fn device_without_id_is_rejected() {
+        let mut restricted = device("", "Car", "Automobile", true);
+        restricted.id = None;
+        let resolver = DeviceResolver::new(vec![restricted], None);
+        assert!(resolver.resolve(None).unwrap_err().to_string().contains("cannot be controlled"));
+    }
+}
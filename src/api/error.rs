@@ -0,0 +1,39 @@
+use std::fmt;
+
+use reqwest::StatusCode;
+
+use crate::objects::ErrorResponse;
+
+/// Spotify answered a request with an error status.
+#[derive(Debug)]
+pub struct RequestFailed {
+    pub status: u16,
+    pub message: String,
+}
+
+impl RequestFailed {
+    /// For endpoints whose error responses carry no body worth reporting.
+    pub(crate) fn with_status(status: StatusCode) -> Self {
+        Self {
+            status: status.as_u16(),
+            message: status.to_string(),
+        }
+    }
+}
+
+impl From<ErrorResponse> for RequestFailed {
+    fn from(e: ErrorResponse) -> Self {
+        Self {
+            status: e.error.status as u16,
+            message: e.error.message,
+        }
+    }
+}
+
+impl fmt::Display for RequestFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Request failed: {}", self.message)
+    }
+}
+
+impl std::error::Error for RequestFailed {}
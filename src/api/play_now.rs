@@ -0,0 +1,179 @@
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use tokio::time::sleep;
+
+use crate::config::SpotifyConfig;
+use super::player::{enqueue_track_uris, expand_uris, get_currently_playing_track, get_queue, start_playback, start_playing, PlaybackRequest};
+
+const VERIFY_ATTEMPTS: u32 = 6;
+const VERIFY_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayMode {
+    /// Replace the current playback with the given items.
+    Replace,
+    /// Queue the items behind anything already queued, and resume playback if paused.
+    /// Spotify has no way to put items at the front of the queue, so they do not play next if the user queued others.
+    Next,
+    /// Queue the items behind anything already queued, and leave playback as it is.
+    Append,
+}
+
+impl FromStr for PlayMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "replace" => Ok(PlayMode::Replace),
+            "next" => Ok(PlayMode::Next),
+            "append" => Ok(PlayMode::Append),
+            _ => bail!("Unknown play mode: {} (expected replace, next or append)", s),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlayOutcome {
+    /// The first requested item is confirmed to be playing.
+    Playing,
+    /// The items are confirmed to be in the queue.
+    Queued,
+    /// Spotify accepted the request, but the requested item was not observed playing or queued.
+    Unconfirmed,
+}
+
+#[derive(Debug)]
+pub struct PlayNowReport {
+    pub requested_mode: PlayMode,
+    pub applied_mode: PlayMode,
+    pub outcome: PlayOutcome,
+    pub now_playing: Option<String>,
+    pub already_queued: Vec<String>,
+}
+
+impl fmt::Display for PlayNowReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.outcome {
+            PlayOutcome::Playing => write!(f, "Playing")?,
+            PlayOutcome::Queued => write!(f, "Queued")?,
+            PlayOutcome::Unconfirmed if self.applied_mode != PlayMode::Replace => write!(f, "Queued, but could not confirm the items are in the queue")?,
+            PlayOutcome::Unconfirmed => write!(f, "Requested playback, but could not confirm it started")?,
+        }
+        if self.applied_mode != self.requested_mode {
+            write!(f, " (nothing was playing, so {:?} fell back to {:?})", self.requested_mode, self.applied_mode)?;
+        }
+        if let Some(uri) = &self.now_playing {
+            write!(f, "; now playing {}", uri)?;
+        }
+        if !self.already_queued.is_empty() {
+            write!(f, "; queued again: {}", self.already_queued.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+struct PlayNow {
+    config: Rc<SpotifyConfig>,
+    device_id: String,
+    uris: Vec<String>,
+    mode: PlayMode,
+}
+
+impl PlayNow {
+    fn new(config: &Rc<SpotifyConfig>, device_id: &str, uris: Vec<String>, mode: PlayMode) -> Self {
+        Self {
+            config: config.clone(),
+            device_id: device_id.to_owned(),
+            uris,
+            mode,
+        }
+    }
+
+    async fn execute(&self) -> Result<PlayNowReport> {
+        let uris = expand_uris(&self.config, self.uris.clone()).await?;
+        if uris.is_empty() {
+            bail!("Nothing to play")
+        }
+
+        let current = get_currently_playing_track(&self.config).await?;
+        let applied_mode = match (&current, self.mode) {
+            (None, _) => PlayMode::Replace,
+            (Some(_), mode) => mode,
+        };
+
+        match applied_mode {
+            PlayMode::Replace => {
+                let first = uris[0].clone();
+                start_playback(&self.config, &self.device_id, PlaybackRequest::uris(uris)).await?;
+                let (playing, now_playing) = self.wait_for_playing(&first).await?;
+                let outcome = if playing { PlayOutcome::Playing } else { PlayOutcome::Unconfirmed };
+                Ok(self.report(applied_mode, outcome, now_playing, vec![]))
+            }
+            PlayMode::Next | PlayMode::Append => {
+                let enqueued = enqueue_track_uris(&self.config, &self.device_id, uris).await?;
+                let was_playing = current.as_ref().is_some_and(|current| current.is_playing);
+                if applied_mode == PlayMode::Next && !was_playing {
+                    start_playing(&self.config, &self.device_id).await?;
+                }
+                // An item that was not in the queue before shows that the request took effect.
+                let probe = enqueued.queued.iter()
+                    .find(|uri| !enqueued.already_queued.contains(uri))
+                    .unwrap_or(&enqueued.queued[0]);
+                let outcome = if self.wait_for_queued(probe).await? { PlayOutcome::Queued } else { PlayOutcome::Unconfirmed };
+                let now_playing = current.and_then(|current| current.item)
+                    .map(|item| item.uri);
+                Ok(self.report(applied_mode, outcome, now_playing, enqueued.already_queued))
+            }
+        }
+    }
+
+    /// Polls until the item with `uri`, or a track relinked from it, is playing.
+    /// Returns whether it was, along with the URI of whatever is playing.
+    async fn wait_for_playing(&self, uri: &str) -> Result<(bool, Option<String>)> {
+        let mut now_playing = None;
+        for _ in 0..VERIFY_ATTEMPTS {
+            sleep(VERIFY_INTERVAL).await;
+            let item = get_currently_playing_track(&self.config).await?
+                .filter(|current| current.is_playing)
+                .and_then(|current| current.item);
+            let playing = item.as_ref().is_some_and(|item| item.matches_uri(uri));
+            now_playing = item.map(|item| item.uri);
+            if playing {
+                return Ok((true, now_playing));
+            }
+        }
+        Ok((false, now_playing))
+    }
+
+    /// Polls until the item with `uri`, or a track relinked from it, is in the queue.
+    async fn wait_for_queued(&self, uri: &str) -> Result<bool> {
+        for _ in 0..VERIFY_ATTEMPTS {
+            let queue = get_queue(&self.config).await?;
+            if queue.queue.iter().any(|item| item.matches_uri(uri)) {
+                return Ok(true);
+            }
+            sleep(VERIFY_INTERVAL).await;
+        }
+        Ok(false)
+    }
+
+    fn report(&self, applied_mode: PlayMode, outcome: PlayOutcome, now_playing: Option<String>, already_queued: Vec<String>) -> PlayNowReport {
+        PlayNowReport {
+            requested_mode: self.mode,
+            applied_mode,
+            outcome,
+            now_playing,
+            already_queued,
+        }
+    }
+}
+
+pub async fn play_now(config: &Rc<SpotifyConfig>, device_id: &str, uris: Vec<String>, mode: PlayMode) -> Result<PlayNowReport> {
+    PlayNow::new(config, device_id, uris, mode)
+        .execute()
+        .await
+}
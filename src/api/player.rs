@@ -1,11 +1,16 @@
 use std::rc::Rc;
+use std::str::FromStr;
 
 use anyhow::{Context, Result, bail};
-use reqwest::Client;
-use serde_json::json;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Map, Value};
 
 use crate::config::SpotifyConfig;
-use crate::objects::{CurrentlyPlayingTrackResponse, ErrorResponse, ListDevicesResponse};
+use crate::objects::{CurrentlyPlayingTrackResponse, ErrorResponse, ListDevicesResponse, PlayHistory, PlaybackState, QueueResponse, RecentlyPlayedResponse};
+use super::error::RequestFailed;
+use super::playlists::playlist_track_uris;
+use super::tracks::album_track_uris;
 
 pub struct ListDevices {
     config: Rc<SpotifyConfig>,
@@ -30,7 +35,7 @@ impl ListDevices {
                 .with_context(|| "Failed to parse response")
         } else {
             let e = response.json::<ErrorResponse>().await?;
-            bail!("Request failed: {}", e.error.message)
+            Err(RequestFailed::from(e).into())
         }
     }
 }
@@ -46,7 +51,7 @@ impl GetCurrentlyPlayingTrack {
         }
     }
 
-    pub async fn execute(&self) -> Result<CurrentlyPlayingTrackResponse> {
+    pub async fn execute(&self) -> Result<Option<CurrentlyPlayingTrackResponse>> {
         let client = Client::new();
         let parameters = [
             ("market", "from_token"),
@@ -57,11 +62,124 @@ impl GetCurrentlyPlayingTrack {
             .send()
             .await?;
 
-        if response.status().is_success() {
+        if response.status() == StatusCode::NO_CONTENT {
+            Ok(None)
+        } else if response.status().is_success() {
             response.json::<CurrentlyPlayingTrackResponse>().await
+                .map(Some)
+                .with_context(|| "Failed to parse response")
+        } else {
+            Err(RequestFailed::with_status(response.status()).into())
+        }
+    }
+}
+
+pub struct GetPlaybackState {
+    config: Rc<SpotifyConfig>,
+}
+
+impl GetPlaybackState {
+    pub fn new(config: &Rc<SpotifyConfig>) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    pub async fn execute(&self) -> Result<Option<PlaybackState>> {
+        let client = Client::new();
+        let parameters = [
+            ("market", "from_token"),
+            ("additional_types", "track,episode"),
+        ];
+        let response = client.get("https://api.spotify.com/v1/me/player")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NO_CONTENT {
+            Ok(None)
+        } else if response.status().is_success() {
+            response.json::<PlaybackState>().await
+                .map(Some)
+                .with_context(|| "Failed to parse response")
+        } else {
+            let e = response.json::<ErrorResponse>().await?;
+            Err(RequestFailed::from(e).into())
+        }
+    }
+}
+
+pub struct GetQueue {
+    config: Rc<SpotifyConfig>,
+}
+
+impl GetQueue {
+    pub fn new(config: &Rc<SpotifyConfig>) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    pub async fn execute(&self) -> Result<QueueResponse> {
+        let client = Client::new();
+        let response = client.get("https://api.spotify.com/v1/me/player/queue")
+            .bearer_auth(&self.config.access_token)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            response.json::<QueueResponse>().await
+                .with_context(|| "Failed to parse response")
+        } else {
+            let e = response.json::<ErrorResponse>().await?;
+            Err(RequestFailed::from(e).into())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RecentlyPlayedCursor {
+    Latest,
+    Before(i64),
+    After(i64),
+}
+
+pub struct GetRecentlyPlayed {
+    config: Rc<SpotifyConfig>,
+    cursor: RecentlyPlayedCursor,
+}
+
+impl GetRecentlyPlayed {
+    pub fn new(config: &Rc<SpotifyConfig>, cursor: RecentlyPlayedCursor) -> Self {
+        Self {
+            config: config.clone(),
+            cursor,
+        }
+    }
+
+    pub async fn execute(&self) -> Result<RecentlyPlayedResponse> {
+        let client = Client::new();
+        let mut parameters = vec![
+            ("limit", "50".to_owned()),
+        ];
+        match self.cursor {
+            RecentlyPlayedCursor::Latest => {}
+            RecentlyPlayedCursor::Before(timestamp) => parameters.push(("before", timestamp.to_string())),
+            RecentlyPlayedCursor::After(timestamp) => parameters.push(("after", timestamp.to_string())),
+        }
+        let response = client.get("https://api.spotify.com/v1/me/player/recently-played")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            response.json::<RecentlyPlayedResponse>().await
                 .with_context(|| "Failed to parse response")
         } else {
-            bail!("Request failed: {}", response.status())
+            let e = response.json::<ErrorResponse>().await?;
+            Err(RequestFailed::from(e).into())
         }
     }
 }
@@ -95,23 +213,109 @@ impl StartPlaying {
         if response.status().is_success() {
             Ok(())
         } else {
-            bail!("Request failed: {}", response.status())
+            Err(RequestFailed::with_status(response.status()).into())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PlaybackOffset {
+    Position(u32),
+    Uri(String),
+}
+
+impl FromStr for PlaybackOffset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.parse::<u32>() {
+            Ok(position) => Ok(PlaybackOffset::Position(position)),
+            Err(_) if s.starts_with("spotify:") => Ok(PlaybackOffset::Uri(s.to_owned())),
+            Err(_) => bail!("Offset must be a zero-based position or a spotify URI: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PlaybackRequest {
+    pub context_uri: Option<String>,
+    pub uris: Vec<String>,
+    pub offset: Option<PlaybackOffset>,
+    pub position_ms: Option<u32>,
+}
+
+impl PlaybackRequest {
+    pub fn context(uri: &str) -> Self {
+        Self {
+            context_uri: Some(uri.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    pub fn uris(uris: Vec<String>) -> Self {
+        Self {
+            uris,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_offset(self, offset: PlaybackOffset) -> Self {
+        Self {
+            offset: Some(offset),
+            ..self
+        }
+    }
+
+    pub fn with_position_ms(self, position_ms: u32) -> Self {
+        Self {
+            position_ms: Some(position_ms),
+            ..self
+        }
+    }
+
+    fn to_body(&self) -> Result<Value> {
+        if self.context_uri.is_some() && !self.uris.is_empty() {
+            bail!("Either a context URI or a list of URIs can be played, not both")
+        }
+        if self.offset.is_some() && self.context_uri.is_none() && self.uris.is_empty() {
+            bail!("An offset requires a context URI or a list of URIs")
+        }
+
+        let mut body = Map::new();
+        if let Some(context_uri) = &self.context_uri {
+            body.insert("context_uri".to_owned(), json!(context_uri));
+        }
+        if !self.uris.is_empty() {
+            body.insert("uris".to_owned(), json!(self.uris));
+        }
+        match &self.offset {
+            Some(PlaybackOffset::Position(position)) => {
+                body.insert("offset".to_owned(), json!({ "position": position }));
+            }
+            Some(PlaybackOffset::Uri(uri)) => {
+                body.insert("offset".to_owned(), json!({ "uri": uri }));
+            }
+            None => {}
         }
+        if let Some(position_ms) = self.position_ms {
+            body.insert("position_ms".to_owned(), json!(position_ms));
+        }
+        Ok(Value::Object(body))
     }
 }
 
-struct Playback {
+pub struct Playback {
     config: Rc<SpotifyConfig>,
     device_id: String,
-    uri: String,
+    request: PlaybackRequest,
 }
 
 impl Playback {
-    pub fn new(config: &Rc<SpotifyConfig>, device_id: &str, uri: &str) -> Self {
+    pub fn new(config: &Rc<SpotifyConfig>, device_id: &str, request: PlaybackRequest) -> Self {
         Self {
             config: config.clone(),
             device_id: device_id.to_owned(),
-            uri: uri.to_owned(),
+            request,
         }
     }
 
@@ -120,9 +324,7 @@ impl Playback {
         let parameters = [
             ("device_id", &self.device_id),
         ];
-        let body = json!({
-            "context_uri": self.uri,
-        });
+        let body = self.request.to_body()?;
         let response = client.put("https://api.spotify.com/v1/me/player/play")
             .bearer_auth(&self.config.access_token)
             .query(&parameters)
@@ -134,7 +336,7 @@ impl Playback {
         if response.status().is_success() {
             Ok(())
         } else {
-            bail!("Request failed: {}", response.status())
+            Err(RequestFailed::with_status(response.status()).into())
         }
     }
 }
@@ -171,7 +373,7 @@ impl EnqueueTrack {
         if response.status().is_success() {
             Ok(())
         } else {
-            bail!("Request failed: {}", response.status())
+            Err(RequestFailed::with_status(response.status()).into())
         }
     }
 }
@@ -203,7 +405,40 @@ impl SkipToNextTrack {
         if response.status().is_success() {
             Ok(())
         } else {
-            bail!("Request failed: {}", response.status())
+            Err(RequestFailed::with_status(response.status()).into())
+        }
+    }
+}
+
+pub struct SkipToPreviousTrack {
+    config: Rc<SpotifyConfig>,
+    device_id: String,
+}
+
+impl SkipToPreviousTrack {
+    pub fn new(config: &Rc<SpotifyConfig>, device_id: &str) -> Self {
+        Self {
+            config: config.clone(),
+            device_id: device_id.to_owned(),
+        }
+    }
+
+    pub async fn execute(&self) -> Result<()> {
+        let client = Client::new();
+        let parameters = [
+            ("device_id", &self.device_id),
+        ];
+        let response = client.post("https://api.spotify.com/v1/me/player/previous")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .body("{}")
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(RequestFailed::with_status(response.status()).into())
         }
     }
 }
@@ -236,30 +471,289 @@ impl PausePlayback {
         if response.status().is_success() {
             Ok(())
         } else {
-            bail!("Request failed: {}", response.status())
+            Err(RequestFailed::with_status(response.status()).into())
         }
     }
 }
 
-pub async fn get_currently_playing_track(config: &Rc<SpotifyConfig>) -> Result<CurrentlyPlayingTrackResponse> {
+struct SetVolume {
+    config: Rc<SpotifyConfig>,
+    device_id: String,
+    volume_percent: u32,
+}
+
+impl SetVolume {
+    fn new(config: &Rc<SpotifyConfig>, device_id: &str, volume_percent: u32) -> Self {
+        Self {
+            config: config.clone(),
+            device_id: device_id.to_owned(),
+            volume_percent,
+        }
+    }
+
+    async fn execute(&self) -> Result<()> {
+        let client = Client::new();
+        let volume_percent = self.volume_percent.min(100).to_string();
+        let parameters = [
+            ("device_id", &self.device_id),
+            ("volume_percent", &volume_percent),
+        ];
+        let response = client.put("https://api.spotify.com/v1/me/player/volume")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .body("{}")
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(RequestFailed::with_status(response.status()).into())
+        }
+    }
+}
+
+struct Seek {
+    config: Rc<SpotifyConfig>,
+    device_id: String,
+    position_ms: u32,
+}
+
+impl Seek {
+    fn new(config: &Rc<SpotifyConfig>, device_id: &str, position_ms: u32) -> Self {
+        Self {
+            config: config.clone(),
+            device_id: device_id.to_owned(),
+            position_ms,
+        }
+    }
+
+    async fn execute(&self) -> Result<()> {
+        let client = Client::new();
+        let position_ms = self.position_ms.to_string();
+        let parameters = [
+            ("device_id", &self.device_id),
+            ("position_ms", &position_ms),
+        ];
+        let response = client.put("https://api.spotify.com/v1/me/player/seek")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .body("{}")
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(RequestFailed::with_status(response.status()).into())
+        }
+    }
+}
+
+struct TransferPlayback {
+    config: Rc<SpotifyConfig>,
+    device_id: String,
+    play: bool,
+}
+
+impl TransferPlayback {
+    fn new(config: &Rc<SpotifyConfig>, device_id: &str, play: bool) -> Self {
+        Self {
+            config: config.clone(),
+            device_id: device_id.to_owned(),
+            play,
+        }
+    }
+
+    async fn execute(&self) -> Result<()> {
+        let client = Client::new();
+        let body = json!({
+            "device_ids": [&self.device_id],
+            "play": self.play,
+        });
+        let response = client.put("https://api.spotify.com/v1/me/player")
+            .bearer_auth(&self.config.access_token)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(RequestFailed::with_status(response.status()).into())
+        }
+    }
+}
+
+struct SetShuffle {
+    config: Rc<SpotifyConfig>,
+    device_id: String,
+    state: bool,
+}
+
+impl SetShuffle {
+    fn new(config: &Rc<SpotifyConfig>, device_id: &str, state: bool) -> Self {
+        Self {
+            config: config.clone(),
+            device_id: device_id.to_owned(),
+            state,
+        }
+    }
+
+    async fn execute(&self) -> Result<()> {
+        let client = Client::new();
+        let state = self.state.to_string();
+        let parameters = [
+            ("device_id", &self.device_id),
+            ("state", &state),
+        ];
+        let response = client.put("https://api.spotify.com/v1/me/player/shuffle")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .body("{}")
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(RequestFailed::with_status(response.status()).into())
+        }
+    }
+}
+
+struct SetRepeat {
+    config: Rc<SpotifyConfig>,
+    device_id: String,
+    state: String,
+}
+
+impl SetRepeat {
+    fn new(config: &Rc<SpotifyConfig>, device_id: &str, state: &str) -> Self {
+        Self {
+            config: config.clone(),
+            device_id: device_id.to_owned(),
+            state: state.to_owned(),
+        }
+    }
+
+    async fn execute(&self) -> Result<()> {
+        if !["track", "context", "off"].contains(&self.state.as_str()) {
+            bail!("Repeat state must be track, context or off: {}", self.state)
+        }
+
+        let client = Client::new();
+        let parameters = [
+            ("device_id", &self.device_id),
+            ("state", &self.state),
+        ];
+        let response = client.put("https://api.spotify.com/v1/me/player/repeat")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .body("{}")
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(RequestFailed::with_status(response.status()).into())
+        }
+    }
+}
+
+pub async fn get_currently_playing_track(config: &Rc<SpotifyConfig>) -> Result<Option<CurrentlyPlayingTrackResponse>> {
     GetCurrentlyPlayingTrack::new(config)
         .execute()
         .await
 }
 
+pub async fn get_playback_state(config: &Rc<SpotifyConfig>) -> Result<Option<PlaybackState>> {
+    GetPlaybackState::new(config)
+        .execute()
+        .await
+}
+
 pub async fn is_playing(config: &Rc<SpotifyConfig>) -> Result<bool> {
     let response = GetCurrentlyPlayingTrack::new(config).execute().await?;
-    Ok(response.is_playing)
+    Ok(response.is_some_and(|response| response.is_playing))
+}
+
+#[derive(Debug, Default)]
+pub struct EnqueueReport {
+    pub queued: Vec<String>,
+    /// Items that were in the queue already and have been queued again. The queue also
+    /// lists the upcoming tracks of the current context, so these are not necessarily duplicates.
+    pub already_queued: Vec<String>,
+}
+
+pub async fn get_queue(config: &Rc<SpotifyConfig>) -> Result<QueueResponse> {
+    GetQueue::new(config)
+        .execute()
+        .await
 }
 
-pub async fn enqueue_tracks(config: &Rc<SpotifyConfig>, device_id: &str, track_uris: Vec<String>) -> Result<()> {
-    for uri in track_uris.iter() {
-        EnqueueTrack::new(config, device_id, uri)
+/// Expands album and playlist URIs into the URIs of their tracks, keeping other URIs as they are.
+pub async fn expand_uris(config: &Rc<SpotifyConfig>, uris: Vec<String>) -> Result<Vec<String>> {
+    let mut expanded = vec![];
+    for uri in uris.into_iter() {
+        if let Some(album_id) = uri.strip_prefix("spotify:album:") {
+            expanded.extend(album_track_uris(config, album_id).await?);
+        } else if let Some(playlist_id) = uri.strip_prefix("spotify:playlist:") {
+            expanded.extend(playlist_track_uris(config, playlist_id).await?);
+        } else {
+            expanded.push(uri);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Queues tracks and episodes, expanding albums and playlists into their tracks.
+pub async fn enqueue_tracks(config: &Rc<SpotifyConfig>, device_id: &str, uris: Vec<String>) -> Result<EnqueueReport> {
+    let track_uris = expand_uris(config, uris).await?;
+    enqueue_track_uris(config, device_id, track_uris).await
+}
+
+/// Queues URIs that are already expanded into tracks and episodes.
+pub(crate) async fn enqueue_track_uris(config: &Rc<SpotifyConfig>, device_id: &str, track_uris: Vec<String>) -> Result<EnqueueReport> {
+    let queue = get_queue(config).await?;
+
+    let mut report = EnqueueReport::default();
+    for uri in track_uris.into_iter() {
+        EnqueueTrack::new(config, device_id, &uri)
             .execute()
             .await?;
+        if queue.queue.iter().any(|item| item.uri() == uri) {
+            report.already_queued.push(uri.clone());
+        }
+        report.queued.push(uri);
     }
 
-    Ok(())
+    Ok(report)
+}
+
+/// Fetches play history between two instants, newest first, by following `before` cursors backwards.
+pub async fn recently_played_between(config: &Rc<SpotifyConfig>, after: Option<DateTime<Utc>>, before: Option<DateTime<Utc>>) -> Result<Vec<PlayHistory>> {
+    let mut history = vec![];
+    let mut cursor = before.map_or(RecentlyPlayedCursor::Latest, |before| RecentlyPlayedCursor::Before(before.timestamp_millis()));
+    loop {
+        let response = GetRecentlyPlayed::new(config, cursor).execute().await?;
+        let next_before = response.cursors.as_ref()
+            .and_then(|cursors| cursors.before.as_ref())
+            .and_then(|before| before.parse::<i64>().ok());
+        let reached_start = response.items.is_empty() || response.items.iter()
+            .any(|item| after.is_some_and(|after| item.played_at < after));
+
+        history.extend(response.items.into_iter()
+            .filter(|item| after.is_none_or(|after| item.played_at >= after))
+            .filter(|item| before.is_none_or(|before| item.played_at < before)));
+
+        match next_before {
+            Some(timestamp) if !reached_start => cursor = RecentlyPlayedCursor::Before(timestamp),
+            _ => return Ok(history),
+        }
+    }
 }
 
 pub async fn skip_to_next(config: &Rc<SpotifyConfig>, device_id: &str) -> Result<()> {
@@ -268,6 +762,12 @@ pub async fn skip_to_next(config: &Rc<SpotifyConfig>, device_id: &str) -> Result
         .await
 }
 
+pub async fn skip_to_previous(config: &Rc<SpotifyConfig>, device_id: &str) -> Result<()> {
+    SkipToPreviousTrack::new(config, device_id)
+        .execute()
+        .await
+}
+
 pub async fn start_playing(config: &Rc<SpotifyConfig>, device_id: &str) -> Result<()> {
     StartPlaying::new(config, device_id)
         .execute()
@@ -275,7 +775,13 @@ pub async fn start_playing(config: &Rc<SpotifyConfig>, device_id: &str) -> Resul
 }
 
 pub async fn playback(config: &Rc<SpotifyConfig>, device_id: &str, uri: &str) -> Result<()> {
-    Playback::new(config, device_id, uri)
+    Playback::new(config, device_id, PlaybackRequest::context(uri))
+        .execute()
+        .await
+}
+
+pub async fn start_playback(config: &Rc<SpotifyConfig>, device_id: &str, request: PlaybackRequest) -> Result<()> {
+    Playback::new(config, device_id, request)
         .execute()
         .await
 }
@@ -285,3 +791,34 @@ pub async fn pause(config: &Rc<SpotifyConfig>, device_id: &str) -> Result<()> {
         .execute()
         .await
 }
+
+pub async fn set_volume(config: &Rc<SpotifyConfig>, device_id: &str, volume_percent: u32) -> Result<()> {
+    SetVolume::new(config, device_id, volume_percent)
+        .execute()
+        .await
+}
+
+/// Moves playback to the device, starting it there if `play` is set.
+pub async fn transfer_playback(config: &Rc<SpotifyConfig>, device_id: &str, play: bool) -> Result<()> {
+    TransferPlayback::new(config, device_id, play)
+        .execute()
+        .await
+}
+
+pub async fn set_shuffle(config: &Rc<SpotifyConfig>, device_id: &str, state: bool) -> Result<()> {
+    SetShuffle::new(config, device_id, state)
+        .execute()
+        .await
+}
+
+pub async fn set_repeat(config: &Rc<SpotifyConfig>, device_id: &str, state: &str) -> Result<()> {
+    SetRepeat::new(config, device_id, state)
+        .execute()
+        .await
+}
+
+pub async fn seek(config: &Rc<SpotifyConfig>, device_id: &str, position_ms: u32) -> Result<()> {
+    Seek::new(config, device_id, position_ms)
+        .execute()
+        .await
+}
@@ -1,10 +1,11 @@
 use std::rc::Rc;
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result};
 use reqwest::Client;
 
 use crate::config::SpotifyConfig;
-use crate::objects::{ErrorResponse, GetPlaylistsResponse};
+use crate::objects::{ErrorResponse, GetPlaylistsResponse, ListPlaylistItemsResponse};
+use super::error::RequestFailed;
 
 struct GetPlaylists {
     config: Rc<SpotifyConfig>,
@@ -29,7 +30,48 @@ impl GetPlaylists {
                 .with_context(|| "Failed to parse response")
         } else {
             let e = response.json::<ErrorResponse>().await?;
-            bail!("Request failed: {}", e.error.message)
+            Err(RequestFailed::from(e).into())
+        }
+    }
+}
+
+struct ListPlaylistItems {
+    config: Rc<SpotifyConfig>,
+    playlist_id: String,
+    offset: u32,
+}
+
+impl ListPlaylistItems {
+    fn new(config: &Rc<SpotifyConfig>, playlist_id: &str, offset: u32) -> Self {
+        Self {
+            config: config.clone(),
+            playlist_id: playlist_id.to_owned(),
+            offset,
+        }
+    }
+
+    async fn execute(&self) -> Result<ListPlaylistItemsResponse> {
+        let client = Client::new();
+        let request_uri = format!("https://api.spotify.com/v1/playlists/{}/tracks", self.playlist_id);
+        let offset = self.offset.to_string();
+        let parameters = [
+            ("market", "from_token"),
+            ("limit", "100"),
+            ("offset", &offset),
+            ("fields", "href,limit,offset,total,next,previous,items(track(uri,is_local))"),
+        ];
+        let response = client.get(request_uri)
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            response.json::<ListPlaylistItemsResponse>().await
+                .with_context(|| "Failed to parse response")
+        } else {
+            let e = response.json::<ErrorResponse>().await?;
+            Err(RequestFailed::from(e).into())
         }
     }
 }
@@ -37,3 +79,22 @@ impl GetPlaylists {
 pub async fn get_playlists(config: &Rc<SpotifyConfig>) -> Result<GetPlaylistsResponse> {
     GetPlaylists::new(config).execute().await
 }
+
+pub async fn playlist_track_uris(config: &Rc<SpotifyConfig>, playlist_id: &str) -> Result<Vec<String>> {
+    let mut uris = vec![];
+    let mut offset = 0;
+    loop {
+        let response = ListPlaylistItems::new(config, playlist_id, offset)
+            .execute()
+            .await?;
+        offset += response.items.len() as u32;
+        let playable = response.items.into_iter()
+            .filter_map(|item| item.track)
+            .filter(|track| !track.is_local)
+            .map(|track| track.uri);
+        uris.extend(playable);
+        if response.next.is_none() {
+            return Ok(uris)
+        }
+    }
+}
@@ -1,32 +1,173 @@
+use std::fmt;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::{Context, Result, bail};
-use reqwest::Client;
+use anyhow::{Context, Error, Result, anyhow, bail};
+use reqwest::{Client, StatusCode};
+use reqwest::header::RETRY_AFTER;
 
 use crate::config::SpotifyConfig;
 use crate::objects::*;
+use super::error::RequestFailed;
 
-pub struct SearchAlbums {
+/// The most results Spotify returns per type in one request.
+const MAX_LIMIT: u32 = 50;
+
+/// The furthest Spotify pages into search results.
+const MAX_OFFSET: u32 = 1000;
+
+/// Spotify refused a request for making too many; it may be retried after `retry_after`.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Rate limited; retry after {}s", self.retry_after.as_secs())
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchType {
+    Track,
+    Album,
+    Artist,
+    Playlist,
+    Show,
+    Episode,
+    Audiobook,
+}
+
+impl SearchType {
+    pub const ALL: [SearchType; 7] = [
+        SearchType::Track, SearchType::Album, SearchType::Artist, SearchType::Playlist,
+        SearchType::Show, SearchType::Episode, SearchType::Audiobook,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchType::Track => "track",
+            SearchType::Album => "album",
+            SearchType::Artist => "artist",
+            SearchType::Playlist => "playlist",
+            SearchType::Show => "show",
+            SearchType::Episode => "episode",
+            SearchType::Audiobook => "audiobook",
+        }
+    }
+}
+
+impl fmt::Display for SearchType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for SearchType {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        let name = name.trim().to_lowercase();
+        let singular = name.strip_suffix('s').unwrap_or(&name);
+        SearchType::ALL.iter()
+            .find(|search_type| search_type.as_str() == singular)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown search type \"{}\"; expected one of: {}", name,
+                SearchType::ALL.iter().map(|search_type| search_type.as_str()).collect::<Vec<_>>().join(", ")))
+    }
+}
+
+/// Searches any combination of item types in one request.
+pub struct Search {
     config: Rc<SpotifyConfig>,
     query: String,
+    types: Vec<SearchType>,
+    limit: u32,
+    offset: u32,
+    market: Option<String>,
+    include_external_audio: bool,
 }
 
-impl SearchAlbums {
-    pub fn new(config: &Rc<SpotifyConfig>, query: &str) -> Self {
+impl Search {
+    /// Defaults to 20 results per type, in the market of the access token.
+    pub fn new(config: &Rc<SpotifyConfig>, query: &str, types: &[SearchType]) -> Self {
         Self {
             config: config.clone(),
             query: query.to_owned(),
+            types: types.to_vec(),
+            limit: 20,
+            offset: 0,
+            market: Some(String::from("from_token")),
+            include_external_audio: false,
         }
     }
 
-    pub async fn execute(&self) -> Result<SearchAlbumsResponse> {
+    /// Results per type, from 1 to 50.
+    pub fn with_limit(self, limit: u32) -> Self {
+        Self {
+            limit,
+            ..self
+        }
+    }
+
+    pub fn with_offset(self, offset: u32) -> Self {
+        Self {
+            offset,
+            ..self
+        }
+    }
+
+    /// An ISO 3166-1 alpha-2 country code, `from_token`, or `None` for content from any market.
+    pub fn with_market(self, market: Option<&str>) -> Self {
+        Self {
+            market: market.map(|market| market.to_owned()),
+            ..self
+        }
+    }
+
+    /// Includes externally hosted audio among episode results.
+    pub fn with_external_audio(self, include_external_audio: bool) -> Self {
+        Self {
+            include_external_audio,
+            ..self
+        }
+    }
+
+    pub async fn execute(&self) -> Result<SearchResponse> {
+        if self.query.trim().is_empty() {
+            bail!("Search query is empty")
+        }
+        if self.types.is_empty() {
+            bail!("No search types given")
+        }
+        if self.limit == 0 || self.limit > MAX_LIMIT {
+            bail!("Search limit must be between 1 and {}", MAX_LIMIT)
+        }
+        if self.offset > MAX_OFFSET {
+            bail!("Search offset must be at most {}", MAX_OFFSET)
+        }
+
         let client = Client::new();
-        let parameters = [
-            ("q", self.query.as_str()),
-            ("type", "album"),
-            ("market", "from_token"),
-            ("limit", "50"),
+        let types = self.types.iter()
+            .map(|search_type| search_type.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut parameters = vec![
+            ("q", self.query.clone()),
+            ("type", types),
+            ("limit", self.limit.to_string()),
+            ("offset", self.offset.to_string()),
         ];
+        if let Some(market) = &self.market {
+            parameters.push(("market", market.clone()));
+        }
+        if self.include_external_audio {
+            parameters.push(("include_external", String::from("audio")));
+        }
         let response = client.get("https://api.spotify.com/v1/search")
             .bearer_auth(&self.config.access_token)
             .query(&parameters)
@@ -34,15 +175,44 @@ impl SearchAlbums {
             .await?;
 
         if response.status().is_success() {
-            response.json::<SearchAlbumsResponse>().await
+            response.json::<SearchResponse>().await
                 .with_context(|| "Failed to parse response")
+        } else if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response.headers().get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(1);
+            Err(RateLimited { retry_after: Duration::from_secs(retry_after) }.into())
         } else {
             let e = response.json::<ErrorResponse>().await?;
-            bail!("Request failed: {}", e.error.message)
+            Err(RequestFailed::from(e).into())
         }
     }
 }
 
+pub struct SearchAlbums {
+    config: Rc<SpotifyConfig>,
+    query: String,
+}
+
+impl SearchAlbums {
+    pub fn new(config: &Rc<SpotifyConfig>, query: &str) -> Self {
+        Self {
+            config: config.clone(),
+            query: query.to_owned(),
+        }
+    }
+
+    pub async fn execute(&self) -> Result<SearchAlbumsResponse> {
+        let response = Search::new(&self.config, &self.query, &[SearchType::Album])
+            .with_limit(MAX_LIMIT)
+            .execute()
+            .await?;
+        let albums = response.albums.ok_or_else(|| anyhow!("Response has no albums"))?;
+        Ok(SearchAlbumsResponse { albums })
+    }
+}
+
 pub struct SearchArtists {
     config: Rc<SpotifyConfig>,
     query: String,
@@ -57,25 +227,59 @@ impl SearchArtists {
     }
 
     pub async fn execute(&self) -> Result<SearchArtistsResponse> {
-        let client = Client::new();
-        let parameters = [
-            ("q", self.query.as_str()),
-            ("type", "artist"),
-            ("market", "from_token"),
-            ("limit", "50"),
-        ];
-        let response = client.get("https://api.spotify.com/v1/search")
-            .bearer_auth(&self.config.access_token)
-            .query(&parameters)
-            .send()
+        let response = Search::new(&self.config, &self.query, &[SearchType::Artist])
+            .with_limit(MAX_LIMIT)
+            .execute()
             .await?;
+        let artists = response.artists.ok_or_else(|| anyhow!("Response has no artists"))?;
+        Ok(SearchArtistsResponse { artists })
+    }
+}
 
-        if response.status().is_success() {
-            response.json::<SearchArtistsResponse>().await
-                .with_context(|| "Failed to parse response")
-        } else {
-            let e = response.json::<ErrorResponse>().await?;
-            bail!("Request failed: {}", e.error.message)
+pub struct SearchTracks {
+    config: Rc<SpotifyConfig>,
+    query: String,
+}
+
+impl SearchTracks {
+    pub fn new(config: &Rc<SpotifyConfig>, query: &str) -> Self {
+        Self {
+            config: config.clone(),
+            query: query.to_owned(),
+        }
+    }
+
+    pub async fn execute(&self) -> Result<SearchTracksResponse> {
+        let response = Search::new(&self.config, &self.query, &[SearchType::Track])
+            .with_limit(MAX_LIMIT)
+            .execute()
+            .await?;
+        let tracks = response.tracks.ok_or_else(|| anyhow!("Response has no tracks"))?;
+        Ok(SearchTracksResponse { tracks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_type_names() {
+        for search_type in SearchType::ALL.iter() {
+            assert_eq!(search_type.to_string().parse::<SearchType>().unwrap(), *search_type);
         }
+        assert_eq!("tracks".parse::<SearchType>().unwrap(), SearchType::Track);
+        assert_eq!(" Shows ".parse::<SearchType>().unwrap(), SearchType::Show);
+        assert_eq!("AudioBook".parse::<SearchType>().unwrap(), SearchType::Audiobook);
+    }
+
+    #[test]
+    fn unknown_search_types() {
+        assert_eq!(
+            "Podcast".parse::<SearchType>().unwrap_err().to_string(),
+            "Unknown search type \"podcast\"; expected one of: track, album, artist, playlist, show, episode, audiobook",
+        );
+        assert!("".parse::<SearchType>().is_err());
+        assert!("trackss".parse::<SearchType>().is_err());
     }
 }
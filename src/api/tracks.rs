@@ -1,30 +1,35 @@
 use std::rc::Rc;
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result};
 use reqwest::Client;
 
 use crate::config::SpotifyConfig;
 use crate::objects::*;
+use super::error::RequestFailed;
 
 struct ListTracks {
     config: Rc<SpotifyConfig>,
     album_id: String,
+    offset: u32,
 }
 
 impl ListTracks {
-    fn new(config: &Rc<SpotifyConfig>, album_id: &str) -> Self {
+    fn new(config: &Rc<SpotifyConfig>, album_id: &str, offset: u32) -> Self {
         Self {
             config: config.clone(),
             album_id: album_id.to_owned(),
+            offset,
         }
     }
 
     async fn execute(&self) -> Result<ListTracksResponse> {
         let client = Client::new();
         let request_uri = format!("https://api.spotify.com/v1/albums/{}/tracks", self.album_id);
+        let offset = self.offset.to_string();
         let parameters = [
             ("market", "from_token"),
             ("limit", "50"),
+            ("offset", &offset),
         ];
         let response = client.get(request_uri)
             .bearer_auth(&self.config.access_token)
@@ -37,13 +42,73 @@ impl ListTracks {
                 .with_context(|| "Failed to parse response")
         } else {
             let e = response.json::<ErrorResponse>().await?;
-            bail!("Request failed: {}", e.error.message)
+            Err(RequestFailed::from(e).into())
+        }
+    }
+}
+
+const MAX_IDS_PER_REQUEST: usize = 50;
+
+struct GetTracks {
+    config: Rc<SpotifyConfig>,
+    ids: Vec<String>,
+}
+
+impl GetTracks {
+    fn new(config: &Rc<SpotifyConfig>, ids: &[String]) -> Self {
+        Self {
+            config: config.clone(),
+            ids: ids.to_vec(),
+        }
+    }
+
+    async fn execute(&self) -> Result<GetTracksResponse> {
+        let client = Client::new();
+        let ids = self.ids.join(",");
+        let parameters = [
+            ("ids", &ids),
+        ];
+        let response = client.get("https://api.spotify.com/v1/tracks")
+            .bearer_auth(&self.config.access_token)
+            .query(&parameters)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            response.json::<GetTracksResponse>().await
+                .with_context(|| "Failed to parse response")
+        } else {
+            let e = response.json::<ErrorResponse>().await?;
+            Err(RequestFailed::from(e).into())
         }
     }
 }
 
 pub async fn list_tracks(config: &Rc<SpotifyConfig>, album_id: &str) -> Result<ListTracksResponse> {
-    ListTracks::new(config, album_id)
+    ListTracks::new(config, album_id, 0)
         .execute()
         .await
 }
+
+pub async fn album_track_uris(config: &Rc<SpotifyConfig>, album_id: &str) -> Result<Vec<String>> {
+    let mut uris = vec![];
+    loop {
+        let response = ListTracks::new(config, album_id, uris.len() as u32)
+            .execute()
+            .await?;
+        uris.extend(response.items.into_iter().map(|track| track.uri));
+        if response.next.is_none() {
+            return Ok(uris)
+        }
+    }
+}
+
+/// Fetches full track objects in batches of 50, skipping ids Spotify does not know.
+pub async fn get_tracks(config: &Rc<SpotifyConfig>, ids: &[String]) -> Result<Vec<Track>> {
+    let mut tracks = vec![];
+    for chunk in ids.chunks(MAX_IDS_PER_REQUEST) {
+        let response = GetTracks::new(config, chunk).execute().await?;
+        tracks.extend(response.tracks.into_iter().flatten());
+    }
+    Ok(tracks)
+}
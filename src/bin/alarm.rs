@@ -0,0 +1,70 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::alarm::{Alarm, AlarmSchedule, AlarmSettings};
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::timestamp::parse_duration;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "alarm")]
+struct Arguments {
+    /// When to ring: a cron expression such as "30 7 * * 1-5", "HH:MM" for daily, or a timestamp; rings now when omitted
+    #[structopt(short, long)]
+    at: Option<AlarmSchedule>,
+
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
+
+    /// Context URI to play, such as a playlist or album; resumes playback when omitted
+    #[structopt(short, long)]
+    uri: Option<String>,
+
+    /// Volume to play at, in percent
+    #[structopt(short, long)]
+    volume: Option<u32>,
+
+    /// Raise the volume from silence over this long, such as 2m
+    #[structopt(short, long, parse(try_from_str = parse_duration))]
+    ramp: Option<Duration>,
+
+    /// Turn shuffle on
+    #[structopt(long, conflicts_with = "no-shuffle")]
+    shuffle: bool,
+
+    /// Turn shuffle off
+    #[structopt(long)]
+    no_shuffle: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    let shuffle = match (arguments.shuffle, arguments.no_shuffle) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    let alarm = Alarm::new(&config, AlarmSettings {
+        device: arguments.device,
+        context_uri: arguments.uri,
+        volume: arguments.volume,
+        ramp: arguments.ramp,
+        shuffle,
+    });
+
+    match arguments.at {
+        Some(schedule) => alarm.run(&schedule).await?,
+        None => {
+            let device = alarm.ring().await?;
+            println!("Playing on {}", device.name);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::history::{HistoryRecorder, HistoryStore};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "history")]
+struct Arguments {
+    /// SQLite database to record plays into
+    #[structopt(short, long, default_value = "history.sqlite3", parse(from_os_str))]
+    database: PathBuf,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Records plays continuously from the player and recently played tracks
+    Record {
+        /// Seconds between syncs of recently played tracks
+        #[structopt(long, default_value = "600")]
+        sync_interval: u64,
+    },
+
+    /// Imports recently played tracks once and exits
+    Sync,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+    let store = HistoryStore::open(&arguments.database)?;
+    let mut recorder = HistoryRecorder::new(&config, store);
+
+    match arguments.command {
+        Command::Record { sync_interval } => {
+            recorder.run(Duration::from_secs(sync_interval)).await
+        }
+        Command::Sync => {
+            let inserted = recorder.sync_recently_played().await?;
+            recorder.fetch_artist_metadata().await?;
+            println!("Recorded {} new plays", inserted);
+            Ok(())
+        }
+    }
+}
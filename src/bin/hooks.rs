@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+use tokio::sync::mpsc;
+
+use spotifyexp::config::{SpotifyConfig, TokenKeeper};
+use spotifyexp::hooks::{HookRunner, HooksConfig};
+use spotifyexp::watcher::PlayerWatcher;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "hooks")]
+struct Arguments {
+    /// Hooks configuration file
+    #[structopt(short, long, default_value = "hooks.toml", parse(from_os_str))]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+    let (failures, mut failed) = mpsc::unbounded_channel();
+    let runner = HookRunner::new(HooksConfig::from_file(&arguments.config)?, failures);
+    tokio::spawn(async move {
+        while let Some(e) = failed.recv().await {
+            eprintln!("{:#}", e);
+        }
+    });
+
+    let tokens = TokenKeeper::new(&config);
+    let mut watcher = PlayerWatcher::new(&config);
+    loop {
+        match watcher.next_event(&tokens).await {
+            Ok(event) => runner.dispatch(&event)?,
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::api::get_artists;
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::history::HistoryStore;
+use spotifyexp::import::{find_export_files, read_export_file, Importer};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "import_history")]
+struct Arguments {
+    /// SQLite database to import plays into
+    #[structopt(short, long, default_value = "history.sqlite3", parse(from_os_str))]
+    database: PathBuf,
+
+    /// Streaming history files, or directories of an unpacked privacy data export
+    #[structopt(required = true, parse(from_os_str))]
+    paths: Vec<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+    let mut store = HistoryStore::open(&arguments.database)?;
+
+    let mut files = vec![];
+    for path in arguments.paths.iter() {
+        if path.is_dir() {
+            files.extend(find_export_files(path)?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    for file in files.iter() {
+        let entries = read_export_file(file)?;
+        let summary = Importer::new(&config, &mut store).import(entries).await?;
+        println!("{}: {} entries, {} imported, {} already known, {} skipped, {} unresolved",
+            file.display(), summary.entries, summary.imported, summary.duplicates, summary.skipped, summary.unresolved);
+    }
+
+    let artist_ids = store.artists_without_metadata()?;
+    let artists = get_artists(&config, &artist_ids).await?;
+    store.store_artist_metadata(&artists)?;
+
+    Ok(())
+}
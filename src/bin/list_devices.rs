@@ -2,13 +2,15 @@ use std::rc::Rc;
 
 use anyhow::Result;
 
-use spotifyexp::api::ListDevices;
+use spotifyexp::api::{format_device, ListDevices};
 use spotifyexp::config::SpotifyConfig;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let config = Rc::new(SpotifyConfig::from_env()?);
     let response = ListDevices::new(&config).execute().await?;
-    println!("{:?}", response);
+    for device in response.devices.iter() {
+        println!("{}", format_device(device));
+    }
     Ok(())
 }
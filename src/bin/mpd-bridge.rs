@@ -0,0 +1,36 @@
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::mpd::{serve, MpdOptions};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "mpd-bridge")]
+struct Arguments {
+    /// Address to accept MPD clients on; anything beyond loopback requires a password
+    #[structopt(short, long, default_value = "127.0.0.1:6600")]
+    bind: SocketAddr,
+
+    /// Password clients must send with the "password" command
+    #[structopt(short, long, env = "SPOTIFYEXP_MPD_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    serve(&config, MpdOptions {
+        bind: arguments.bind,
+        device: arguments.device,
+        password: arguments.password,
+    }).await
+}
@@ -0,0 +1,25 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::mpris::{run, MprisOptions};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "mpris")]
+struct Arguments {
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    run(&config, MprisOptions {
+        device: arguments.device,
+    }).await
+}
@@ -0,0 +1,65 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::mqtt::{run, MqttBridgeOptions};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "mqtt-bridge")]
+struct Arguments {
+    /// MQTT broker host
+    #[structopt(short, long, default_value = "localhost")]
+    host: String,
+
+    /// MQTT broker port
+    #[structopt(short, long, default_value = "1883")]
+    port: u16,
+
+    /// Client id presented to the broker
+    #[structopt(long, default_value = "spotifyexp")]
+    client_id: String,
+
+    /// Broker username
+    #[structopt(short, long, env = "SPOTIFYEXP_MQTT_USERNAME")]
+    username: Option<String>,
+
+    /// Broker password
+    #[structopt(long, env = "SPOTIFYEXP_MQTT_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Prefix of the state, event, availability and command topics
+    #[structopt(short = "t", long, default_value = "spotifyexp")]
+    prefix: String,
+
+    /// Home Assistant discovery prefix
+    #[structopt(long, default_value = "homeassistant")]
+    discovery_prefix: String,
+
+    /// Don't publish Home Assistant discovery messages
+    #[structopt(long)]
+    no_discovery: bool,
+
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+    let no_discovery = arguments.no_discovery;
+
+    run(&config, MqttBridgeOptions {
+        host: arguments.host,
+        port: arguments.port,
+        client_id: arguments.client_id,
+        username: arguments.username,
+        password: arguments.password,
+        prefix: arguments.prefix,
+        discovery_prefix: Some(arguments.discovery_prefix).filter(|_| !no_discovery),
+        device: arguments.device,
+    }).await
+}
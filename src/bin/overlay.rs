@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::overlay::{OverlayOptions, OverlayWriter};
+use spotifyexp::template::Template;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "overlay")]
+struct Arguments {
+    /// Directory to keep now_playing.txt, now_playing.json and cover.jpg in
+    #[structopt(default_value = "overlay", parse(from_os_str))]
+    directory: PathBuf,
+
+    /// Text for now_playing.txt; fields: {title} {artist} {album} {state} {icon} {progress}
+    /// {duration} {percent} {device} {volume} {shuffle} {repeat} {uri}, cut to N characters with {field:N}
+    #[structopt(short, long, default_value = "{artist} - {title}")]
+    template: Template,
+
+    /// Text written when nothing is playing
+    #[structopt(long, default_value = "")]
+    stopped_text: String,
+
+    /// Preferred cover width in pixels
+    #[structopt(long, default_value = "640")]
+    cover_width: u32,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    let mut writer = OverlayWriter::new(OverlayOptions {
+        directory: arguments.directory,
+        template: arguments.template,
+        stopped_text: arguments.stopped_text,
+        cover_width: arguments.cover_width,
+    })?;
+    writer.run(&config).await
+}
@@ -3,20 +3,22 @@ use std::rc::Rc;
 use anyhow::Result;
 use structopt::StructOpt;
 
-use spotifyexp::api::pause;
+use spotifyexp::api::{pause, resolve_device_id};
 use spotifyexp::config::SpotifyConfig;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "pause")]
 struct Arguments {
-    #[structopt(short, long)]
-    device_id: String,
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let arguments = Arguments::from_args();
     let config = Rc::new(SpotifyConfig::from_env()?);
+    let device_id = resolve_device_id(&config, arguments.device.as_deref()).await?;
 
-    pause(&config, &arguments.device_id).await
+    pause(&config, &device_id).await
 }
@@ -3,14 +3,19 @@ use std::rc::Rc;
 use anyhow::Result;
 use structopt::StructOpt;
 
-use spotifyexp::api::{enqueue_tracks, is_playing, skip_to_next, start_playing};
+use spotifyexp::api::{play_now, resolve_device_id, PlayMode};
 use spotifyexp::config::SpotifyConfig;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "play")]
 struct Arguments {
-    #[structopt(short, long)]
-    device_id: String,
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
+
+    /// How to combine the tracks with current playback: replace, next (queue and resume) or append
+    #[structopt(short, long, default_value = "replace")]
+    mode: PlayMode,
 
     #[structopt(short, long)]
     uri: Vec<String>,
@@ -20,15 +25,10 @@ struct Arguments {
 async fn main() -> Result<()> {
     let arguments = Arguments::from_args();
     let config = Rc::new(SpotifyConfig::from_env()?);
+    let device_id = resolve_device_id(&config, arguments.device.as_deref()).await?;
 
-    enqueue_tracks(&config, &arguments.device_id, arguments.uri).await?;
-
-    let playing = is_playing(&config).await?;
-    skip_to_next(&config, &arguments.device_id).await?;
-
-    if !playing {
-        start_playing(&config, &arguments.device_id).await?;
-    }
+    let report = play_now(&config, &device_id, arguments.uri, arguments.mode).await?;
+    println!("{}", report);
 
     Ok(())
 }
@@ -3,23 +3,44 @@ use std::rc::Rc;
 use anyhow::Result;
 use structopt::StructOpt;
 
-use spotifyexp::api::playback;
+use spotifyexp::api::{resolve_device_id, start_playback, PlaybackOffset, PlaybackRequest};
 use spotifyexp::config::SpotifyConfig;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "playback")]
 struct Arguments {
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
+
+    /// Context URI (album, artist or playlist) to play
+    #[structopt(short, long)]
+    uri: Option<String>,
+
+    /// Track or episode URIs to play instead of a context
+    #[structopt(short, long = "track", conflicts_with = "uri")]
+    tracks: Vec<String>,
+
+    /// Zero-based position or item URI to start from
     #[structopt(short, long)]
-    device_id: String,
+    offset: Option<PlaybackOffset>,
 
+    /// Position in the first track to start at, in milliseconds
     #[structopt(short, long)]
-    uri: String,
+    position_ms: Option<u32>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let arguments = Arguments::from_args();
     let config = Rc::new(SpotifyConfig::from_env()?);
+    let device_id = resolve_device_id(&config, arguments.device.as_deref()).await?;
 
-    playback(&config, &arguments.device_id, &arguments.uri).await
+    let request = PlaybackRequest {
+        context_uri: arguments.uri,
+        uris: arguments.tracks,
+        offset: arguments.offset,
+        position_ms: arguments.position_ms,
+    };
+    start_playback(&config, &device_id, request).await
 }
@@ -0,0 +1,73 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::api::{enqueue_tracks, get_queue, resolve_device_id};
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::objects::PlayableItem;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "queue")]
+enum Arguments {
+    /// Shows the currently playing item and the upcoming queue
+    Show,
+
+    /// Adds tracks, episodes, albums or playlists to the queue
+    Add {
+        /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+        #[structopt(short, long, alias = "device-id")]
+        device: Option<String>,
+
+        #[structopt(short, long, required = true)]
+        uri: Vec<String>,
+    },
+}
+
+fn show_item(item: &PlayableItem) {
+    match item {
+        PlayableItem::Track(track) => {
+            println!("{} {} - {}", track.uri, track.name, track.artist_names());
+        }
+        PlayableItem::Episode(episode) => {
+            let show = episode.show.as_ref()
+                .map_or("unknown", |show| &show.name);
+            println!("{} {} [{}]", episode.uri, episode.name, show);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    match arguments {
+        Arguments::Show => {
+            let response = get_queue(&config).await?;
+            match &response.currently_playing {
+                Some(item) => {
+                    print!("Now playing: ");
+                    show_item(item);
+                }
+                None => println!("Nothing is playing"),
+            }
+            for item in response.queue.iter() {
+                show_item(item);
+            }
+        }
+        Arguments::Add { device, uri } => {
+            let device_id = resolve_device_id(&config, device.as_deref()).await?;
+            let report = enqueue_tracks(&config, &device_id, uri).await?;
+            for uri in report.queued.iter() {
+                if report.already_queued.contains(uri) {
+                    println!("queued {} (it was already in the queue)", uri);
+                } else {
+                    println!("queued {}", uri);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
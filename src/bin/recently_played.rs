@@ -0,0 +1,46 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use structopt::StructOpt;
+
+use spotifyexp::api::recently_played_between;
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::objects::PlayHistory;
+use spotifyexp::timestamp::parse_timestamp;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "recently_played")]
+struct Arguments {
+    /// Only show plays at or after this time (RFC 3339, or local "YYYY-MM-DD HH:MM")
+    #[structopt(short, long, parse(try_from_str = parse_timestamp))]
+    after: Option<DateTime<Utc>>,
+
+    /// Only show plays before this time (RFC 3339, or local "YYYY-MM-DD HH:MM")
+    #[structopt(short, long, parse(try_from_str = parse_timestamp))]
+    before: Option<DateTime<Utc>>,
+}
+
+fn show_play(play: &PlayHistory) {
+    let context = play.context.as_ref()
+        .map_or("-", |context| &context.uri);
+    println!("{} {} {} - {} [{}]",
+        play.played_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+        play.track.uri,
+        play.track.name,
+        play.track.artist_names(),
+        context);
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    let history = recently_played_between(&config, arguments.after, arguments.before).await?;
+    for play in history.iter().rev() {
+        show_play(play);
+    }
+
+    Ok(())
+}
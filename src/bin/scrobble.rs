@@ -0,0 +1,79 @@
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::history::HistoryStore;
+use spotifyexp::scrobble::{read_listens, render_lastfm_csv, render_listenbrainz, ListenBrainzClient, Scrobbler};
+use spotifyexp::timestamp::parse_timestamp;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "scrobble")]
+enum Arguments {
+    /// Exports recorded history as ListenBrainz listens or Last.fm scrobble CSV
+    Export {
+        /// SQLite database recorded by the history command
+        #[structopt(short, long, default_value = "history.sqlite3", parse(from_os_str))]
+        database: PathBuf,
+
+        /// Output format: listenbrainz or lastfm
+        #[structopt(short, long, default_value = "listenbrainz")]
+        format: String,
+
+        /// Only export listens at or after this time (RFC 3339, or local "YYYY-MM-DD HH:MM")
+        #[structopt(long, parse(try_from_str = parse_timestamp))]
+        since: Option<DateTime<Utc>>,
+
+        /// Only export listens before this time (RFC 3339, or local "YYYY-MM-DD HH:MM")
+        #[structopt(long, parse(try_from_str = parse_timestamp))]
+        until: Option<DateTime<Utc>>,
+
+        /// Write to a file instead of standard output
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Submits listens to a ListenBrainz-compatible server as tracks are played
+    Submit {
+        /// API root of the ListenBrainz-compatible server
+        #[structopt(long, default_value = "https://api.listenbrainz.org")]
+        endpoint: String,
+
+        /// User token for the server
+        #[structopt(long, env = "LISTENBRAINZ_TOKEN", hide_env_values = true)]
+        token: String,
+
+        /// Also report the track that is playing now
+        #[structopt(long)]
+        playing_now: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    match Arguments::from_args() {
+        Arguments::Export { database, format, since, until, output } => {
+            let store = HistoryStore::open(&database)?;
+            let listens = read_listens(&store, since, until)?;
+            let rendered = match format.as_str() {
+                "listenbrainz" => render_listenbrainz(&listens)?,
+                "lastfm" => render_lastfm_csv(&listens),
+                other => bail!("Unknown format: {} (expected listenbrainz or lastfm)", other),
+            };
+            match output {
+                Some(path) => fs::write(path, rendered)?,
+                None => print!("{}", rendered),
+            }
+            Ok(())
+        }
+        Arguments::Submit { endpoint, token, playing_now } => {
+            let config = Rc::new(SpotifyConfig::from_env()?);
+            let client = ListenBrainzClient::new(&endpoint, &token);
+            Scrobbler::new(&config, client, playing_now).run().await
+        }
+    }
+}
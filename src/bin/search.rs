@@ -0,0 +1,108 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::api::{Search, SearchType};
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::objects::{Paging, Person, SearchResponse};
+use spotifyexp::search_query::SearchQueryArguments;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "search")]
+struct Arguments {
+    #[structopt(flatten)]
+    query: SearchQueryArguments,
+
+    /// Item types to search, comma separated: track, album, artist, playlist, show, episode, audiobook
+    #[structopt(short, long = "type", use_delimiter = true, default_value = "track")]
+    types: Vec<SearchType>,
+
+    /// Results per type, up to 50
+    #[structopt(short, long, default_value = "20")]
+    limit: u32,
+
+    /// Index of the first result, for paging
+    #[structopt(short, long, default_value = "0")]
+    offset: u32,
+
+    /// Country code to search in, or "any" for all markets; defaults to the account's country
+    #[structopt(short, long, default_value = "from_token")]
+    market: String,
+
+    /// Include externally hosted audio among episodes
+    #[structopt(long)]
+    include_external: bool,
+}
+
+fn people(people: &[Person]) -> String {
+    people.iter()
+        .map(|person| person.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn show_section<T, F>(heading: &str, paging: &Option<Paging<T>>, show_item: F)
+    where F: Fn(&T) -> String
+{
+    let paging = match paging {
+        Some(paging) => paging,
+        None => return,
+    };
+    println!("{} ({} of {})", heading, paging.items.len(), paging.total);
+    for item in paging.items.iter() {
+        println!("  {}", show_item(item));
+    }
+}
+
+fn show_results(response: &SearchResponse) {
+    show_section("Tracks", &response.tracks, |track| format!("{} {} - {}", track.uri, track.name, track.artist_names()));
+    show_section("Albums", &response.albums, |album| format!("{} {} - {}", album.uri, album.name, album.artist_names()));
+    show_section("Artists", &response.artists, |artist| format!("{} {}", artist.uri, artist.name));
+    show_section("Playlists", &response.playlists, |playlist| match playlist {
+        Some(playlist) => format!("{} {} ({} tracks)", playlist.uri, playlist.name, playlist.tracks.total),
+        None => String::from("(unavailable)"),
+    });
+    show_section("Shows", &response.shows, |show| format!("{} {} - {}", show.uri, show.name, show.publisher));
+    show_section("Episodes", &response.episodes, |episode| format!("{} {} ({})", episode.uri, episode.name, episode.release_date));
+    show_section("Audiobooks", &response.audiobooks, |audiobook| format!("{} {} - {}", audiobook.uri, audiobook.name, people(&audiobook.authors)));
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    let market = Some(arguments.market.as_str()).filter(|market| *market != "any");
+    let query = arguments.query.to_query()?;
+    let search = Search::new(&config, &query.to_string(), &arguments.types)
+        .with_limit(arguments.limit)
+        .with_offset(arguments.offset)
+        .with_market(market)
+        .with_external_audio(arguments.include_external);
+    let response = search.execute().await?;
+    show_results(&response);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(arguments: &[&str]) -> std::result::Result<Arguments, structopt::clap::Error> {
+        Arguments::from_iter_safe(std::iter::once("search").chain(arguments.iter().copied()))
+    }
+
+    #[test]
+    fn types_are_a_comma_separated_list() {
+        assert_eq!(parse(&["--artist", "Daft Punk"]).unwrap().types, [SearchType::Track]);
+        assert_eq!(parse(&["--type", "albums,artist", "--artist", "Daft Punk"]).unwrap().types, [SearchType::Album, SearchType::Artist]);
+    }
+
+    #[test]
+    fn unknown_types_are_rejected() {
+        let error = parse(&["--type", "album,podcast", "--artist", "Daft Punk"]).unwrap_err();
+        assert!(error.message.contains("Unknown search type \"podcast\"; expected one of: track, album"), "{}", error.message);
+    }
+}
@@ -6,12 +6,13 @@ use structopt::StructOpt;
 use spotifyexp::api::SearchArtists;
 use spotifyexp::config::SpotifyConfig;
 use spotifyexp::objects::{SearchArtistsResponse, Artist};
+use spotifyexp::search_query::SearchQueryArguments;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "search_artists")]
 struct Arguments {
-    #[structopt(short, long)]
-    query: String,
+    #[structopt(flatten)]
+    query: SearchQueryArguments,
 }
 
 fn show_artist(artist: &Artist) {
@@ -29,7 +30,8 @@ async fn main() -> Result<()> {
     let arguments = Arguments::from_args();
     let config = Rc::new(SpotifyConfig::from_env()?);
 
-    let search = SearchArtists::new(&config, &arguments.query);
+    let query = arguments.query.to_query()?;
+    let search = SearchArtists::new(&config, &query.to_string());
     let response = search.execute().await?;
     show_results(&response);
 
@@ -0,0 +1,31 @@
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::server::{serve, ServerOptions};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "serve")]
+struct Arguments {
+    /// Address to listen on
+    #[structopt(short, long, default_value = "127.0.0.1:8937")]
+    bind: SocketAddr,
+
+    /// Key clients must send as "Authorization: Bearer <key>"
+    #[structopt(short, long, env = "SPOTIFYEXP_API_KEY", hide_env_values = true)]
+    key: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    serve(&config, ServerOptions {
+        bind: arguments.bind,
+        api_key: arguments.key,
+    }).await
+}
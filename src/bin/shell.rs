@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::shell::Shell;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "shell")]
+struct Arguments {
+    /// File to keep command history in
+    #[structopt(long, default_value = "shell_history", parse(from_os_str))]
+    history: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+    Shell::new(&config).run(&arguments.history).await
+}
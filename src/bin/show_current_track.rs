@@ -7,13 +7,9 @@ use spotifyexp::config::SpotifyConfig;
 use spotifyexp::objects::CurrentlyPlayingItem;
 
 fn show_track(item: &CurrentlyPlayingItem) {
-    let artists = item.artists.iter()
-        .map(|artist| artist.name.clone())
-        .collect::<Vec<_>>()
-        .join(", ");
     let album_title = item.album.as_ref()
         .map_or("unknown", |album| &album.name);
-    println!("{} [{}] - {}", item.name, album_title, artists);
+    println!("{} [{}] - {}", item.name, album_title, item.artist_names());
 }
 
 #[tokio::main]
@@ -21,7 +17,10 @@ async fn main() -> Result<()> {
     let config = Rc::new(SpotifyConfig::from_env()?);
 
     let response = get_currently_playing_track(&config).await?;
-    show_track(&response.item);
+    match response.and_then(|response| response.item) {
+        Some(item) => show_track(&item),
+        None => println!("Nothing is playing"),
+    }
 
     Ok(())
 }
@@ -0,0 +1,43 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::api::resolve_device_id;
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::sleep_timer::{FadeCurve, SleepTimer, SleepTrigger};
+use spotifyexp::timestamp::parse_duration;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "sleep")]
+struct Arguments {
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
+
+    /// When to stop: a duration such as 30m or 1h15m, "track" or "album"
+    #[structopt(short, long)]
+    at: SleepTrigger,
+
+    /// How long to fade the volume out before pausing
+    #[structopt(short, long, default_value = "60s", parse(try_from_str = parse_duration))]
+    fade: Duration,
+
+    /// Fade curve: linear, ease-out or exponential
+    #[structopt(short, long, default_value = "ease-out")]
+    curve: FadeCurve,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+    let device_id = resolve_device_id(&config, arguments.device.as_deref()).await?;
+
+    let timer = SleepTimer::new(&config, &device_id, arguments.at, arguments.fade, arguments.curve);
+    let outcome = timer.run().await?;
+    println!("{}", outcome);
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::snapshot::Snapshot;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "snapshot")]
+enum Arguments {
+    /// Captures the current player state to a file
+    Save {
+        #[structopt(default_value = "snapshot.json", parse(from_os_str))]
+        file: PathBuf,
+    },
+
+    /// Puts the player back to the state captured in a file
+    Restore {
+        #[structopt(default_value = "snapshot.json", parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    match arguments {
+        Arguments::Save { file } => {
+            let snapshot = Snapshot::take(&config).await?;
+            snapshot.save(&file)?;
+            println!("Saved {} at {} ms on {}", snapshot.item_uri, snapshot.progress_ms, snapshot.device_name);
+        }
+        Arguments::Restore { file } => {
+            let snapshot = Snapshot::load(&file)?;
+            let device = snapshot.restore(&config).await?;
+            println!("Restored {} at {} ms on {}", snapshot.item_uri, snapshot.progress_ms, device.name);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use structopt::StructOpt;
+
+use spotifyexp::history::HistoryStore;
+use spotifyexp::stats::{Period, RecentPeriod, Report, ReportFormat};
+use spotifyexp::timestamp::parse_timestamp;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "stats")]
+struct Arguments {
+    /// SQLite database recorded by the history command
+    #[structopt(short, long, default_value = "history.sqlite3", parse(from_os_str))]
+    database: PathBuf,
+
+    /// Reporting period: day, week, month, year or all
+    #[structopt(short, long, default_value = "month")]
+    period: RecentPeriod,
+
+    /// Start of the period, overriding --period (RFC 3339, or local "YYYY-MM-DD HH:MM")
+    #[structopt(long, parse(try_from_str = parse_timestamp))]
+    since: Option<DateTime<Utc>>,
+
+    /// End of the period (RFC 3339, or local "YYYY-MM-DD HH:MM")
+    #[structopt(long, parse(try_from_str = parse_timestamp))]
+    until: Option<DateTime<Utc>>,
+
+    /// Number of entries in each ranking
+    #[structopt(short, long, default_value = "10")]
+    limit: usize,
+
+    /// Output format: table, json or html
+    #[structopt(short, long, default_value = "table")]
+    format: ReportFormat,
+
+    /// Write the report to a file instead of standard output
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+fn period(arguments: &Arguments) -> Period {
+    let mut period = arguments.period.period();
+    if arguments.since.is_some() {
+        period.since = arguments.since;
+    }
+    period.until = arguments.until;
+    period
+}
+
+fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let store = HistoryStore::open(&arguments.database)?;
+    let report = Report::build(&store, period(&arguments), arguments.limit)?;
+    let rendered = arguments.format.render(&report)?;
+
+    match &arguments.output {
+        Some(path) => fs::write(path, rendered)?,
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
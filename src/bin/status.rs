@@ -0,0 +1,94 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::status::{follow, print_status, ClickAction, StatusFormat, StatusOptions};
+use spotifyexp::template::Template;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "status")]
+struct Arguments {
+    /// Output format: plain, waybar, polybar, i3blocks or tmux
+    #[structopt(short, long, default_value = "plain")]
+    format: StatusFormat,
+
+    /// Status text; fields: {title} {artist} {album} {state} {icon} {progress} {duration}
+    /// {percent} {device} {volume} {shuffle} {repeat} {uri}, cut to N characters with {field:N}
+    #[structopt(short, long, default_value = "{icon} {artist} - {title}")]
+    template: Template,
+
+    /// Waybar tooltip and i3blocks short text, in the same syntax as --template
+    #[structopt(long, default_value = "{title:40}")]
+    tooltip: Template,
+
+    /// Text shown when nothing is playing
+    #[structopt(long, default_value = "")]
+    stopped_text: String,
+
+    /// Keep running and print a line whenever the status changes
+    #[structopt(long)]
+    follow: bool,
+
+    /// Device id, name, type, or "active"; defaults to SPOTIFY_DEFAULT_DEVICE
+    #[structopt(short, long, alias = "device-id")]
+    device: Option<String>,
+
+    /// Click action to carry out instead of printing the status
+    #[structopt(subcommand)]
+    action: Option<Action>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Action {
+    /// Pauses if playing, resumes otherwise
+    PlayPause,
+
+    /// Skips to the next track
+    Next,
+
+    /// Skips to the previous track
+    Previous,
+
+    /// Raises the volume
+    VolumeUp {
+        #[structopt(default_value = "5")]
+        step: i32,
+    },
+
+    /// Lowers the volume
+    VolumeDown {
+        #[structopt(default_value = "5")]
+        step: i32,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::from_args();
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    if let Some(action) = arguments.action {
+        let action = match action {
+            Action::PlayPause => ClickAction::PlayPause,
+            Action::Next => ClickAction::Next,
+            Action::Previous => ClickAction::Previous,
+            Action::VolumeUp { step } => ClickAction::Volume(step),
+            Action::VolumeDown { step } => ClickAction::Volume(-step),
+        };
+        return action.perform(&config, arguments.device.as_deref()).await;
+    }
+
+    let options = StatusOptions {
+        format: arguments.format,
+        template: arguments.template,
+        tooltip: arguments.tooltip,
+        stopped_text: arguments.stopped_text,
+    };
+    if arguments.follow {
+        follow(&config, &options).await
+    } else {
+        print_status(&config, &options, arguments.device.as_deref()).await
+    }
+}
@@ -0,0 +1,12 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+
+use spotifyexp::config::SpotifyConfig;
+use spotifyexp::tui;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = Rc::new(SpotifyConfig::from_env()?);
+    tui::run(&config).await
+}
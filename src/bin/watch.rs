@@ -0,0 +1,23 @@
+use std::rc::Rc;
+
+use anyhow::Result;
+use futures::StreamExt;
+
+use spotifyexp::config::{SpotifyConfig, TokenKeeper};
+use spotifyexp::watcher::PlayerWatcher;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = Rc::new(SpotifyConfig::from_env()?);
+
+    let events = PlayerWatcher::new(&config).into_stream(TokenKeeper::new(&config));
+    futures::pin_mut!(events);
+    while let Some(event) = events.next().await {
+        match event {
+            Ok(event) => println!("{}", serde_json::to_string(&event)?),
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
+
+    Ok(())
+}
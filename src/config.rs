@@ -1,6 +1,20 @@
-use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use reqwest::Client;
 use serde_derive::Deserialize;
 
+/// Access tokens last an hour; refresh a little before that.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SpotifyConfig {
     #[serde(rename = "spotify_client_id")]
@@ -14,6 +28,9 @@ pub struct SpotifyConfig {
 
     #[serde(rename = "spotify_refresh_token")]
     pub refresh_token: String,
+
+    #[serde(rename = "spotify_default_device")]
+    pub default_device: Option<String>,
 }
 
 impl SpotifyConfig {
@@ -21,4 +38,74 @@ impl SpotifyConfig {
         envy::from_env::<Self>()
             .with_context(|| "Failed to fetch configuration")
     }
+
+    /// Exchanges the refresh token for a new access token, for processes that outlive an hour.
+    pub async fn refresh(&self) -> Result<Self> {
+        let client = Client::new();
+        let parameters = [
+            ("grant_type", "refresh_token"),
+            ("refresh_token", &self.refresh_token),
+        ];
+        let response = client.post("https://accounts.spotify.com/api/token")
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&parameters)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            bail!("Token refresh failed: {} {}", status, body)
+        }
+        let token = response.json::<TokenResponse>().await?;
+        Ok(Self {
+            client_id: self.client_id.clone(),
+            client_secret: self.client_secret.clone(),
+            access_token: token.access_token,
+            refresh_token: token.refresh_token.unwrap_or_else(|| self.refresh_token.clone()),
+            default_device: self.default_device.clone(),
+        })
+    }
+}
+
+/// Hands out a configuration whose access token is refreshed before it expires,
+/// for long-running processes.
+pub struct TokenKeeper {
+    config: RefCell<Rc<SpotifyConfig>>,
+    refreshed_at: RefCell<Option<Instant>>,
+}
+
+impl TokenKeeper {
+    pub fn new(config: &Rc<SpotifyConfig>) -> Self {
+        Self {
+            config: RefCell::new(config.clone()),
+            refreshed_at: RefCell::new(None),
+        }
+    }
+
+    /// The current configuration, refreshing the token first when it is due.
+    /// If refreshing fails, the previous token is used and the refresh is retried next time.
+    pub async fn config(&self) -> Rc<SpotifyConfig> {
+        let due = self.refreshed_at.borrow().is_none_or(|at| at.elapsed() >= TOKEN_LIFETIME);
+        if due {
+            if let Err(e) = self.refresh().await {
+                eprintln!("{:#}", e);
+            }
+        }
+        self.config.borrow().clone()
+    }
+
+    pub async fn refresh(&self) -> Result<()> {
+        let current = self.config.borrow().clone();
+        let refreshed = current.refresh().await?;
+        *self.config.borrow_mut() = Rc::new(refreshed);
+        *self.refreshed_at.borrow_mut() = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Compares a secret a client sent with the expected one, looking at every byte so that
+/// timing does not reveal how much of it matched.
+pub fn secrets_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
 }
@@ -0,0 +1,542 @@
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use tokio::time::sleep;
+
+use crate::api::{get_artists, recently_played_between};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::{Artist, PlayableItem, PlaybackState, Track};
+use crate::watcher::PlayerWatcher;
+
+/// Spotify counts a track as played after 30 seconds; so do we.
+const MINIMUM_PLAY_MS: u32 = 30_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS artists (
+    id TEXT PRIMARY KEY,
+    uri TEXT NOT NULL,
+    name TEXT NOT NULL,
+    popularity INTEGER,
+    metadata_fetched_at INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS artist_genres (
+    artist_id TEXT NOT NULL REFERENCES artists (id),
+    genre TEXT NOT NULL,
+    PRIMARY KEY (artist_id, genre)
+);
+
+CREATE TABLE IF NOT EXISTS albums (
+    id TEXT PRIMARY KEY,
+    uri TEXT NOT NULL,
+    name TEXT NOT NULL,
+    release_date TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS album_artists (
+    album_id TEXT NOT NULL REFERENCES albums (id),
+    artist_id TEXT NOT NULL REFERENCES artists (id),
+    position INTEGER NOT NULL,
+    PRIMARY KEY (album_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS tracks (
+    id TEXT PRIMARY KEY,
+    uri TEXT NOT NULL,
+    name TEXT NOT NULL,
+    album_id TEXT REFERENCES albums (id),
+    disc_number INTEGER NOT NULL,
+    track_number INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS track_artists (
+    track_id TEXT NOT NULL REFERENCES tracks (id),
+    artist_id TEXT NOT NULL REFERENCES artists (id),
+    position INTEGER NOT NULL,
+    PRIMARY KEY (track_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS plays (
+    id INTEGER PRIMARY KEY,
+    track_id TEXT NOT NULL REFERENCES tracks (id),
+    started_at INTEGER NOT NULL,
+    ms_played INTEGER,
+    source TEXT NOT NULL,
+    context_uri TEXT
+);
+
+CREATE INDEX IF NOT EXISTS plays_by_track ON plays (track_id, started_at);
+CREATE INDEX IF NOT EXISTS plays_by_time ON plays (started_at);
+
+CREATE TABLE IF NOT EXISTS resolved_names (
+    artist_name TEXT NOT NULL,
+    track_name TEXT NOT NULL,
+    track_id TEXT,
+    PRIMARY KEY (artist_name, track_name)
+);
+
+CREATE TABLE IF NOT EXISTS sync_state (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+";
+
+/// The artists of the track aliased `t`, comma separated in credit order.
+pub(crate) const TRACK_ARTISTS: &str = "(SELECT GROUP_CONCAT(name, ', ') FROM (
+    SELECT a.name FROM track_artists ta JOIN artists a ON a.id = ta.artist_id
+    WHERE ta.track_id = t.id ORDER BY ta.position))";
+
+/// The artists of the album aliased `al`, comma separated in credit order.
+pub(crate) const ALBUM_ARTISTS: &str = "(SELECT GROUP_CONCAT(name, ', ') FROM (
+    SELECT a.name FROM album_artists aa JOIN artists a ON a.id = aa.artist_id
+    WHERE aa.album_id = al.id ORDER BY aa.position))";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaySource {
+    CurrentlyPlaying,
+    RecentlyPlayed,
+    Export,
+}
+
+impl PlaySource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PlaySource::CurrentlyPlaying => "currently_playing",
+            PlaySource::RecentlyPlayed => "recently_played",
+            PlaySource::Export => "export",
+        }
+    }
+}
+
+pub struct PlayRecord<'a> {
+    pub track: &'a Track,
+    pub started_at: DateTime<Utc>,
+    pub ms_played: Option<u32>,
+    pub source: PlaySource,
+    pub context_uri: Option<&'a str>,
+}
+
+pub struct KnownPlay {
+    pub track_id: String,
+    pub started_at: DateTime<Utc>,
+    pub ms_played: Option<u32>,
+    pub context_uri: Option<String>,
+}
+
+pub struct HistoryStore {
+    connection: Connection,
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        connection.execute_batch(SCHEMA)
+            .with_context(|| "Failed to create history schema")?;
+        Ok(Self { connection })
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Records a play unless the same play is already known from another source.
+    /// Returns whether a new play was added; plays of local files, which have no id, are never added.
+    pub fn record_play(&mut self, play: &PlayRecord) -> Result<bool> {
+        let track_id = match &play.track.id {
+            Some(track_id) => track_id.clone(),
+            None => return Ok(false),
+        };
+        let transaction = self.connection.transaction()?;
+        store_track(&transaction, play.track)?;
+        let inserted = insert_play(&transaction, &KnownPlay {
+            track_id,
+            started_at: play.started_at,
+            ms_played: play.ms_played,
+            context_uri: play.context_uri.map(|uri| uri.to_owned()),
+        }, play.source)?;
+        transaction.commit()?;
+        Ok(inserted)
+    }
+
+    /// Records plays of tracks already in the store in a single transaction.
+    /// Returns how many were new.
+    pub fn record_known_plays(&mut self, plays: &[KnownPlay], source: PlaySource) -> Result<usize> {
+        let transaction = self.connection.transaction()?;
+        let mut inserted = 0;
+        for play in plays.iter() {
+            if insert_play(&transaction, play, source)? {
+                inserted += 1;
+            }
+        }
+        transaction.commit()?;
+        Ok(inserted)
+    }
+
+    pub fn store_tracks(&mut self, tracks: &[Track]) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        for track in tracks.iter() {
+            store_track(&transaction, track)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn has_track(&self, id: &str) -> Result<bool> {
+        let found = self.connection.query_row(
+            "SELECT 1 FROM tracks WHERE id = ?1",
+            params![id],
+            |_| Ok(()),
+        ).optional()?;
+        Ok(found.is_some())
+    }
+
+    /// Looks up a cached track id for an artist and track name. The inner
+    /// `None` records that an earlier lookup found nothing.
+    pub fn resolved_name(&self, artist_name: &str, track_name: &str) -> Result<Option<Option<String>>> {
+        let resolved = self.connection.query_row(
+            "SELECT track_id FROM resolved_names WHERE artist_name = ?1 AND track_name = ?2",
+            params![artist_name, track_name],
+            |row| row.get::<_, Option<String>>(0),
+        ).optional()?;
+        Ok(resolved)
+    }
+
+    pub fn store_resolved_name(&mut self, artist_name: &str, track_name: &str, track_id: Option<&str>) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO resolved_names (artist_name, track_name, track_id) VALUES (?1, ?2, ?3)",
+            params![artist_name, track_name, track_id],
+        )?;
+        Ok(())
+    }
+
+    /// Stores full artist metadata such as genres and popularity.
+    pub fn store_artist_metadata(&mut self, artists: &[Artist]) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        let now = Utc::now().timestamp_millis();
+        for artist in artists.iter() {
+            store_artist(&transaction, artist)?;
+            transaction.execute(
+                "UPDATE artists SET popularity = ?2, metadata_fetched_at = ?3 WHERE id = ?1",
+                params![artist.id, artist.popularity, now],
+            )?;
+            transaction.execute("DELETE FROM artist_genres WHERE artist_id = ?1", params![artist.id])?;
+            for genre in artist.genres.iter().flatten() {
+                transaction.execute(
+                    "INSERT INTO artist_genres (artist_id, genre) VALUES (?1, ?2)",
+                    params![artist.id, genre],
+                )?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Marks artists as fetched without storing anything, for ids that Spotify returned no artist for.
+    /// Otherwise they would be asked for again on every run.
+    pub fn mark_metadata_fetched(&mut self, ids: &[String]) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        let now = Utc::now().timestamp_millis();
+        for id in ids.iter() {
+            transaction.execute("UPDATE artists SET metadata_fetched_at = ?2 WHERE id = ?1", params![id, now])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn artists_without_metadata(&self) -> Result<Vec<String>> {
+        let mut statement = self.connection.prepare("SELECT id FROM artists WHERE metadata_fetched_at IS NULL")?;
+        let ids = statement.query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(ids)
+    }
+
+    pub fn sync_value(&self, key: &str) -> Result<Option<i64>> {
+        let value = self.connection.query_row(
+            "SELECT value FROM sync_state WHERE key = ?1",
+            params![key],
+            |row| row.get::<_, i64>(0),
+        ).optional()?;
+        Ok(value)
+    }
+
+    pub fn set_sync_value(&mut self, key: &str, value: i64) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO sync_state (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+}
+
+/// Sources disagree on timing: the player reports progress while recently
+/// played reports only when a track was played. Plays of the same track whose
+/// start times are closer than the track length minus 30 seconds are the same
+/// play, while a track repeated back to back starts one full length later.
+fn insert_play(transaction: &Transaction, play: &KnownPlay, source: PlaySource) -> Result<bool> {
+    let duration_ms = transaction.query_row(
+        "SELECT duration_ms FROM tracks WHERE id = ?1",
+        params![play.track_id],
+        |row| row.get::<_, i64>(0),
+    ).with_context(|| format!("Unknown track {}", play.track_id))?;
+
+    let started_at = play.started_at.timestamp_millis();
+    let window = (duration_ms - MINIMUM_PLAY_MS as i64).max(5_000);
+    let existing = transaction.query_row(
+        "SELECT id FROM plays
+         WHERE track_id = ?1 AND started_at BETWEEN ?2 AND ?3
+         ORDER BY ABS(started_at - ?4) LIMIT 1",
+        params![play.track_id, started_at - window, started_at + window, started_at],
+        |row| row.get::<_, i64>(0),
+    ).optional()?;
+
+    match existing {
+        Some(id) => {
+            transaction.execute(
+                "UPDATE plays SET
+                     context_uri = COALESCE(context_uri, ?2),
+                     ms_played = MAX(COALESCE(ms_played, ?3), COALESCE(?3, ms_played))
+                 WHERE id = ?1",
+                params![id, play.context_uri, play.ms_played],
+            )?;
+            Ok(false)
+        }
+        None => {
+            transaction.execute(
+                "INSERT INTO plays (track_id, started_at, ms_played, source, context_uri)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![play.track_id, started_at, play.ms_played, source.as_str(), play.context_uri],
+            )?;
+            Ok(true)
+        }
+    }
+}
+
+fn store_artist(transaction: &Transaction, artist: &Artist) -> Result<()> {
+    transaction.execute(
+        "INSERT INTO artists (id, uri, name) VALUES (?1, ?2, ?3)
+         ON CONFLICT (id) DO UPDATE SET uri = excluded.uri, name = excluded.name",
+        params![artist.id, artist.uri, artist.name],
+    )?;
+    Ok(())
+}
+
+fn store_track(transaction: &Transaction, track: &Track) -> Result<()> {
+    // Local files have no id to store them under.
+    if track.id.is_none() {
+        return Ok(());
+    }
+
+    if let Some(album) = &track.album {
+        transaction.execute(
+            "INSERT INTO albums (id, uri, name, release_date) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (id) DO UPDATE SET uri = excluded.uri, name = excluded.name, release_date = excluded.release_date",
+            params![album.id, album.uri, album.name, album.release_date],
+        )?;
+        for (position, artist) in album.artists.iter().enumerate() {
+            store_artist(transaction, artist)?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO album_artists (album_id, artist_id, position) VALUES (?1, ?2, ?3)",
+                params![album.id, artist.id, position],
+            )?;
+        }
+    }
+
+    transaction.execute(
+        "INSERT INTO tracks (id, uri, name, album_id, disc_number, track_number, duration_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT (id) DO UPDATE SET
+             uri = excluded.uri, name = excluded.name, album_id = COALESCE(excluded.album_id, album_id),
+             disc_number = excluded.disc_number, track_number = excluded.track_number, duration_ms = excluded.duration_ms",
+        params![
+            track.id, track.uri, track.name, track.album.as_ref().map(|album| &album.id),
+            track.disc_number, track.track_number, track.duration_ms,
+        ],
+    )?;
+    for (position, artist) in track.artists.iter().enumerate() {
+        store_artist(transaction, artist)?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO track_artists (track_id, artist_id, position) VALUES (?1, ?2, ?3)",
+            params![track.id, artist.id, position],
+        )?;
+    }
+    Ok(())
+}
+
+const RECENTLY_PLAYED_CURSOR: &str = "recently_played_after";
+
+pub struct HistoryRecorder {
+    tokens: TokenKeeper,
+    store: HistoryStore,
+}
+
+impl HistoryRecorder {
+    pub fn new(config: &Rc<SpotifyConfig>, store: HistoryStore) -> Self {
+        Self {
+            tokens: TokenKeeper::new(config),
+            store,
+        }
+    }
+
+    /// Records the current track once it has played long enough to count.
+    pub fn record_current(&mut self, state: &PlaybackState) -> Result<bool> {
+        let track = match &state.item {
+            Some(PlayableItem::Track(track)) => track,
+            _ => return Ok(false),
+        };
+        let progress = match state.progress_ms {
+            Some(progress) if progress >= MINIMUM_PLAY_MS || progress >= track.duration_ms => progress,
+            _ => return Ok(false),
+        };
+
+        let started_at = Utc::now() - chrono::Duration::milliseconds(progress as i64);
+        self.store.record_play(&PlayRecord {
+            track,
+            started_at,
+            ms_played: Some(progress),
+            source: PlaySource::CurrentlyPlaying,
+            context_uri: state.context.as_ref().map(|context| context.uri.as_str()),
+        })
+    }
+
+    /// Imports plays from the recently played endpoint since the previous sync.
+    pub async fn sync_recently_played(&mut self) -> Result<usize> {
+        let after = self.store.sync_value(RECENTLY_PLAYED_CURSOR)?
+            .and_then(|timestamp| Utc.timestamp_millis_opt(timestamp + 1).single());
+        let history = recently_played_between(&self.tokens.config().await, after, None).await?;
+
+        let mut inserted = 0;
+        for play in history.iter() {
+            // played_at marks the end of the play; assume the whole track was heard.
+            let started_at = play.played_at - chrono::Duration::milliseconds(play.track.duration_ms as i64);
+            let added = self.store.record_play(&PlayRecord {
+                track: &play.track,
+                started_at,
+                ms_played: None,
+                source: PlaySource::RecentlyPlayed,
+                context_uri: play.context.as_ref().map(|context| context.uri.as_str()),
+            })?;
+            if added {
+                inserted += 1;
+            }
+        }
+
+        if let Some(latest) = history.iter().map(|play| play.played_at).max() {
+            self.store.set_sync_value(RECENTLY_PLAYED_CURSOR, latest.timestamp_millis())?;
+        }
+        Ok(inserted)
+    }
+
+    pub async fn fetch_artist_metadata(&mut self) -> Result<()> {
+        let ids = self.store.artists_without_metadata()?;
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let artists = get_artists(&self.tokens.config().await, &ids).await?;
+        self.store.store_artist_metadata(&artists)?;
+
+        let missing = ids.into_iter()
+            .filter(|id| !artists.iter().any(|artist| artist.id == *id))
+            .collect::<Vec<_>>();
+        self.store.mark_metadata_fetched(&missing)
+    }
+
+    /// Records plays until the process is stopped, syncing recently played at the given interval.
+    pub async fn run(&mut self, sync_interval: Duration) -> Result<()> {
+        let mut watcher = PlayerWatcher::new(&self.tokens.config().await);
+        let mut synced_at: Option<Instant> = None;
+        loop {
+            if synced_at.is_none_or(|synced_at| synced_at.elapsed() >= sync_interval) {
+                synced_at = Some(Instant::now());
+                if let Err(e) = self.sync_recently_played().await {
+                    eprintln!("Failed to sync recently played tracks: {:#}", e);
+                }
+                if let Err(e) = self.fetch_artist_metadata().await {
+                    eprintln!("Failed to fetch artist metadata: {:#}", e);
+                }
+            }
+
+            watcher.set_config(&self.tokens.config().await);
+            match watcher.poll().await {
+                Ok(_) => {
+                    if let Some(state) = watcher.state() {
+                        self.record_current(state)?;
+                    }
+                }
+                Err(e) => eprintln!("Failed to fetch playback state: {:#}", e),
+            }
+
+            sleep(watcher.next_delay()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn store() -> HistoryStore {
+        let mut store = HistoryStore::open(Path::new(":memory:")).unwrap();
+        let track = serde_json::from_value::<Track>(json!({
+            "type": "track", "id": "t1", "linked_from": null, "href": null,
+            "album": { "id": "a1", "href": null, "name": "Album", "release_date": "2020", "uri": "spotify:album:a1", "artists": [] },
+            "artists": [{ "id": "r1", "href": null, "name": "Band", "uri": "spotify:artist:r1" }],
+            "name": "Song", "disc_number": 1, "track_number": 1, "duration_ms": 200_000, "uri": "spotify:track:t1",
+        })).unwrap();
+        store.store_tracks(&[track]).unwrap();
+        store
+    }
+
+    fn play(track_id: &str, seconds: i64, ms_played: Option<u32>) -> KnownPlay {
+        KnownPlay {
+            track_id: track_id.to_owned(),
+            started_at: Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap(),
+            ms_played,
+            context_uri: None,
+        }
+    }
+
+    fn play_count(store: &HistoryStore) -> i64 {
+        store.connection().query_row("SELECT COUNT(*) FROM plays", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn overlapping_batches_are_merged() {
+        let mut store = store();
+        // The track is 200 seconds long, so back to back repeats are separate plays.
+        let first = [play("t1", 0, None), play("t1", 200, None), play("t1", 400, None)];
+        assert_eq!(store.record_known_plays(&first, PlaySource::RecentlyPlayed).unwrap(), 3);
+        assert_eq!(play_count(&store), 3);
+
+        // The same plays seen a little off, along with one new play.
+        let second = [play("t1", 10, Some(190_000)), play("t1", 395, None), play("t1", 600, Some(200_000))];
+        assert_eq!(store.record_known_plays(&second, PlaySource::Export).unwrap(), 1);
+        assert_eq!(play_count(&store), 4);
+
+        // Merging fills in what the first source did not know.
+        let ms_played = store.connection()
+            .query_row("SELECT ms_played FROM plays ORDER BY started_at LIMIT 1", [], |row| row.get::<_, Option<u32>>(0))
+            .unwrap();
+        assert_eq!(ms_played, Some(190_000));
+
+        assert_eq!(store.record_known_plays(&second, PlaySource::Export).unwrap(), 0);
+        assert_eq!(play_count(&store), 4);
+    }
+
+    #[test]
+    fn plays_of_unknown_tracks_are_rejected() {
+        let mut store = store();
+        let error = store.record_known_plays(&[play("t1", 0, None), play("t2", 0, None)], PlaySource::Export).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown track t2");
+        // The batch is one transaction, so nothing from it was kept.
+        assert_eq!(play_count(&store), 0);
+    }
+}
@@ -0,0 +1,268 @@
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Error, Result, bail};
+use serde_derive::Deserialize;
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{sleep, timeout};
+
+use crate::watcher::PlayerEvent;
+
+/// Event names a hook can subscribe to; `*` subscribes to all of them.
+const EVENT_NAMES: [&str; 10] = [
+    "track_changed", "resumed", "paused", "stopped", "seeked",
+    "device_changed", "volume_changed", "shuffle_changed", "repeat_changed", "context_changed",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct HooksConfig {
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: usize,
+
+    #[serde(default, rename = "hook")]
+    pub hooks: Vec<Hook>,
+}
+
+/// A command to run on player events. The event is passed as JSON on stdin and
+/// flattened into `SPOTIFYEXP_*` environment variables. Commands are not run
+/// through a shell; use `command = "sh"` with `args = ["-c", "..."]` for that.
+#[derive(Debug, Deserialize)]
+pub struct Hook {
+    pub events: Vec<String>,
+    pub command: String,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Run only for the last of a burst of events arriving within this window.
+    #[serde(default)]
+    pub debounce_ms: u64,
+
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_max_concurrency() -> usize {
+    4
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+impl HooksConfig {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config = toml::from_str::<Self>(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.max_concurrency == 0 {
+            bail!("max_concurrency must be at least 1")
+        }
+        for hook in self.hooks.iter() {
+            for event in hook.events.iter() {
+                if event != "*" && !EVENT_NAMES.contains(&event.as_str()) {
+                    bail!("Unknown event \"{}\" in hook for {}; expected one of: {}", event, hook.command, EVENT_NAMES.join(", "))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Hook {
+    fn matches(&self, event: &PlayerEvent) -> bool {
+        self.events.iter().any(|name| name == "*" || name == event.name())
+    }
+}
+
+pub struct HookRunner {
+    hooks: Vec<(Arc<Hook>, Arc<AtomicU64>)>,
+    semaphore: Arc<Semaphore>,
+    failures: UnboundedSender<Error>,
+}
+
+impl HookRunner {
+    /// Hooks run in the background, so their failures are sent to `failures` rather than returned.
+    pub fn new(config: HooksConfig, failures: UnboundedSender<Error>) -> Self {
+        Self {
+            hooks: config.hooks.into_iter()
+                .map(|hook| (Arc::new(hook), Arc::new(AtomicU64::new(0))))
+                .collect(),
+            semaphore: Arc::new(Semaphore::new(config.max_concurrency)),
+            failures,
+        }
+    }
+
+    /// Starts every hook subscribed to the event in the background.
+    pub fn dispatch(&self, event: &PlayerEvent) -> Result<()> {
+        let payload = serde_json::to_value(event)?;
+        for (hook, generation) in self.hooks.iter().filter(|(hook, _)| hook.matches(event)) {
+            let hook = hook.clone();
+            let generation = generation.clone();
+            let semaphore = self.semaphore.clone();
+            let failures = self.failures.clone();
+            let payload = payload.clone();
+            let current = generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+            tokio::spawn(async move {
+                if hook.debounce_ms > 0 {
+                    sleep(Duration::from_millis(hook.debounce_ms)).await;
+                    if generation.load(Ordering::SeqCst) != current {
+                        return;
+                    }
+                }
+                let _permit = match semaphore.acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                };
+                if let Err(e) = run_hook(&hook, &payload).await {
+                    let _ = failures.send(e.context(format!("Hook {} failed", hook.command)));
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+async fn run_hook(hook: &Hook, payload: &Value) -> Result<()> {
+    let mut variables = vec![];
+    flatten("SPOTIFYEXP", payload, &mut variables);
+
+    let mut child = Command::new(&hook.command)
+        .args(&hook.args)
+        .envs(variables)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| "Failed to start command")?;
+
+    let stdin = child.stdin.take();
+    let input = payload.to_string();
+    let finished = async {
+        if let Some(mut stdin) = stdin {
+            // The command may exit without reading its input; that is not an error.
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+        child.wait().await
+    };
+
+    // A command that never reads its input would block the write, so the write counts towards the timeout too.
+    let status = timeout(Duration::from_millis(hook.timeout_ms), finished).await
+        .with_context(|| format!("Timed out after {}ms", hook.timeout_ms))??;
+    if !status.success() {
+        bail!("Exited with {}", status)
+    }
+    Ok(())
+}
+
+/// Flattens nested JSON into `PREFIX_KEY_SUBKEY=value` pairs; array elements are keyed by index.
+fn flatten(prefix: &str, value: &Value, variables: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter() {
+                flatten(&format!("{}_{}", prefix, key.to_uppercase()), value, variables);
+            }
+        }
+        Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                flatten(&format!("{}_{}", prefix, index), value, variables);
+            }
+        }
+        Value::String(s) => variables.push((prefix.to_owned(), s.clone())),
+        Value::Null => {}
+        other => variables.push((prefix.to_owned(), other.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    #[test]
+    fn nested_values_become_variables() {
+        let mut variables = vec![];
+        flatten("SPOTIFYEXP", &json!({
+            "event": "track_changed",
+            "previous_uri": null,
+            "item": {
+                "name": "Song",
+                "duration_ms": 200_000,
+                "explicit": false,
+                "artists": [{ "name": "Singer" }, { "name": "Band" }],
+            },
+        }), &mut variables);
+        variables.sort();
+
+        let expected = [
+            ("SPOTIFYEXP_EVENT", "track_changed"),
+            ("SPOTIFYEXP_ITEM_ARTISTS_0_NAME", "Singer"),
+            ("SPOTIFYEXP_ITEM_ARTISTS_1_NAME", "Band"),
+            ("SPOTIFYEXP_ITEM_DURATION_MS", "200000"),
+            ("SPOTIFYEXP_ITEM_EXPLICIT", "false"),
+            ("SPOTIFYEXP_ITEM_NAME", "Song"),
+        ];
+        let expected = expected.iter()
+            .map(|(name, value)| (String::from(*name), String::from(*value)))
+            .collect::<Vec<_>>();
+        assert_eq!(variables, expected);
+    }
+
+    /// A runner with one hook on seeks that appends the position seeked to to a file.
+    fn seek_logger(debounce_ms: u64, log: &Path) -> HookRunner {
+        let hook = Hook {
+            events: vec![String::from("seeked")],
+            command: String::from("sh"),
+            args: ["-c", "echo \"$SPOTIFYEXP_TO_MS\" >> \"$0\""].iter()
+                .map(|arg| String::from(*arg))
+                .chain(std::iter::once(log.display().to_string()))
+                .collect(),
+            debounce_ms,
+            timeout_ms: default_timeout_ms(),
+        };
+        let (failures, _) = unbounded_channel();
+        HookRunner::new(HooksConfig { max_concurrency: 1, hooks: vec![hook] }, failures)
+    }
+
+    async fn seek_log(debounce_ms: u64, name: &str) -> String {
+        let log = std::env::temp_dir().join(format!("spotifyexp-hooks-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&log);
+        let runner = seek_logger(debounce_ms, &log);
+        for to_ms in [1_000, 2_000, 3_000].iter() {
+            runner.dispatch(&PlayerEvent::Seeked { from_ms: 0, to_ms: *to_ms }).unwrap();
+        }
+        sleep(Duration::from_millis(debounce_ms + 500)).await;
+        let content = fs::read_to_string(&log).unwrap_or_default();
+        let _ = fs::remove_file(&log);
+        content
+    }
+
+    #[tokio::test]
+    async fn bursts_are_debounced_to_the_last_event() {
+        assert_eq!(seek_log(100, "debounced").await, "3000\n");
+    }
+
+    #[tokio::test]
+    async fn every_event_runs_without_debounce() {
+        let log = seek_log(0, "immediate").await;
+        let mut lines = log.lines().collect::<Vec<_>>();
+        lines.sort_unstable();
+        assert_eq!(lines, ["1000", "2000", "3000"]);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use serde_derive::Deserialize;
+use tokio::time::sleep;
+
+use crate::api::{get_tracks, RateLimited, SearchTracks};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::history::{HistoryStore, KnownPlay, PlaySource};
+use crate::search_query::SearchQuery;
+
+/// Plays shorter than this are skips and are not imported.
+const MINIMUM_PLAY_MS: u32 = 30_000;
+
+/// How often one name search is retried when Spotify asks to slow down.
+const RATE_LIMIT_RETRIES: u32 = 5;
+
+/// One play from either format of the privacy data export.
+#[derive(Debug)]
+pub struct ExportEntry {
+    pub ended_at: DateTime<Utc>,
+    pub ms_played: u32,
+    pub artist_name: Option<String>,
+    pub track_name: Option<String>,
+    pub track_uri: Option<String>,
+}
+
+impl ExportEntry {
+    /// Whether it was played long enough to count; shorter plays are skips.
+    fn is_play(&self) -> bool {
+        self.ms_played >= MINIMUM_PLAY_MS
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawEntry {
+    /// `endsong_*.json` and `Streaming_History_Audio_*.json` from the extended history.
+    Extended {
+        ts: DateTime<Utc>,
+        ms_played: u32,
+        master_metadata_track_name: Option<String>,
+        master_metadata_album_artist_name: Option<String>,
+        spotify_track_uri: Option<String>,
+    },
+    /// `StreamingHistory*.json` from the account data, with times in UTC.
+    Basic {
+        #[serde(rename = "endTime")]
+        end_time: String,
+        #[serde(rename = "artistName")]
+        artist_name: String,
+        #[serde(rename = "trackName")]
+        track_name: String,
+        #[serde(rename = "msPlayed")]
+        ms_played: u32,
+    },
+}
+
+impl RawEntry {
+    fn into_entry(self) -> Result<ExportEntry> {
+        match self {
+            RawEntry::Extended { ts, ms_played, master_metadata_track_name, master_metadata_album_artist_name, spotify_track_uri } => {
+                Ok(ExportEntry {
+                    ended_at: ts,
+                    ms_played,
+                    artist_name: master_metadata_album_artist_name,
+                    track_name: master_metadata_track_name,
+                    track_uri: spotify_track_uri,
+                })
+            }
+            RawEntry::Basic { end_time, artist_name, track_name, ms_played } => {
+                let ended_at = NaiveDateTime::parse_from_str(&end_time, "%Y-%m-%d %H:%M")
+                    .with_context(|| format!("Invalid endTime: {}", end_time))?;
+                Ok(ExportEntry {
+                    ended_at: Utc.from_utc_datetime(&ended_at),
+                    ms_played,
+                    artist_name: Some(artist_name),
+                    track_name: Some(track_name),
+                    track_uri: None,
+                })
+            }
+        }
+    }
+}
+
+pub fn read_export_file(path: &Path) -> Result<Vec<ExportEntry>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let raw = serde_json::from_str::<Vec<RawEntry>>(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    raw.into_iter()
+        .map(|entry| entry.into_entry())
+        .collect()
+}
+
+/// Lists streaming history files in an unpacked export directory.
+pub fn find_export_files(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(directory).with_context(|| format!("Failed to read {}", directory.display()))? {
+        let path = entry?.path();
+        let name = path.file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let is_history = name.starts_with("StreamingHistory")
+            || name.starts_with("endsong_")
+            || name.starts_with("Streaming_History_Audio_");
+        if is_history && name.ends_with(".json") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub entries: usize,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub skipped: usize,
+    pub unresolved: usize,
+}
+
+pub struct Importer<'a> {
+    tokens: TokenKeeper,
+    store: &'a mut HistoryStore,
+}
+
+impl<'a> Importer<'a> {
+    pub fn new(config: &Rc<SpotifyConfig>, store: &'a mut HistoryStore) -> Self {
+        Self {
+            tokens: TokenKeeper::new(config),
+            store,
+        }
+    }
+
+    pub async fn import(&mut self, entries: Vec<ExportEntry>) -> Result<ImportSummary> {
+        let mut summary = ImportSummary {
+            entries: entries.len(),
+            ..Default::default()
+        };
+
+        let mut resolved = vec![];
+        let mut names = HashMap::new();
+        for entry in entries.into_iter() {
+            if !entry.is_play() {
+                summary.skipped += 1;
+                continue;
+            }
+            let track_id = match (&entry.track_uri, &entry.artist_name, &entry.track_name) {
+                (Some(uri), _, _) => uri.strip_prefix("spotify:track:").map(|id| id.to_owned()),
+                (None, Some(artist_name), Some(track_name)) => {
+                    let key = (artist_name.clone(), track_name.clone());
+                    if !names.contains_key(&key) {
+                        let track_id = self.resolve_name(artist_name, track_name).await?;
+                        names.insert(key.clone(), track_id);
+                    }
+                    names[&key].clone()
+                }
+                // Podcast episodes have neither a track URI nor track metadata.
+                _ => None,
+            };
+            match track_id {
+                Some(track_id) => resolved.push((track_id, entry)),
+                None => summary.unresolved += 1,
+            }
+        }
+
+        let mut missing = vec![];
+        let mut seen = HashSet::new();
+        for (track_id, _) in resolved.iter() {
+            if seen.insert(track_id.clone()) && !self.store.has_track(track_id)? {
+                missing.push(track_id.clone());
+            }
+        }
+        let tracks = get_tracks(&self.tokens.config().await, &missing).await?;
+        self.store.store_tracks(&tracks)?;
+
+        let mut plays = vec![];
+        for (track_id, entry) in resolved.into_iter() {
+            if !self.store.has_track(&track_id)? {
+                summary.unresolved += 1;
+                continue;
+            }
+            plays.push(KnownPlay {
+                track_id,
+                started_at: entry.ended_at - Duration::milliseconds(entry.ms_played as i64),
+                ms_played: Some(entry.ms_played),
+                context_uri: None,
+            });
+        }
+        summary.imported = self.store.record_known_plays(&plays, PlaySource::Export)?;
+        summary.duplicates = plays.len() - summary.imported;
+        Ok(summary)
+    }
+
+    /// Finds a track id by artist and track name, consulting the store's cache first.
+    /// Only a track with exactly that name and artist counts; anything else leaves the entry unresolved.
+    async fn resolve_name(&mut self, artist_name: &str, track_name: &str) -> Result<Option<String>> {
+        if let Some(track_id) = self.store.resolved_name(artist_name, track_name)? {
+            return Ok(track_id);
+        }
+
+        let query = SearchQuery::new()
+            .with_artist(artist_name)
+            .with_track(track_name)
+            .to_string();
+        let mut retries = 0;
+        let response = loop {
+            match SearchTracks::new(&self.tokens.config().await, &query).execute().await {
+                Ok(response) => break response,
+                Err(e) => match e.downcast_ref::<RateLimited>() {
+                    Some(limited) if retries < RATE_LIMIT_RETRIES => {
+                        retries += 1;
+                        sleep(limited.retry_after).await;
+                    }
+                    _ => return Err(e),
+                },
+            }
+        };
+        let track = response.tracks.items.iter()
+            .find(|track| {
+                track.name.eq_ignore_ascii_case(track_name)
+                    && track.artists.iter().any(|artist| artist.name.eq_ignore_ascii_case(artist_name))
+            });
+        if let Some(track) = track {
+            self.store.store_tracks(std::slice::from_ref(track))?;
+        }
+
+        let track_id = track.and_then(|track| track.id.clone());
+        self.store.store_resolved_name(artist_name, track_name, track_id.as_deref())?;
+        Ok(track_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(value: serde_json::Value) -> ExportEntry {
+        serde_json::from_value::<RawEntry>(value).unwrap().into_entry().unwrap()
+    }
+
+    #[test]
+    fn extended_entries() {
+        let entry = parse(json!({
+            "ts": "2023-04-05T06:07:08Z",
+            "username": "someone",
+            "platform": "Android",
+            "ms_played": 215_000,
+            "master_metadata_track_name": "Song",
+            "master_metadata_album_artist_name": "Band",
+            "master_metadata_album_album_name": "Album",
+            "spotify_track_uri": "spotify:track:t1",
+            "episode_name": null,
+            "skipped": null,
+        }));
+        assert_eq!(entry.ended_at, Utc.with_ymd_and_hms(2023, 4, 5, 6, 7, 8).unwrap());
+        assert_eq!(entry.ms_played, 215_000);
+        assert_eq!(entry.artist_name.as_deref(), Some("Band"));
+        assert_eq!(entry.track_name.as_deref(), Some("Song"));
+        assert_eq!(entry.track_uri.as_deref(), Some("spotify:track:t1"));
+    }
+
+    #[test]
+    fn extended_entries_without_track_metadata() {
+        // Podcast episodes leave the track fields null.
+        let entry = parse(json!({
+            "ts": "2023-04-05T06:07:08Z",
+            "ms_played": 1_200_000,
+            "master_metadata_track_name": null,
+            "master_metadata_album_artist_name": null,
+            "spotify_track_uri": null,
+            "episode_name": "Episode",
+        }));
+        assert!(entry.artist_name.is_none());
+        assert!(entry.track_name.is_none());
+        assert!(entry.track_uri.is_none());
+    }
+
+    #[test]
+    fn basic_entries() {
+        let entry = parse(json!({
+            "endTime": "2023-04-05 06:07",
+            "artistName": "Band",
+            "trackName": "Song",
+            "msPlayed": 215_000,
+        }));
+        assert_eq!(entry.ended_at, Utc.with_ymd_and_hms(2023, 4, 5, 6, 7, 0).unwrap());
+        assert_eq!(entry.artist_name.as_deref(), Some("Band"));
+        assert_eq!(entry.track_name.as_deref(), Some("Song"));
+        assert!(entry.track_uri.is_none());
+
+        let invalid = serde_json::from_value::<RawEntry>(json!({
+            "endTime": "yesterday", "artistName": "Band", "trackName": "Song", "msPlayed": 1,
+        })).unwrap();
+        assert_eq!(invalid.into_entry().unwrap_err().to_string(), "Invalid endTime: yesterday");
+    }
+
+    #[test]
+    fn short_plays_are_skips() {
+        let entry = |ms_played: u32| parse(json!({
+            "endTime": "2023-04-05 06:07", "artistName": "Band", "trackName": "Song", "msPlayed": ms_played,
+        }));
+        assert!(!entry(0).is_play());
+        assert!(!entry(MINIMUM_PLAY_MS - 1).is_play());
+        assert!(entry(MINIMUM_PLAY_MS).is_play());
+    }
+}
@@ -1,3 +1,24 @@
+pub mod alarm;
 pub mod api;
 pub mod config;
+pub mod history;
+pub mod hooks;
+pub mod import;
+pub mod mpd;
+#[cfg(feature = "mpris")]
+pub mod mpris;
+pub mod mqtt;
 pub mod objects;
+pub mod overlay;
+pub mod scrobble;
+pub mod search_query;
+pub mod server;
+pub mod shell;
+pub mod sleep_timer;
+pub mod snapshot;
+pub mod stats;
+pub mod status;
+pub mod template;
+pub mod timestamp;
+pub mod tui;
+pub mod watcher;
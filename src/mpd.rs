@@ -0,0 +1,677 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Write};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use anyhow::{Context, Result, anyhow, bail};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::LocalSet;
+
+use crate::api::{enqueue_tracks, get_playback_state, get_queue, pause, resolve_device_id, seek, set_repeat, set_shuffle, set_volume, skip_to_next, skip_to_previous, start_playback, start_playing, PlaybackRequest, SearchTracks};
+use crate::config::{secrets_match, SpotifyConfig, TokenKeeper};
+use crate::objects::{PlayableItem, Track};
+use crate::search_query::SearchQuery;
+use crate::watcher::{PlayerEvent, PlayerWatcher};
+
+/// The protocol version announced to clients; the subset spoken here is much older.
+const PROTOCOL_VERSION: &str = "0.23.0";
+
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_PASSWORD: u32 = 3;
+const ACK_ERROR_PERMISSION: u32 = 4;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+const ACK_ERROR_SYSTEM: u32 = 52;
+
+const TAG_TYPES: [&str; 4] = ["Artist", "Album", "AlbumArtist", "Title"];
+
+pub struct MpdOptions {
+    pub bind: SocketAddr,
+    /// Device selector as accepted by `DeviceResolver`.
+    pub device: Option<String>,
+    /// Clients must send this with the `password` command before any other when set.
+    pub password: Option<String>,
+}
+
+struct BridgeState {
+    tokens: TokenKeeper,
+    device: Option<String>,
+    password: Option<String>,
+}
+
+/// Accepts MPD clients until the process is stopped.
+/// Refuses to listen beyond the loopback interface without a password.
+pub async fn serve(config: &Rc<SpotifyConfig>, options: MpdOptions) -> Result<()> {
+    if !options.bind.ip().is_loopback() && options.password.is_none() {
+        bail!("Refusing to listen on {} without a password", options.bind)
+    }
+
+    let listener = TcpListener::bind(options.bind).await
+        .with_context(|| format!("Failed to listen on {}", options.bind))?;
+    println!("Listening on {}", options.bind);
+    let state = Rc::new(BridgeState {
+        tokens: TokenKeeper::new(config),
+        device: options.device,
+        password: options.password,
+    });
+
+    let local = LocalSet::new();
+    local.run_until(async move {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let state = state.clone();
+            tokio::task::spawn_local(async move {
+                if let Err(e) = handle_connection(&state, stream).await {
+                    eprintln!("{}: {:#}", peer, e);
+                }
+            });
+        }
+    }).await
+}
+
+/// An MPD protocol error, reported to the client as an `ACK` line.
+#[derive(Debug)]
+struct MpdError {
+    code: u32,
+    message: String,
+}
+
+impl MpdError {
+    fn error(code: u32, message: &str) -> anyhow::Error {
+        Self { code, message: message.to_owned() }.into()
+    }
+}
+
+impl fmt::Display for MpdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for MpdError {}
+
+fn ack(error: anyhow::Error, index: usize, command: &str) -> String {
+    let (code, message) = match error.downcast::<MpdError>() {
+        Ok(error) => (error.code, error.message),
+        Err(error) => (ACK_ERROR_SYSTEM, format!("{:#}", error)),
+    };
+    format!("ACK [{}@{}] {{{}}} {}\n", code, index, command, message.replace('\n', " "))
+}
+
+async fn handle_connection(state: &BridgeState, stream: TcpStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(format!("OK MPD {}\n", PROTOCOL_VERSION).as_bytes()).await?;
+
+    let mut authorized = state.password.is_none();
+    let mut command_list: Option<(bool, Vec<String>)> = None;
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim().to_owned();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some((list_ok, commands)) = &mut command_list {
+            if line != "command_list_end" {
+                commands.push(line);
+                continue;
+            }
+            let response = run_command_list(state, commands, *list_ok, &mut authorized).await;
+            command_list = None;
+            writer.write_all(response.as_bytes()).await?;
+            continue;
+        }
+
+        let response = match line.as_str() {
+            "command_list_begin" | "command_list_ok_begin" => {
+                command_list = Some((line == "command_list_ok_begin", vec![]));
+                continue;
+            }
+            "close" => return Ok(()),
+            // Only meaningful while idle, where it is handled.
+            "noidle" => continue,
+            _ if !authorized && (line == "idle" || line.starts_with("idle ")) => ack(no_permission("idle"), 0, "idle"),
+            _ if line == "idle" || line.starts_with("idle ") => {
+                let subsystems = tokenize(&line)?.into_iter().skip(1).collect::<Vec<_>>();
+                idle(state, &subsystems, &mut lines).await
+            }
+            _ => run_command_list(state, &[line], false, &mut authorized).await,
+        };
+        writer.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn run_command_list(state: &BridgeState, commands: &[String], list_ok: bool, authorized: &mut bool) -> String {
+    let mut response = String::new();
+    for (index, line) in commands.iter().enumerate() {
+        let name = line.split_whitespace().next().unwrap_or("").to_owned();
+        let result = run_command(state, line, authorized).await;
+        match result {
+            Ok(output) => {
+                response.push_str(&output);
+                if list_ok {
+                    response.push_str("list_OK\n");
+                }
+            }
+            Err(e) => {
+                response.push_str(&ack(e, index, &name));
+                return response;
+            }
+        }
+    }
+    response.push_str("OK\n");
+    response
+}
+
+async fn run_command(state: &BridgeState, line: &str, authorized: &mut bool) -> Result<String> {
+    let tokens = tokenize(line)?;
+    let command = argument(&tokens, 0)?;
+    if command == "password" {
+        check_password(state.password.as_deref(), argument(&tokens, 1)?, authorized)?;
+        return Ok(String::new());
+    }
+    // Clients keep connections alive with `ping` before they authenticate.
+    if !*authorized && command != "ping" {
+        return Err(no_permission(command));
+    }
+    execute(state, &tokens).await
+}
+
+fn check_password(expected: Option<&str>, given: &str, authorized: &mut bool) -> Result<()> {
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    if !secrets_match(given, expected) {
+        return Err(MpdError::error(ACK_ERROR_PASSWORD, "incorrect password"));
+    }
+    *authorized = true;
+    Ok(())
+}
+
+fn no_permission(command: &str) -> anyhow::Error {
+    MpdError::error(ACK_ERROR_PERMISSION, &format!("you don't have permission for \"{}\"", command))
+}
+
+/// Splits a command line into words, honouring double quotes and backslash escapes.
+fn tokenize(line: &str) -> Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let first = match chars.next() {
+            Some(c) => c,
+            None => return Ok(tokens),
+        };
+
+        let mut token = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('\\') => token.extend(chars.next()),
+                    Some('"') => break,
+                    Some(c) => token.push(c),
+                    None => return Err(MpdError::error(ACK_ERROR_ARG, "Missing closing '\"'")),
+                }
+            }
+        } else {
+            token.push(first);
+            while let Some(c) = chars.peek().copied().filter(|c| !c.is_whitespace()) {
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+}
+
+/// Waits for a player change, or for the client to send `noidle`.
+async fn idle<R>(state: &BridgeState, subsystems: &[String], lines: &mut tokio::io::Lines<R>) -> String
+where R: tokio::io::AsyncBufRead + Unpin {
+    tokio::select! {
+        changed = wait_for_change(state, subsystems) => changed,
+        // Any line ends the idle; clients only send noidle here.
+        _ = lines.next_line() => String::from("OK\n"),
+    }
+}
+
+/// Polls until the player changes in one of `subsystems`, or in any of them when none are given.
+/// Failed polls are retried, since idle clients wait indefinitely.
+async fn wait_for_change(state: &BridgeState, subsystems: &[String]) -> String {
+    let config = state.tokens.config().await;
+    let mut watcher = PlayerWatcher::new(&config);
+    if let Err(e) = watcher.poll().await {
+        eprintln!("{:#}", e);
+    }
+
+    loop {
+        let event = match watcher.next_event(&state.tokens).await {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                continue;
+            }
+        };
+        let changed = event_subsystems(&event).iter()
+            .filter(|subsystem| subsystems.is_empty() || subsystems.iter().any(|wanted| wanted == *subsystem))
+            .map(|subsystem| format!("changed: {}\n", subsystem))
+            .collect::<String>();
+        if !changed.is_empty() {
+            return changed + "OK\n";
+        }
+    }
+}
+
+fn event_subsystems(event: &PlayerEvent) -> &'static [&'static str] {
+    match event {
+        PlayerEvent::TrackChanged { .. } | PlayerEvent::ContextChanged { .. } => &["player", "playlist"],
+        PlayerEvent::VolumeChanged { .. } => &["mixer"],
+        PlayerEvent::ShuffleChanged { .. } | PlayerEvent::RepeatChanged { .. } => &["options"],
+        PlayerEvent::DeviceChanged { .. } => &["player", "output"],
+        _ => &["player"],
+    }
+}
+
+fn argument(tokens: &[String], index: usize) -> Result<&str> {
+    tokens.get(index)
+        .map(|token| token.as_str())
+        .ok_or_else(|| MpdError::error(ACK_ERROR_ARG, "too few arguments"))
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T> {
+    value.parse::<T>()
+        .map_err(|_| MpdError::error(ACK_ERROR_ARG, &format!("Number expected: {}", value)))
+}
+
+fn flag(value: &str) -> Result<bool> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(MpdError::error(ACK_ERROR_ARG, &format!("Boolean (0/1) expected: {}", value))),
+    }
+}
+
+async fn execute(state: &BridgeState, tokens: &[String]) -> Result<String> {
+    let config = state.tokens.config().await;
+    let command = argument(tokens, 0)?;
+    let device_id = || resolve_device_id(&config, state.device.as_deref());
+
+    let mut out = String::new();
+    match command {
+        "ping" | "clearerror" | "subscribe" | "unsubscribe" | "consume" | "single" | "crossfade" => {}
+        "status" => {
+            let playback = get_playback_state(&config).await?;
+            let queue = get_queue(&config).await?;
+            let length = queue.currently_playing.iter().count() + queue.queue.len();
+            let _ = writeln!(out, "repeat: {}", playback.as_ref().is_some_and(|state| state.repeat_state != "off") as u8);
+            let _ = writeln!(out, "random: {}", playback.as_ref().is_some_and(|state| state.shuffle_state) as u8);
+            let _ = writeln!(out, "single: {}", playback.as_ref().is_some_and(|state| state.repeat_state == "track") as u8);
+            let _ = writeln!(out, "consume: 0");
+            let _ = writeln!(out, "playlist: {}", playlist_version(&queue.currently_playing, &queue.queue));
+            let _ = writeln!(out, "playlistlength: {}", length);
+            match &playback {
+                Some(playback) => {
+                    if let Some(volume) = playback.device.volume_percent {
+                        let _ = writeln!(out, "volume: {}", volume);
+                    }
+                    let _ = writeln!(out, "state: {}", if playback.is_playing { "play" } else { "pause" });
+                    if let Some(item) = &playback.item {
+                        let elapsed = playback.progress_ms.unwrap_or(0) as f64 / 1000.0;
+                        let duration = item.duration_ms() as f64 / 1000.0;
+                        let _ = writeln!(out, "song: 0\nsongid: 1");
+                        let _ = writeln!(out, "time: {}:{}", elapsed as u32, duration as u32);
+                        let _ = writeln!(out, "elapsed: {:.3}\nduration: {:.3}", elapsed, duration);
+                    }
+                    if length > 1 {
+                        let _ = writeln!(out, "nextsong: 1\nnextsongid: 2");
+                    }
+                }
+                None => {
+                    let _ = writeln!(out, "state: stop");
+                }
+            }
+        }
+        "currentsong" => {
+            let playback = get_playback_state(&config).await?;
+            if let Some(item) = playback.and_then(|playback| playback.item) {
+                write_item(&mut out, &item, Some(0));
+            }
+        }
+        "playlistinfo" | "playlistid" | "plchanges" => {
+            let queue = get_queue(&config).await?;
+            let items = queue.currently_playing.iter().chain(queue.queue.iter());
+            for (position, item) in items.enumerate() {
+                write_item(&mut out, item, Some(position));
+            }
+        }
+        "play" | "playid" => {
+            let device_id = device_id().await?;
+            match tokens.get(1) {
+                Some(value) => {
+                    let position = song_position(value, command == "playid")?;
+                    play_position(&config, &device_id, position, None).await?;
+                }
+                None => start_playing(&config, &device_id).await?,
+            }
+        }
+        "pause" => {
+            let device_id = device_id().await?;
+            let should_pause = match tokens.get(1) {
+                Some(value) => flag(value)?,
+                None => get_playback_state(&config).await?.is_some_and(|state| state.is_playing),
+            };
+            if should_pause {
+                pause(&config, &device_id).await?;
+            } else {
+                start_playing(&config, &device_id).await?;
+            }
+        }
+        "stop" => pause(&config, &device_id().await?).await?,
+        "next" => skip_to_next(&config, &device_id().await?).await?,
+        "previous" => skip_to_previous(&config, &device_id().await?).await?,
+        "setvol" => {
+            let volume = number::<u32>(argument(tokens, 1)?)?;
+            set_volume(&config, &device_id().await?, volume.min(100)).await?;
+        }
+        "seekcur" => {
+            let value = argument(tokens, 1)?;
+            let seconds = number::<f64>(value.trim_start_matches(['+', '-']))?;
+            let target = if value.starts_with('+') || value.starts_with('-') {
+                let playback = get_playback_state(&config).await?
+                    .ok_or_else(|| anyhow!("Nothing is playing"))?;
+                let current = playback.progress_ms.unwrap_or(0) as f64 / 1000.0;
+                if value.starts_with('-') { current - seconds } else { current + seconds }
+            } else {
+                seconds
+            };
+            seek(&config, &device_id().await?, (target.max(0.0) * 1000.0) as u32).await?;
+        }
+        "seek" | "seekid" => {
+            let position = song_position(argument(tokens, 1)?, command == "seekid")?;
+            let position_ms = (number::<f64>(argument(tokens, 2)?)?.max(0.0) * 1000.0) as u32;
+            let device_id = device_id().await?;
+            if position == 0 {
+                seek(&config, &device_id, position_ms).await?;
+            } else {
+                play_position(&config, &device_id, position, Some(position_ms)).await?;
+            }
+        }
+        "random" => set_shuffle(&config, &device_id().await?, flag(argument(tokens, 1)?)?).await?,
+        "repeat" => {
+            let state = if flag(argument(tokens, 1)?)? { "context" } else { "off" };
+            set_repeat(&config, &device_id().await?, state).await?;
+        }
+        "add" | "addid" => {
+            let uri = argument(tokens, 1)?;
+            if !uri.starts_with("spotify:") {
+                return Err(MpdError::error(ACK_ERROR_NO_EXIST, "Only spotify: URIs can be added"));
+            }
+            let report = enqueue_tracks(&config, &device_id().await?, vec![uri.to_owned()]).await?;
+            if command == "addid" && !report.queued.is_empty() {
+                let queue = get_queue(&config).await?;
+                let _ = writeln!(out, "Id: {}", queue.currently_playing.iter().count() + queue.queue.len());
+            }
+        }
+        "search" | "find" | "searchadd" | "findadd" => {
+            let query = search_query(&tokens[1..])?;
+            let response = SearchTracks::new(&config, &query).execute().await?;
+            if command.ends_with("add") {
+                let uris = response.tracks.items.iter().map(|track| track.uri.clone()).collect();
+                enqueue_tracks(&config, &device_id().await?, uris).await?;
+            } else {
+                for track in response.tracks.items.iter() {
+                    write_track(&mut out, track);
+                }
+            }
+        }
+        "stats" => {
+            let _ = writeln!(out, "uptime: 0\nplaytime: 0\nartists: 0\nalbums: 0\nsongs: 0\ndb_playtime: 0\ndb_update: 0");
+        }
+        "outputs" => {
+            let playback = get_playback_state(&config).await?;
+            let name = playback.map_or(String::from("Spotify"), |playback| playback.device.name);
+            let _ = writeln!(out, "outputid: 0\noutputname: {}\nplugin: spotify\noutputenabled: 1", name);
+        }
+        "tagtypes" => {
+            for tag in TAG_TYPES.iter() {
+                let _ = writeln!(out, "tagtype: {}", tag);
+            }
+        }
+        "urlhandlers" => {
+            let _ = writeln!(out, "handler: spotify:");
+        }
+        "replay_gain_status" => {
+            let _ = writeln!(out, "replay_gain_mode: off");
+        }
+        "commands" => {
+            for name in COMMANDS.iter() {
+                let _ = writeln!(out, "command: {}", name);
+            }
+        }
+        "notcommands" => {}
+        // There is no local library or stored playlists to browse.
+        "lsinfo" | "listplaylists" | "list" | "listall" | "listallinfo" | "decoders" | "channels" | "readmessages" => {}
+        _ => return Err(MpdError::error(ACK_ERROR_UNKNOWN, &format!("unknown command \"{}\"", command))),
+    }
+    Ok(out)
+}
+
+/// Converts a song argument to a playlist position; song ids are positions plus one.
+fn song_position(value: &str, by_id: bool) -> Result<usize> {
+    let song = number::<usize>(value)?;
+    if !by_id {
+        return Ok(song);
+    }
+    song.checked_sub(1)
+        .ok_or_else(|| MpdError::error(ACK_ERROR_NO_EXIST, "No such song"))
+}
+
+/// Plays the visible playlist from `position` onwards, as Spotify cannot jump ahead within its queue.
+async fn play_position(config: &Rc<SpotifyConfig>, device_id: &str, position: usize, position_ms: Option<u32>) -> Result<()> {
+    let queue = get_queue(config).await?;
+    let uris = queue.currently_playing.iter().chain(queue.queue.iter())
+        .skip(position)
+        .map(|item| item.uri().to_owned())
+        .collect::<Vec<_>>();
+    if uris.is_empty() {
+        return Err(MpdError::error(ACK_ERROR_ARG, "Bad song index"));
+    }
+    let request = PlaybackRequest::uris(uris);
+    let request = match position_ms {
+        Some(position_ms) => request.with_position_ms(position_ms),
+        None => request,
+    };
+    start_playback(config, device_id, request).await
+}
+
+const COMMANDS: [&str; 38] = [
+    "add", "addid", "channels", "clearerror", "close", "commands", "consume", "crossfade",
+    "currentsong", "decoders", "find", "findadd", "idle", "list", "listplaylists", "lsinfo",
+    "next", "noidle", "notcommands", "outputs", "password", "pause", "ping", "play", "playid",
+    "playlistid", "playlistinfo", "plchanges", "previous", "random", "repeat", "search",
+    "searchadd", "seek", "seekcur", "setvol", "status", "stop",
+];
+
+/// Builds a Spotify search query from `TAG VALUE` pairs or a simple `(TAG == 'VALUE')` filter.
+fn search_query(arguments: &[String]) -> Result<String> {
+    let pairs = match arguments {
+        [filter] if filter.starts_with('(') => parse_filter(filter)?,
+        _ => arguments.chunks(2)
+            .map(|pair| match pair {
+                [tag, value] => Ok((tag.to_lowercase(), value.clone())),
+                _ => Err(MpdError::error(ACK_ERROR_ARG, "Tag and value expected")),
+            })
+            .collect::<Result<Vec<_>>>()?,
+    };
+    let mut query = SearchQuery::new();
+    for (tag, value) in pairs.iter() {
+        query = match tag.as_str() {
+            "artist" | "albumartist" => query.with_artist(value),
+            "album" => query.with_album(value),
+            "title" => query.with_track(value),
+            "any" | "file" => query.with_text(value),
+            _ => return Err(MpdError::error(ACK_ERROR_ARG, &format!("Unsupported tag: {}", tag))),
+        };
+    }
+    if query.is_empty() {
+        return Err(MpdError::error(ACK_ERROR_ARG, "too few arguments"));
+    }
+    Ok(query.to_string())
+}
+
+/// Parses filters such as `((artist == 'Foo') AND (album contains 'Bar'))`.
+fn parse_filter(filter: &str) -> Result<Vec<(String, String)>> {
+    let mut pairs = vec![];
+    for expression in filter.split(" AND ") {
+        let expression = expression.trim().trim_start_matches('(').trim_end_matches(')');
+        let (tag, value) = [" == ", " contains ", " =~ "].iter()
+            .find_map(|operator| expression.split_once(operator))
+            .ok_or_else(|| MpdError::error(ACK_ERROR_ARG, &format!("Unsupported filter: {}", filter)))?;
+        let value = value.trim().trim_matches(|c| c == '\'' || c == '"').replace("\\'", "'");
+        pairs.push((tag.trim().to_lowercase(), value));
+    }
+    Ok(pairs)
+}
+
+/// A version number that changes whenever the visible playlist does.
+fn playlist_version(current: &Option<PlayableItem>, queue: &[PlayableItem]) -> u32 {
+    let mut hasher = DefaultHasher::new();
+    for item in current.iter().chain(queue.iter()) {
+        item.uri().hash(&mut hasher);
+    }
+    (hasher.finish() as u32) & 0x7fff_ffff
+}
+
+fn write_item(out: &mut String, item: &PlayableItem, position: Option<usize>) {
+    match item {
+        PlayableItem::Track(track) => write_track(out, track),
+        PlayableItem::Episode(episode) => {
+            let _ = writeln!(out, "file: {}\nTitle: {}", episode.uri, episode.name);
+            if let Some(show) = &episode.show {
+                let _ = writeln!(out, "Album: {}", show.name);
+            }
+            write_duration(out, episode.duration_ms);
+        }
+    }
+    if let Some(position) = position {
+        let _ = writeln!(out, "Pos: {}\nId: {}", position, position + 1);
+    }
+}
+
+fn write_track(out: &mut String, track: &Track) {
+    let _ = writeln!(out, "file: {}\nTitle: {}\nArtist: {}", track.uri, track.name, track.artist_names());
+    if let Some(album) = &track.album {
+        let _ = writeln!(out, "Album: {}\nAlbumArtist: {}", album.name, album.artist_names());
+    }
+    write_duration(out, track.duration_ms);
+}
+
+fn write_duration(out: &mut String, duration_ms: u32) {
+    let _ = writeln!(out, "Time: {}\nduration: {:.3}", duration_ms / 1000, duration_ms as f64 / 1000.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn track() -> PlayableItem {
+        serde_json::from_value(json!({
+            "type": "track", "id": "t1", "linked_from": null, "href": null,
+            "album": {
+                "id": "a1", "href": null, "name": "Album", "release_date": "2020", "uri": "spotify:album:a1",
+                "artists": [{ "id": "r2", "href": null, "name": "Band", "uri": "spotify:artist:r2" }],
+            },
+            "artists": [
+                { "id": "r1", "href": null, "name": "Singer", "uri": "spotify:artist:r1" },
+                { "id": "r2", "href": null, "name": "Band", "uri": "spotify:artist:r2" },
+            ],
+            "name": "Song", "disc_number": 1, "track_number": 1, "duration_ms": 201_500, "uri": "spotify:track:t1",
+        })).unwrap()
+    }
+
+    fn code(error: anyhow::Error) -> u32 {
+        error.downcast::<MpdError>().unwrap().code
+    }
+
+    #[test]
+    fn tokenize_splits_words_and_quoted_arguments() {
+        let tokens = tokenize(r#"find  artist "The \"Best\" Band" album Songs\ "#).unwrap();
+        assert_eq!(tokens, ["find", "artist", "The \"Best\" Band", "album", "Songs\\"]);
+        assert_eq!(tokenize("   ").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn tokenize_rejects_unterminated_quotes() {
+        assert_eq!(code(tokenize(r#"add "spotify:track:t1"#).unwrap_err()), ACK_ERROR_ARG);
+    }
+
+    #[test]
+    fn ack_reports_protocol_and_other_errors() {
+        let error = MpdError::error(ACK_ERROR_UNKNOWN, "unknown command \"foo\"");
+        assert_eq!(ack(error, 2, "foo"), "ACK [5@2] {foo} unknown command \"foo\"\n");
+        let error = anyhow!("No active device;\n  available devices:");
+        assert_eq!(ack(error, 0, "play"), "ACK [52@0] {play} No active device;   available devices:\n");
+    }
+
+    #[test]
+    fn search_query_maps_tags_and_filters() {
+        let arguments = ["Artist", "Foo \"Bar\"", "title", "Baz"].map(String::from);
+        assert_eq!(search_query(&arguments).unwrap(), "artist:\"Foo Bar\" track:Baz");
+
+        let filter = [String::from("((albumartist == 'Foo') AND (album contains 'It\\'s'))")];
+        assert_eq!(search_query(&filter).unwrap(), "artist:Foo album:It's");
+
+        let any = ["any", "free text"].map(String::from);
+        assert_eq!(search_query(&any).unwrap(), "free text");
+    }
+
+    #[test]
+    fn search_query_rejects_bad_arguments() {
+        assert_eq!(code(search_query(&[]).unwrap_err()), ACK_ERROR_ARG);
+        assert_eq!(code(search_query(&[String::from("artist")]).unwrap_err()), ACK_ERROR_ARG);
+        assert_eq!(code(search_query(&["title", "\"\""].map(String::from)).unwrap_err()), ACK_ERROR_ARG);
+        assert_eq!(code(search_query(&["genre", "jazz"].map(String::from)).unwrap_err()), ACK_ERROR_ARG);
+        assert_eq!(code(search_query(&[String::from("(artist != 'Foo')")]).unwrap_err()), ACK_ERROR_ARG);
+    }
+
+    #[test]
+    fn song_ids_are_positions_plus_one() {
+        assert_eq!(song_position("3", false).unwrap(), 3);
+        assert_eq!(song_position("3", true).unwrap(), 2);
+        assert_eq!(code(song_position("0", true).unwrap_err()), ACK_ERROR_NO_EXIST);
+        assert_eq!(code(song_position("-1", false).unwrap_err()), ACK_ERROR_ARG);
+    }
+
+    #[test]
+    fn password_authorizes_the_connection() {
+        let mut authorized = false;
+        assert_eq!(code(check_password(Some("secret"), "secreT", &mut authorized).unwrap_err()), ACK_ERROR_PASSWORD);
+        assert!(!authorized);
+        check_password(Some("secret"), "secret", &mut authorized).unwrap();
+        assert!(authorized);
+    }
+
+    #[test]
+    fn items_are_written_as_song_tags() {
+        let mut out = String::new();
+        write_item(&mut out, &track(), Some(2));
+        assert_eq!(out, "file: spotify:track:t1\nTitle: Song\nArtist: Singer, Band\nAlbum: Album\nAlbumArtist: Band\n\
+            Time: 201\nduration: 201.500\nPos: 2\nId: 3\n");
+    }
+
+    #[test]
+    fn playlist_version_follows_the_items() {
+        let item = track();
+        let version = playlist_version(&Some(item.clone()), &[]);
+        assert_eq!(version, playlist_version(&Some(item.clone()), &[]));
+        assert_ne!(version, playlist_version(&Some(item.clone()), &[item]));
+        assert!(version <= i32::MAX as u32);
+    }
+}
@@ -0,0 +1,594 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time::{sleep_until, Duration};
+use zbus::object_server::{InterfaceRef, SignalContext};
+use zbus::zvariant::{ObjectPath, Value};
+use zbus::{connection, fdo, interface};
+
+use crate::api::{pause, resolve_device_id, seek, set_repeat, set_shuffle, set_volume, skip_to_next, skip_to_previous, start_playback, start_playing, PlaybackRequest};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::{PlayableItem, PlaybackState};
+use crate::watcher::{PlayerEvent, PlayerWatcher};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.spotifyexp";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const TRACK_PATH_PREFIX: &str = "/org/mpris/MediaPlayer2/spotifyexp/track/";
+const NO_TRACK_PATH: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// How soon to look at the player again after a command, so that clients see its effect.
+const COMMAND_SETTLE: Duration = Duration::from_millis(500);
+
+pub struct MprisOptions {
+    /// Device selector as accepted by `DeviceResolver`.
+    pub device: Option<String>,
+}
+
+/// Requests from D-Bus clients, carried out by the polling loop which owns the API configuration.
+#[derive(Debug)]
+enum Command {
+    PlayPause,
+    Play,
+    Pause,
+    Next,
+    Previous,
+    /// Relative seek in microseconds.
+    Seek(i64),
+    /// Absolute position in microseconds.
+    SetPosition(i64),
+    Volume(f64),
+    Shuffle(bool),
+    LoopStatus(String),
+    OpenUri(String),
+}
+
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "Spotify (spotifyexp)"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec![String::from("spotify")]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct Player {
+    commands: UnboundedSender<Command>,
+    state: Option<PlaybackState>,
+    polled_at: Instant,
+}
+
+impl Player {
+    fn send(&self, command: Command) -> fdo::Result<()> {
+        self.commands.send(command)
+            .map_err(|_| fdo::Error::Failed(String::from("The player loop has stopped")))
+    }
+
+    fn item(&self) -> Option<&PlayableItem> {
+        self.state.as_ref().and_then(|state| state.item.as_ref())
+    }
+
+    fn position_us(&self) -> i64 {
+        let state = match &self.state {
+            Some(state) => state,
+            None => return 0,
+        };
+        let mut progress = state.progress_ms.unwrap_or(0) as u64;
+        if state.is_playing {
+            progress += self.polled_at.elapsed().as_millis() as u64;
+        }
+        if let Some(item) = &state.item {
+            progress = progress.min(item.duration_ms() as u64);
+        }
+        progress as i64 * 1000
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn play_pause(&self) -> fdo::Result<()> {
+        self.send(Command::PlayPause)
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        self.send(Command::Play)
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        self.send(Command::Pause)
+    }
+
+    /// Spotify Connect has no stop, so this pauses.
+    fn stop(&self) -> fdo::Result<()> {
+        self.send(Command::Pause)
+    }
+
+    fn next(&self) -> fdo::Result<()> {
+        self.send(Command::Next)
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        self.send(Command::Previous)
+    }
+
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        self.send(Command::Seek(offset))
+    }
+
+    /// Ignored unless `track_id` is the current track, as the specification requires.
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        if track_id.as_str() != track_path(self.item()) {
+            return Ok(());
+        }
+        self.send(Command::SetPosition(position))
+    }
+
+    fn open_uri(&self, uri: String) -> fdo::Result<()> {
+        if !uri.starts_with("spotify:") {
+            return Err(fdo::Error::InvalidArgs(format!("Not a Spotify URI: {}", uri)));
+        }
+        self.send(Command::OpenUri(uri))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(context: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        match &self.state {
+            Some(state) if state.is_playing => "Playing",
+            Some(_) => "Paused",
+            None => "Stopped",
+        }
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> &str {
+        match self.state.as_ref().map(|state| state.repeat_state.as_str()) {
+            Some("track") => "Track",
+            Some("context") => "Playlist",
+            _ => "None",
+        }
+    }
+
+    #[zbus(property)]
+    fn set_loop_status(&mut self, status: String) -> fdo::Result<()> {
+        let repeat = match status.as_str() {
+            "Track" => "track",
+            "Playlist" => "context",
+            "None" => "off",
+            _ => return Err(fdo::Error::InvalidArgs(format!("Unknown loop status: {}", status))),
+        };
+        self.send(Command::LoopStatus(String::from(repeat)))
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn set_rate(&mut self, _rate: f64) {}
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        self.state.as_ref().is_some_and(|state| state.shuffle_state)
+    }
+
+    #[zbus(property)]
+    fn set_shuffle(&mut self, shuffle: bool) -> fdo::Result<()> {
+        self.send(Command::Shuffle(shuffle))
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<&'static str, Value<'_>> {
+        metadata(self.item())
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.state.as_ref()
+            .and_then(|state| state.device.volume_percent)
+            .map_or(0.0, |volume| volume as f64 / 100.0)
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) -> fdo::Result<()> {
+        self.send(Command::Volume(volume))
+    }
+
+    /// Extrapolated from the latest poll; clients are told about jumps through `Seeked`.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.position_us()
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.state.is_some()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.state.is_some()
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.item().is_some()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// D-Bus object paths only allow `[A-Za-z0-9_]`, which Spotify ids happen to satisfy.
+fn track_path(item: Option<&PlayableItem>) -> String {
+    let id = item.and_then(|item| match item {
+        PlayableItem::Track(track) => track.id.as_deref(),
+        PlayableItem::Episode(episode) => Some(episode.id.as_str()),
+    });
+    match id {
+        Some(id) if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => format!("{}{}", TRACK_PATH_PREFIX, id),
+        _ => String::from(NO_TRACK_PATH),
+    }
+}
+
+fn metadata(item: Option<&PlayableItem>) -> HashMap<&'static str, Value<'static>> {
+    let mut metadata = HashMap::new();
+    metadata.insert("mpris:trackid", Value::from(ObjectPath::from_string_unchecked(track_path(item))));
+    let item = match item {
+        Some(item) => item,
+        None => return metadata,
+    };
+
+    metadata.insert("mpris:length", Value::from(item.duration_ms() as i64 * 1000));
+    metadata.insert("xesam:title", Value::from(item.name().to_owned()));
+    metadata.insert("xesam:url", Value::from(item.uri().to_owned()));
+    match item {
+        PlayableItem::Track(track) => {
+            let artists = track.artists.iter().map(|artist| artist.name.clone()).collect::<Vec<_>>();
+            metadata.insert("xesam:artist", Value::from(artists));
+            metadata.insert("xesam:trackNumber", Value::from(track.track_number as i32));
+            metadata.insert("xesam:discNumber", Value::from(track.disc_number as i32));
+            if let Some(album) = &track.album {
+                let album_artists = album.artists.iter().map(|artist| artist.name.clone()).collect::<Vec<_>>();
+                metadata.insert("xesam:album", Value::from(album.name.clone()));
+                metadata.insert("xesam:albumArtist", Value::from(album_artists));
+            }
+        }
+        PlayableItem::Episode(episode) => {
+            if let Some(show) = &episode.show {
+                metadata.insert("xesam:album", Value::from(show.name.clone()));
+                metadata.insert("xesam:artist", Value::from(vec![show.publisher.clone()]));
+            }
+        }
+    }
+    metadata
+}
+
+/// Registers on the session bus and keeps the MPRIS properties in step with Spotify until stopped.
+pub async fn run(config: &Rc<SpotifyConfig>, options: MprisOptions) -> Result<()> {
+    let tokens = TokenKeeper::new(config);
+    let (sender, mut commands) = mpsc::unbounded_channel();
+    let player = Player {
+        commands: sender,
+        state: None,
+        polled_at: Instant::now(),
+    };
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root)?
+        .serve_at(OBJECT_PATH, player)?
+        .build()
+        .await
+        .context("Failed to register on the session bus")?;
+    let player = connection.object_server().interface::<_, Player>(OBJECT_PATH).await?;
+    println!("Registered {}", BUS_NAME);
+
+    let mut target = TargetDevice::new(options.device);
+    let mut watcher = PlayerWatcher::new(config);
+    let mut next_poll = tokio::time::Instant::now();
+    loop {
+        tokio::select! {
+            command = commands.recv() => {
+                let command = match command {
+                    Some(command) => command,
+                    None => return Ok(()),
+                };
+                let config = tokens.config().await;
+                let position_us = player.get().await.position_us();
+                let result = match target.device_id(&config).await {
+                    Ok(device_id) => execute(&config, &device_id, watcher.state(), position_us, command).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    // The device may have gone away; look it up again for the next command.
+                    target.forget();
+                    eprintln!("{:#}", e);
+                }
+                next_poll = tokio::time::Instant::now() + COMMAND_SETTLE;
+            }
+            _ = sleep_until(next_poll) => {
+                watcher.set_config(&tokens.config().await);
+                match watcher.poll().await {
+                    Ok(events) => {
+                        if events.iter().any(|event| matches!(event, PlayerEvent::DeviceChanged { .. })) {
+                            target.forget();
+                        }
+                        publish(&player, &watcher, &events).await?
+                    }
+                    Err(e) => eprintln!("Failed to fetch the playback state: {:#}", e),
+                }
+                next_poll = tokio::time::Instant::now() + watcher.next_delay();
+            }
+        }
+    }
+}
+
+/// The device commands go to, resolved once and reused until playback moves or a command fails.
+struct TargetDevice {
+    selector: Option<String>,
+    device_id: Option<String>,
+}
+
+impl TargetDevice {
+    fn new(selector: Option<String>) -> Self {
+        Self {
+            selector,
+            device_id: None,
+        }
+    }
+
+    async fn device_id(&mut self, config: &Rc<SpotifyConfig>) -> Result<String> {
+        if let Some(device_id) = &self.device_id {
+            return Ok(device_id.clone());
+        }
+        let device_id = resolve_device_id(config, self.selector.as_deref()).await?;
+        self.device_id = Some(device_id.clone());
+        Ok(device_id)
+    }
+
+    fn forget(&mut self) {
+        self.device_id = None;
+    }
+}
+
+async fn execute(config: &Rc<SpotifyConfig>, device_id: &str, state: Option<&PlaybackState>, position_us: i64, command: Command) -> Result<()> {
+    match command {
+        Command::PlayPause if state.is_some_and(|state| state.is_playing) => pause(config, device_id).await,
+        Command::PlayPause | Command::Play => start_playing(config, device_id).await,
+        Command::Pause => pause(config, device_id).await,
+        Command::Next => skip_to_next(config, device_id).await,
+        Command::Previous => skip_to_previous(config, device_id).await,
+        Command::Seek(offset) => seek(config, device_id, to_position_ms(position_us.saturating_add(offset))).await,
+        Command::SetPosition(position) => seek(config, device_id, to_position_ms(position)).await,
+        Command::Volume(volume) => set_volume(config, device_id, (volume.clamp(0.0, 1.0) * 100.0).round() as u32).await,
+        Command::Shuffle(shuffle) => set_shuffle(config, device_id, shuffle).await,
+        Command::LoopStatus(repeat) => set_repeat(config, device_id, &repeat).await,
+        Command::OpenUri(uri) if uri.starts_with("spotify:track:") || uri.starts_with("spotify:episode:") => {
+            start_playback(config, device_id, PlaybackRequest::uris(vec![uri])).await
+        }
+        Command::OpenUri(uri) => start_playback(config, device_id, PlaybackRequest::context(&uri)).await,
+    }
+}
+
+fn to_position_ms(position_us: i64) -> u32 {
+    (position_us.max(0) / 1000).min(u32::MAX as i64) as u32
+}
+
+/// Copies the watcher's state into the interface and tells clients what changed.
+async fn publish(player: &InterfaceRef<Player>, watcher: &PlayerWatcher, events: &[PlayerEvent]) -> Result<()> {
+    {
+        let mut player = player.get_mut().await;
+        player.state = watcher.state().cloned();
+        player.polled_at = Instant::now();
+    }
+
+    let context = player.signal_context();
+    let player = player.get().await;
+    for event in events {
+        match event {
+            PlayerEvent::TrackChanged { .. } => {
+                player.metadata_changed(context).await?;
+                player.can_seek_changed(context).await?;
+            }
+            PlayerEvent::Resumed | PlayerEvent::Paused => player.playback_status_changed(context).await?,
+            PlayerEvent::Stopped => {
+                player.playback_status_changed(context).await?;
+                player.metadata_changed(context).await?;
+                player.can_go_next_changed(context).await?;
+                player.can_go_previous_changed(context).await?;
+            }
+            PlayerEvent::Seeked { to_ms, .. } => Player::seeked(context, *to_ms as i64 * 1000).await?,
+            PlayerEvent::DeviceChanged { .. } | PlayerEvent::VolumeChanged { .. } => player.volume_changed(context).await?,
+            PlayerEvent::ShuffleChanged { .. } => player.shuffle_changed(context).await?,
+            PlayerEvent::RepeatChanged { .. } => player.loop_status_changed(context).await?,
+            PlayerEvent::ContextChanged { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn track(id: Option<&str>) -> PlayableItem {
+        serde_json::from_value(json!({
+            "type": "track", "id": id, "linked_from": null, "href": null,
+            "album": {
+                "id": "a1", "href": null, "name": "Album", "release_date": "2020", "uri": "spotify:album:a1",
+                "artists": [{ "id": "r2", "href": null, "name": "Band", "uri": "spotify:artist:r2" }],
+            },
+            "artists": [{ "id": "r1", "href": null, "name": "Singer", "uri": "spotify:artist:r1" }],
+            "name": "Song", "disc_number": 2, "track_number": 7, "duration_ms": 200_000, "uri": "spotify:track:t1",
+        })).unwrap()
+    }
+
+    fn episode() -> PlayableItem {
+        serde_json::from_value(json!({
+            "type": "episode", "id": "e1", "href": "", "name": "Episode", "duration_ms": 3_600_000,
+            "release_date": "2021-01-01", "uri": "spotify:episode:e1",
+            "show": { "id": "s1", "href": "", "name": "Show", "publisher": "Studio", "uri": "spotify:show:s1" },
+        })).unwrap()
+    }
+
+    fn player(is_playing: bool, repeat_state: &str, progress_ms: u32) -> Player {
+        let state = serde_json::from_value(json!({
+            "device": {
+                "id": "abc123", "is_active": true, "is_private_session": false, "is_restricted": false,
+                "name": "Kitchen", "type": "Speaker", "volume_percent": 35,
+            },
+            "repeat_state": repeat_state,
+            "shuffle_state": true,
+            "context": null,
+            "timestamp": 0,
+            "progress_ms": progress_ms,
+            "is_playing": is_playing,
+            "item": track(Some("t1")),
+            "currently_playing_type": "track",
+        })).unwrap();
+        Player {
+            commands: mpsc::unbounded_channel().0,
+            state: Some(state),
+            polled_at: Instant::now(),
+        }
+    }
+
+    fn path(path: &str) -> Value<'static> {
+        Value::from(ObjectPath::from_string_unchecked(path.to_owned()))
+    }
+
+    #[test]
+    fn track_paths_need_a_valid_id() {
+        assert_eq!(track_path(Some(&track(Some("t1")))), "/org/mpris/MediaPlayer2/spotifyexp/track/t1");
+        assert_eq!(track_path(Some(&episode())), "/org/mpris/MediaPlayer2/spotifyexp/track/e1");
+        assert_eq!(track_path(Some(&track(None))), NO_TRACK_PATH);
+        assert_eq!(track_path(Some(&track(Some("a-b")))), NO_TRACK_PATH);
+        assert_eq!(track_path(None), NO_TRACK_PATH);
+    }
+
+    #[test]
+    fn track_metadata() {
+        let metadata = metadata(Some(&track(Some("t1"))));
+        assert_eq!(metadata["mpris:trackid"], path("/org/mpris/MediaPlayer2/spotifyexp/track/t1"));
+        assert_eq!(metadata["mpris:length"], Value::from(200_000_000i64));
+        assert_eq!(metadata["xesam:title"], Value::from("Song"));
+        assert_eq!(metadata["xesam:url"], Value::from("spotify:track:t1"));
+        assert_eq!(metadata["xesam:artist"], Value::from(vec![String::from("Singer")]));
+        assert_eq!(metadata["xesam:album"], Value::from("Album"));
+        assert_eq!(metadata["xesam:albumArtist"], Value::from(vec![String::from("Band")]));
+        assert_eq!(metadata["xesam:trackNumber"], Value::from(7));
+        assert_eq!(metadata["xesam:discNumber"], Value::from(2));
+    }
+
+    #[test]
+    fn episode_metadata_uses_the_show() {
+        let metadata = metadata(Some(&episode()));
+        assert_eq!(metadata["xesam:album"], Value::from("Show"));
+        assert_eq!(metadata["xesam:artist"], Value::from(vec![String::from("Studio")]));
+        assert!(!metadata.contains_key("xesam:trackNumber"));
+    }
+
+    #[test]
+    fn nothing_playing_has_only_the_no_track_id() {
+        let metadata = metadata(None);
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata["mpris:trackid"], path(NO_TRACK_PATH));
+    }
+
+    #[test]
+    fn properties_follow_the_playback_state() {
+        let paused = player(false, "context", 12_000);
+        assert_eq!(paused.playback_status(), "Paused");
+        assert_eq!(paused.loop_status(), "Playlist");
+        assert!(paused.shuffle());
+        assert_eq!(paused.volume(), 0.35);
+        assert_eq!(paused.position_us(), 12_000_000);
+        assert!(paused.can_seek());
+
+        let playing = player(true, "track", 199_999);
+        assert_eq!(playing.playback_status(), "Playing");
+        assert_eq!(playing.loop_status(), "Track");
+
+        let stopped = Player {
+            state: None,
+            ..playing
+        };
+        assert_eq!(stopped.playback_status(), "Stopped");
+        assert_eq!(stopped.loop_status(), "None");
+        assert_eq!(stopped.position_us(), 0);
+        assert!(!stopped.can_go_next());
+    }
+
+    #[test]
+    fn position_is_extrapolated_while_playing_but_not_past_the_end() {
+        let mut player = player(true, "off", 199_000);
+        player.polled_at = Instant::now() - std::time::Duration::from_secs(5);
+        assert_eq!(player.position_us(), 200_000_000);
+    }
+
+    #[test]
+    fn positions_are_clamped_to_milliseconds() {
+        assert_eq!(to_position_ms(-5), 0);
+        assert_eq!(to_position_ms(1_500_999), 1_500);
+        assert_eq!(to_position_ms(i64::MAX), u32::MAX);
+    }
+}
@@ -0,0 +1,480 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, Publish, QoS};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::{spawn_local, LocalSet};
+use tokio::time::{sleep, sleep_until, Instant};
+
+use crate::api::{pause, resolve_device_id, set_volume, skip_to_next, skip_to_previous, start_playback, start_playing, transfer_playback, PlaybackRequest};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::{PlayableItem, PlaybackState};
+use crate::watcher::PlayerWatcher;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How soon to look at the player again after a command, so that the state topic reflects it.
+const COMMAND_SETTLE: Duration = Duration::from_millis(500);
+
+const REQUEST_CAPACITY: usize = 64;
+
+pub struct MqttBridgeOptions {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefix for the state, event, availability and command topics.
+    pub prefix: String,
+    /// Home Assistant discovery prefix, or `None` to skip discovery.
+    pub discovery_prefix: Option<String>,
+    /// Device selector as accepted by `DeviceResolver`, used when a command names none.
+    pub device: Option<String>,
+}
+
+/// A command payload given as JSON rather than plain text.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CommandBody {
+    device: Option<String>,
+    uri: Option<String>,
+    volume_percent: Option<u32>,
+    play: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Play(Option<String>),
+    PlayPause,
+    Pause,
+    Next,
+    Previous,
+    Volume(u32),
+    Device { play: bool },
+}
+
+struct Bridge {
+    tokens: Rc<TokenKeeper>,
+    client: AsyncClient,
+    options: MqttBridgeOptions,
+    watcher: PlayerWatcher,
+}
+
+/// Publishes the player state to the broker and carries out commands received from it until stopped.
+pub async fn run(config: &Rc<SpotifyConfig>, options: MqttBridgeOptions) -> Result<()> {
+    let mut mqtt_options = MqttOptions::new(options.client_id.as_str(), options.host.as_str(), options.port);
+    mqtt_options.set_keep_alive(KEEP_ALIVE);
+    mqtt_options.set_last_will(LastWill::new(availability_topic(&options.prefix), "offline", QoS::AtLeastOnce, true));
+    if let Some(username) = &options.username {
+        mqtt_options.set_credentials(username.as_str(), options.password.as_deref().unwrap_or(""));
+    }
+    let (client, eventloop) = AsyncClient::new(mqtt_options, REQUEST_CAPACITY);
+
+    let bridge = Bridge {
+        tokens: Rc::new(TokenKeeper::new(config)),
+        client,
+        watcher: PlayerWatcher::new(config),
+        options,
+    };
+    // The connection and the commands run as local tasks, so that neither waits on the other or on Spotify.
+    LocalSet::new().run_until(bridge.run(eventloop)).await
+}
+
+impl Bridge {
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.options.prefix, name)
+    }
+
+    async fn run(mut self, eventloop: EventLoop) -> Result<()> {
+        let mut packets = drive_connection(eventloop);
+        let (finished, mut commands_finished) = unbounded_channel();
+        let mut next_poll = Instant::now();
+        loop {
+            tokio::select! {
+                Some(packet) = packets.recv() => match packet {
+                    Packet::ConnAck(_) => {
+                        println!("Connected to {}:{}", self.options.host, self.options.port);
+                        self.announce().await?;
+                    }
+                    Packet::Publish(message) => self.handle_command(&message, &finished),
+                    _ => {}
+                },
+                Some(()) = commands_finished.recv() => next_poll = Instant::now() + COMMAND_SETTLE,
+                _ = sleep_until(next_poll) => {
+                    self.poll_player().await?;
+                    next_poll = Instant::now() + self.watcher.next_delay();
+                }
+            }
+        }
+    }
+
+    /// Subscribes to commands and publishes availability, discovery and the current state after each (re)connect.
+    async fn announce(&self) -> Result<()> {
+        self.client.subscribe(self.topic("command/+"), QoS::AtLeastOnce).await?;
+        self.client.publish(availability_topic(&self.options.prefix), QoS::AtLeastOnce, true, "online").await?;
+        if let Some(discovery_prefix) = &self.options.discovery_prefix {
+            for (component, object_id, config) in self.discovery_configs() {
+                let topic = format!("{}/{}/{}/{}/config", discovery_prefix, component, node_id(&self.options.prefix), object_id);
+                self.client.publish(topic, QoS::AtLeastOnce, true, config.to_string()).await?;
+            }
+        }
+        if self.watcher.state().is_some() {
+            self.publish_state().await?;
+        }
+        Ok(())
+    }
+
+    async fn poll_player(&mut self) -> Result<()> {
+        self.watcher.set_config(&self.tokens.config().await);
+        let first_poll = self.watcher.state().is_none();
+        let events = match self.watcher.poll().await {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("Failed to fetch the playback state: {:#}", e);
+                return Ok(());
+            }
+        };
+        for event in events.iter() {
+            self.client.publish(self.topic("event"), QoS::AtLeastOnce, false, json!(event).to_string()).await?;
+        }
+        if first_poll || !events.is_empty() {
+            self.publish_state().await?;
+        }
+        Ok(())
+    }
+
+    async fn publish_state(&self) -> Result<()> {
+        let state = state_message(self.watcher.state(), self.watcher.progress_ms());
+        self.client.publish(self.topic("state"), QoS::AtLeastOnce, true, state.to_string()).await?;
+        Ok(())
+    }
+
+    /// Starts carrying out a command, reporting failures on the error topic rather than stopping the bridge.
+    fn handle_command(&self, message: &Publish, finished: &UnboundedSender<()>) {
+        let command = match message.topic.strip_prefix(&self.topic("command/")) {
+            Some(command) => command.to_owned(),
+            None => return,
+        };
+        let payload = String::from_utf8_lossy(&message.payload).trim().to_owned();
+        eprintln!("Command {} {}", command, payload);
+
+        let tokens = self.tokens.clone();
+        let client = self.client.clone();
+        let error_topic = self.topic("error");
+        let default_device = self.options.device.clone();
+        let is_playing = self.watcher.state().is_some_and(|state| state.is_playing);
+        let finished = finished.clone();
+        spawn_local(async move {
+            if let Err(e) = execute(&tokens, &command, &payload, default_device.as_deref(), is_playing).await {
+                eprintln!("Command {} failed: {:#}", command, e);
+                let error = json!({ "command": command, "message": format!("{:#}", e) });
+                if let Err(e) = client.publish(error_topic, QoS::AtLeastOnce, false, error.to_string()).await {
+                    eprintln!("Failed to report the error: {}", e);
+                }
+            }
+            let _ = finished.send(());
+        });
+    }
+
+    /// Home Assistant entities: (component, object id, config).
+    fn discovery_configs(&self) -> Vec<(&'static str, &'static str, Value)> {
+        let node = node_id(&self.options.prefix);
+        let device = json!({
+            "identifiers": [node],
+            "name": "Spotify",
+            "manufacturer": "spotifyexp",
+        });
+        let availability = availability_topic(&self.options.prefix);
+        let entity = |object_id: &str, name: &str, extra: Value| {
+            let mut config = json!({
+                "name": name,
+                "unique_id": format!("{}_{}", node, object_id),
+                "availability_topic": availability,
+                "device": device,
+            });
+            if let (Some(config), Value::Object(extra)) = (config.as_object_mut(), extra) {
+                config.extend(extra);
+            }
+            config
+        };
+        let button = |object_id: &'static str, name: &str, icon: &str| {
+            ("button", object_id, entity(object_id, name, json!({
+                "command_topic": self.topic(&format!("command/{}", object_id)),
+                "payload_press": "",
+                "icon": icon,
+            })))
+        };
+
+        vec![
+            ("sensor", "now_playing", entity("now_playing", "Now playing", json!({
+                "state_topic": self.topic("state"),
+                "value_template": "{{ value_json.title if value_json.title else 'Nothing' }}",
+                "json_attributes_topic": self.topic("state"),
+                "icon": "mdi:spotify",
+            }))),
+            ("sensor", "player_state", entity("player_state", "Player state", json!({
+                "state_topic": self.topic("state"),
+                "value_template": "{{ value_json.state }}",
+                "icon": "mdi:play-pause",
+            }))),
+            ("number", "volume", entity("volume", "Volume", json!({
+                "state_topic": self.topic("state"),
+                "value_template": "{{ value_json.volume_percent }}",
+                "command_topic": self.topic("command/volume"),
+                "min": 0,
+                "max": 100,
+                "step": 1,
+                "mode": "slider",
+                "unit_of_measurement": "%",
+                "icon": "mdi:volume-high",
+            }))),
+            button("play_pause", "Play/pause", "mdi:play-pause"),
+            button("next", "Next", "mdi:skip-next"),
+            button("previous", "Previous", "mdi:skip-previous"),
+        ]
+    }
+}
+
+/// Polls the connection in a task of its own and passes on the packets received, so that the
+/// requests the bridge awaits are sent even while it is busy, and reconnecting never waits on it.
+fn drive_connection(mut eventloop: EventLoop) -> UnboundedReceiver<Packet> {
+    let (packets, received) = unbounded_channel();
+    spawn_local(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(packet)) => {
+                    if packets.send(packet).is_err() {
+                        break;
+                    }
+                }
+                Ok(Event::Outgoing(_)) => {}
+                Err(e) => {
+                    // The event loop reconnects on the next poll.
+                    eprintln!("MQTT connection failed: {}", e);
+                    sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+    received
+}
+
+async fn execute(tokens: &TokenKeeper, name: &str, payload: &str, default_device: Option<&str>, is_playing: bool) -> Result<()> {
+    let (command, device) = parse_command(name, payload)?;
+    let config = tokens.config().await;
+    let device_id = resolve_device_id(&config, device.as_deref().or(default_device)).await?;
+
+    match command {
+        Command::Play(Some(uri)) if uri.starts_with("spotify:track:") || uri.starts_with("spotify:episode:") => {
+            start_playback(&config, &device_id, PlaybackRequest::uris(vec![uri])).await
+        }
+        Command::Play(Some(uri)) => start_playback(&config, &device_id, PlaybackRequest::context(&uri)).await,
+        Command::Play(None) => start_playing(&config, &device_id).await,
+        Command::PlayPause if is_playing => pause(&config, &device_id).await,
+        Command::PlayPause => start_playing(&config, &device_id).await,
+        Command::Pause => pause(&config, &device_id).await,
+        Command::Next => skip_to_next(&config, &device_id).await,
+        Command::Previous => skip_to_previous(&config, &device_id).await,
+        Command::Volume(volume) => set_volume(&config, &device_id, volume).await,
+        Command::Device { play } => transfer_playback(&config, &device_id, play).await,
+    }
+}
+
+/// Reads a command and the device it names from a topic suffix and a plain text or JSON payload.
+fn parse_command(name: &str, payload: &str) -> Result<(Command, Option<String>)> {
+    let body = if payload.starts_with('{') {
+        serde_json::from_str::<CommandBody>(payload)
+            .map_err(|e| anyhow!("Invalid command payload: {}", e))?
+    } else {
+        CommandBody::default()
+    };
+    let argument = Some(payload)
+        .filter(|payload| !payload.is_empty() && !payload.starts_with('{'))
+        .map(|payload| payload.to_owned());
+
+    let command = match name {
+        "play" => match body.uri.or_else(|| argument.clone()) {
+            Some(uri) if !uri.starts_with("spotify:") => bail!("Not a Spotify URI: {}", uri),
+            uri => Command::Play(uri),
+        },
+        "play_pause" => Command::PlayPause,
+        "pause" => Command::Pause,
+        "next" => Command::Next,
+        "previous" => Command::Previous,
+        "volume" => {
+            let volume = match (body.volume_percent, &argument) {
+                (Some(volume), _) => volume,
+                // Home Assistant sends number entities as decimals.
+                (None, Some(argument)) => argument.parse::<f64>()
+                    .ok()
+                    .filter(|volume| volume.is_finite() && *volume >= 0.0)
+                    .ok_or_else(|| anyhow!("Invalid volume: {}", argument))?
+                    .round() as u32,
+                (None, None) => bail!("Missing volume"),
+            };
+            if volume > 100 {
+                bail!("Volume must be between 0 and 100")
+            }
+            Command::Volume(volume)
+        }
+        "device" => match body.device.or_else(|| argument.clone()) {
+            Some(device) => return Ok((Command::Device { play: body.play }, Some(device))),
+            None => bail!("Missing device"),
+        },
+        other => bail!("Unknown command: {}", other),
+    };
+    Ok((command, body.device))
+}
+
+fn availability_topic(prefix: &str) -> String {
+    format!("{}/availability", prefix)
+}
+
+/// Discovery topics allow only `[A-Za-z0-9_-]` in node ids.
+fn node_id(prefix: &str) -> String {
+    prefix.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// A flat summary of the playback state, easy to pick apart in automation templates.
+fn state_message(state: Option<&PlaybackState>, progress_ms: Option<u32>) -> Value {
+    let state = match state {
+        Some(state) => state,
+        None => return json!({ "state": "idle" }),
+    };
+    let (artists, album) = match &state.item {
+        Some(PlayableItem::Track(track)) => (
+            track.artists.iter().map(|artist| artist.name.clone()).collect::<Vec<_>>(),
+            track.album.as_ref().map(|album| album.name.clone()),
+        ),
+        Some(PlayableItem::Episode(episode)) => (
+            episode.show.iter().map(|show| show.publisher.clone()).collect(),
+            episode.show.as_ref().map(|show| show.name.clone()),
+        ),
+        None => (Vec::new(), None),
+    };
+    json!({
+        "state": if state.is_playing { "playing" } else { "paused" },
+        "title": state.item.as_ref().map(|item| item.name()),
+        "artist": artists.join(", "),
+        "artists": artists,
+        "album": album,
+        "uri": state.item.as_ref().map(|item| item.uri()),
+        "context_uri": state.context.as_ref().map(|context| context.uri.as_str()),
+        "progress_ms": progress_ms,
+        "duration_ms": state.item.as_ref().map(|item| item.duration_ms()),
+        "device": state.device.name,
+        "device_id": state.device.id,
+        "volume_percent": state.device.volume_percent,
+        "shuffle": state.shuffle_state,
+        "repeat": state.repeat_state,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(name: &str, payload: &str) -> (Command, Option<String>) {
+        parse_command(name, payload).unwrap()
+    }
+
+    fn parse_error(name: &str, payload: &str) -> String {
+        parse_command(name, payload).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn plain_payloads() {
+        assert_eq!(parse("play", ""), (Command::Play(None), None));
+        assert_eq!(parse("play", "spotify:album:a1"), (Command::Play(Some(String::from("spotify:album:a1"))), None));
+        assert_eq!(parse("play_pause", ""), (Command::PlayPause, None));
+        assert_eq!(parse("next", "ignored"), (Command::Next, None));
+        assert_eq!(parse("device", "Kitchen"), (Command::Device { play: false }, Some(String::from("Kitchen"))));
+    }
+
+    #[test]
+    fn json_payloads() {
+        assert_eq!(
+            parse("play", r#"{"uri": "spotify:track:t1", "device": "Kitchen"}"#),
+            (Command::Play(Some(String::from("spotify:track:t1"))), Some(String::from("Kitchen"))),
+        );
+        assert_eq!(parse("pause", r#"{"device": "active"}"#), (Command::Pause, Some(String::from("active"))));
+        assert_eq!(parse("device", r#"{"device": "Kitchen", "play": true}"#), (Command::Device { play: true }, Some(String::from("Kitchen"))));
+        assert!(parse_error("pause", r#"{"device": 1}"#).starts_with("Invalid command payload"));
+    }
+
+    #[test]
+    fn volume_rules() {
+        assert_eq!(parse("volume", "42").0, Command::Volume(42));
+        // Home Assistant sends number entities as decimals.
+        assert_eq!(parse("volume", "42.6").0, Command::Volume(43));
+        assert_eq!(parse("volume", r#"{"volume_percent": 100}"#).0, Command::Volume(100));
+        assert_eq!(parse_error("volume", "101"), "Volume must be between 0 and 100");
+        assert_eq!(parse_error("volume", r#"{"volume_percent": 101}"#), "Volume must be between 0 and 100");
+        assert_eq!(parse_error("volume", "-1"), "Invalid volume: -1");
+        assert_eq!(parse_error("volume", "NaN"), "Invalid volume: NaN");
+        assert_eq!(parse_error("volume", ""), "Missing volume");
+    }
+
+    #[test]
+    fn invalid_commands() {
+        assert_eq!(parse_error("play", "https://example.com"), "Not a Spotify URI: https://example.com");
+        assert_eq!(parse_error("device", ""), "Missing device");
+        assert_eq!(parse_error("shuffle", "1"), "Unknown command: shuffle");
+    }
+
+    #[test]
+    fn idle_state() {
+        assert_eq!(state_message(None, None), json!({ "state": "idle" }));
+    }
+
+    #[test]
+    fn track_state() {
+        let state = serde_json::from_value::<PlaybackState>(json!({
+            "device": {
+                "id": "abc123", "is_active": true, "is_private_session": false, "is_restricted": false,
+                "name": "Kitchen", "type": "Speaker", "volume_percent": 50,
+            },
+            "repeat_state": "context",
+            "shuffle_state": true,
+            "context": { "uri": "spotify:album:a1", "type": "album", "href": null },
+            "timestamp": 0,
+            "progress_ms": 1_000,
+            "is_playing": false,
+            "item": {
+                "type": "track", "id": "t1", "linked_from": null, "href": null,
+                "album": { "id": "a1", "href": null, "name": "Album", "release_date": "2020", "uri": "spotify:album:a1", "artists": [] },
+                "artists": [
+                    { "id": "r1", "href": null, "name": "Singer", "uri": "spotify:artist:r1" },
+                    { "id": "r2", "href": null, "name": "Band", "uri": "spotify:artist:r2" },
+                ],
+                "name": "Song", "disc_number": 1, "track_number": 1, "duration_ms": 200_000, "uri": "spotify:track:t1",
+            },
+            "currently_playing_type": "track",
+        })).unwrap();
+
+        assert_eq!(state_message(Some(&state), Some(1_500)), json!({
+            "state": "paused",
+            "title": "Song",
+            "artist": "Singer, Band",
+            "artists": ["Singer", "Band"],
+            "album": "Album",
+            "uri": "spotify:track:t1",
+            "context_uri": "spotify:album:a1",
+            "progress_ms": 1_500,
+            "duration_ms": 200_000,
+            "device": "Kitchen",
+            "device_id": "abc123",
+            "volume_percent": 50,
+            "shuffle": true,
+            "repeat": "context",
+        }));
+    }
+}
@@ -1,27 +1,69 @@
-use serde_derive::Deserialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize)]
+/// Local files come with null ids and links for their album and artists, which read as empty strings.
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Artist {
+    #[serde(deserialize_with = "null_as_empty")]
     pub id: String,
+    #[serde(deserialize_with = "null_as_empty")]
     pub href: String,
     pub name: String,
     pub uri: String,
+    pub genres: Option<Vec<String>>,
+    pub popularity: Option<u32>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Artist names in credit order, comma separated.
+fn join_artist_names(artists: &[Artist]) -> String {
+    artists.iter()
+        .map(|artist| artist.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Image {
+    pub url: String,
+    pub height: Option<u32>,
+    pub width: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Album {
+    #[serde(deserialize_with = "null_as_empty")]
     pub id: String,
+    #[serde(deserialize_with = "null_as_empty")]
     pub href: String,
     pub artists: Vec<Artist>,
     pub name: String,
+    #[serde(deserialize_with = "null_as_empty")]
     pub release_date: String,
+    #[serde(deserialize_with = "null_as_empty")]
     pub uri: String,
+    /// Cover art, widest first.
+    #[serde(default)]
+    pub images: Vec<Image>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Album {
+    pub fn artist_names(&self) -> String {
+        join_artist_names(&self.artists)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Track {
-    pub id: String,
-    pub href: String,
+    /// `None` for local files.
+    pub id: Option<String>,
+    pub linked_from: Option<LinkedTrack>,
+    pub href: Option<String>,
+    pub album: Option<Album>,
     pub artists: Vec<Artist>,
     pub name: String,
     pub disc_number: u32,
@@ -30,6 +72,102 @@ pub struct Track {
     pub uri: String,
 }
 
+impl Track {
+    pub fn artist_names(&self) -> String {
+        join_artist_names(&self.artists)
+    }
+}
+
+/// The track originally asked for, when Spotify relinked it to a copy playable in the user's market.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LinkedTrack {
+    pub id: String,
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Show {
+    pub id: String,
+    pub href: String,
+    pub name: String,
+    pub publisher: String,
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Person {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Audiobook {
+    pub id: String,
+    pub href: String,
+    pub name: String,
+    pub authors: Vec<Person>,
+    pub narrators: Vec<Person>,
+    pub publisher: String,
+    pub total_chapters: Option<u32>,
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Episode {
+    pub id: String,
+    pub href: String,
+    pub name: String,
+    pub duration_ms: u32,
+    pub release_date: String,
+    pub show: Option<Show>,
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PlayableItem {
+    Track(Track),
+    Episode(Episode),
+}
+
+impl PlayableItem {
+    pub fn name(&self) -> &str {
+        match self {
+            PlayableItem::Track(track) => &track.name,
+            PlayableItem::Episode(episode) => &episode.name,
+        }
+    }
+
+    pub fn uri(&self) -> &str {
+        match self {
+            PlayableItem::Track(track) => &track.uri,
+            PlayableItem::Episode(episode) => &episode.uri,
+        }
+    }
+
+    /// Whether this is the item with `uri`, or a track relinked from it.
+    pub fn matches_uri(&self, uri: &str) -> bool {
+        match self {
+            PlayableItem::Track(track) => track.uri == uri || track.linked_from.as_ref().is_some_and(|linked| linked.uri == uri),
+            PlayableItem::Episode(episode) => episode.uri == uri,
+        }
+    }
+
+    pub fn duration_ms(&self) -> u32 {
+        match self {
+            PlayableItem::Track(track) => track.duration_ms,
+            PlayableItem::Episode(episode) => episode.duration_ms,
+        }
+    }
+
+    /// The album cover of a track; episodes have none.
+    pub fn images(&self) -> &[Image] {
+        match self {
+            PlayableItem::Track(track) => track.album.as_ref().map_or(&[], |album| &album.images),
+            PlayableItem::Episode(_) => &[],
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Paging<T> {
     pub href: String,
@@ -41,6 +179,22 @@ pub struct Paging<T> {
     pub previous: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CursorPaging<T> {
+    pub href: String,
+    pub items: Vec<T>,
+    pub limit: u32,
+    pub next: Option<String>,
+    pub cursors: Option<Cursors>,
+    pub total: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Cursors {
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SearchAlbumsResponse {
     pub albums: Paging<Album>,
@@ -51,23 +205,87 @@ pub struct SearchArtistsResponse {
     pub artists: Paging<Artist>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SearchTracksResponse {
+    pub tracks: Paging<Track>,
+}
+
+/// Results of a search over several item types; only the requested types are present.
+#[derive(Debug, Deserialize)]
+pub struct SearchResponse {
+    pub tracks: Option<Paging<Track>>,
+    pub albums: Option<Paging<Album>>,
+    pub artists: Option<Paging<Artist>>,
+    /// Spotify sometimes returns null in place of playlists it cannot show.
+    pub playlists: Option<Paging<Option<Playlist>>>,
+    pub shows: Option<Paging<Show>>,
+    pub episodes: Option<Paging<Episode>>,
+    pub audiobooks: Option<Paging<Audiobook>>,
+}
+
 pub type ListTracksResponse = Paging<Track>;
 
+#[derive(Debug, Deserialize)]
+pub struct GetTracksResponse {
+    pub tracks: Vec<Option<Track>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetArtistsResponse {
+    pub artists: Vec<Option<Artist>>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ListDevicesResponse {
     pub devices: Vec<Device>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Device {
-    pub id: String,
+    pub id: Option<String>,
     pub is_active: bool,
     pub is_private_session: bool,
     pub is_restricted: bool,
     pub name: String,
     #[serde(rename = "type")]
     pub device_type: String,
-    pub volume_percent: u32,
+    pub volume_percent: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Context {
+    #[serde(rename = "type")]
+    pub context_type: String,
+    pub href: Option<String>,
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlaybackState {
+    pub device: Device,
+    pub repeat_state: String,
+    pub shuffle_state: bool,
+    pub context: Option<Context>,
+    pub timestamp: u64,
+    pub progress_ms: Option<u32>,
+    pub is_playing: bool,
+    pub item: Option<PlayableItem>,
+    pub currently_playing_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayHistory {
+    pub track: Track,
+    pub played_at: DateTime<Utc>,
+    pub context: Option<Context>,
+}
+
+pub type RecentlyPlayedResponse = CursorPaging<PlayHistory>;
+
+#[derive(Debug, Deserialize)]
+pub struct QueueResponse {
+    pub currently_playing: Option<PlayableItem>,
+    pub queue: Vec<PlayableItem>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -75,7 +293,8 @@ pub struct CurrentlyPlayingTrackResponse {
     pub timestamp: u64,
     pub is_playing: bool,
     pub currently_playing_type: String,
-    pub item: CurrentlyPlayingItem,
+    pub progress_ms: Option<u32>,
+    pub item: Option<CurrentlyPlayingItem>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -83,6 +302,20 @@ pub struct CurrentlyPlayingItem {
     pub album: Option<Album>,
     pub artists: Vec<Artist>,
     pub name: String,
+    pub duration_ms: u32,
+    pub uri: String,
+    pub linked_from: Option<LinkedTrack>,
+}
+
+impl CurrentlyPlayingItem {
+    pub fn artist_names(&self) -> String {
+        join_artist_names(&self.artists)
+    }
+
+    /// Whether this is the item with `uri`, or a track relinked from it.
+    pub fn matches_uri(&self, uri: &str) -> bool {
+        self.uri == uri || self.linked_from.as_ref().is_some_and(|linked| linked.uri == uri)
+    }
 }
 
 pub type GetPlaylistsResponse = Paging<Playlist>;
@@ -103,6 +336,20 @@ pub struct PlaylistTracksRef {
     pub total: u32,
 }
 
+pub type ListPlaylistItemsResponse = Paging<PlaylistItem>;
+
+#[derive(Debug, Deserialize)]
+pub struct PlaylistItem {
+    pub track: Option<PlaylistItemRef>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaylistItemRef {
+    pub uri: String,
+    #[serde(default)]
+    pub is_local: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ErrorResponse {
     pub error: Error,
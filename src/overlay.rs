@@ -0,0 +1,181 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::json;
+use tokio::time::{sleep, Instant};
+
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::{Image, PlaybackState};
+use crate::template::Template;
+use crate::watcher::PlayerWatcher;
+
+const TEXT_FILE: &str = "now_playing.txt";
+const JSON_FILE: &str = "now_playing.json";
+const COVER_FILE: &str = "cover.jpg";
+
+/// How often to rewrite the text while playing, when the template shows progress.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct OverlayOptions {
+    pub directory: PathBuf,
+    pub template: Template,
+    /// Written instead of the template when nothing is playing.
+    pub stopped_text: String,
+    /// Preferred cover width in pixels; the smallest image at least this wide is used.
+    pub cover_width: u32,
+}
+
+/// Keeps now-playing text, cover art and a JSON snapshot up to date in a directory.
+/// Every file is replaced atomically, so readers never see one half written.
+pub struct OverlayWriter {
+    options: OverlayOptions,
+    text: Option<String>,
+    cover_url: Option<String>,
+}
+
+impl OverlayWriter {
+    pub fn new(options: OverlayOptions) -> Result<Self> {
+        fs::create_dir_all(&options.directory)
+            .with_context(|| format!("Failed to create {}", options.directory.display()))?;
+        Ok(Self {
+            options,
+            text: None,
+            cover_url: None,
+        })
+    }
+
+    /// Polls the player until stopped, rewriting the files whenever what they show changes.
+    pub async fn run(&mut self, config: &Rc<SpotifyConfig>) -> Result<()> {
+        let tokens = TokenKeeper::new(config);
+        let mut watcher = PlayerWatcher::new(config);
+        let mut next_poll = Instant::now();
+
+        loop {
+            if Instant::now() >= next_poll {
+                watcher.set_config(&tokens.config().await);
+                let first_poll = watcher.state().is_none();
+                match watcher.poll().await {
+                    Ok(events) => {
+                        if first_poll || !events.is_empty() {
+                            self.write_json(watcher.state(), watcher.progress_ms())?;
+                        }
+                        // Checked on every poll, so that a failed download is retried.
+                        if let Err(e) = self.update_cover(watcher.state()).await {
+                            eprintln!("Failed to update the cover: {:#}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("{:#}", e),
+                }
+                next_poll = Instant::now() + watcher.next_delay();
+            }
+            self.write_text(watcher.state(), watcher.progress_ms())?;
+
+            let ticking = self.options.template.uses_progress() && watcher.state().is_some_and(|state| state.is_playing);
+            let until_poll = next_poll.saturating_duration_since(Instant::now());
+            sleep(if ticking { until_poll.min(TICK_INTERVAL) } else { until_poll }).await;
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.options.directory.join(name)
+    }
+
+    fn render(&self, state: Option<&PlaybackState>, progress_ms: Option<u32>) -> String {
+        match state {
+            Some(_) => self.options.template.render(state, progress_ms),
+            None => self.options.stopped_text.clone(),
+        }
+    }
+
+    fn write_text(&mut self, state: Option<&PlaybackState>, progress_ms: Option<u32>) -> Result<()> {
+        let text = self.render(state, progress_ms);
+        if self.text.as_ref() != Some(&text) {
+            write_atomically(&self.path(TEXT_FILE), text.as_bytes())?;
+            self.text = Some(text);
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, state: Option<&PlaybackState>, progress_ms: Option<u32>) -> Result<()> {
+        let item = state.and_then(|state| state.item.as_ref());
+        let snapshot = json!({
+            "updated_at": Utc::now(),
+            "is_playing": state.is_some_and(|state| state.is_playing),
+            "text": self.render(state, progress_ms),
+            "progress_ms": progress_ms,
+            "device": state.map(|state| &state.device.name),
+            "cover_url": item.and_then(|item| choose_image(item.images(), self.options.cover_width)).map(|image| &image.url),
+            "item": item,
+        });
+        let content = serde_json::to_string_pretty(&snapshot)? + "\n";
+        write_atomically(&self.path(JSON_FILE), content.as_bytes())
+    }
+
+    /// Downloads the cover when it changes, and removes it when nothing with a cover is playing
+    /// or the new one fails to download.
+    async fn update_cover(&mut self, state: Option<&PlaybackState>) -> Result<()> {
+        let url = state.and_then(|state| state.item.as_ref())
+            .and_then(|item| choose_image(item.images(), self.options.cover_width))
+            .map(|image| image.url.clone());
+        if url == self.cover_url {
+            return Ok(());
+        }
+
+        let path = self.path(COVER_FILE);
+        match &url {
+            Some(url) => {
+                if let Err(e) = download(url, &path).await {
+                    // The old cover would show beside the new track until the download succeeds.
+                    remove_if_exists(&path)?;
+                    self.cover_url = None;
+                    return Err(e);
+                }
+            }
+            None => remove_if_exists(&path)?,
+        }
+        self.cover_url = url;
+        Ok(())
+    }
+}
+
+async fn download(url: &str, path: &Path) -> Result<()> {
+    let response = Client::new().get(url).send().await?;
+    if !response.status().is_success() {
+        bail!("Failed to download {}: {}", url, response.status())
+    }
+    write_atomically(path, &response.bytes().await?)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// The smallest image at least `width` pixels wide, or the widest one if none is that big.
+fn choose_image(images: &[Image], width: u32) -> Option<&Image> {
+    let image_width = |image: &Image| image.width.unwrap_or(0);
+    images.iter()
+        .filter(|image| image_width(image) >= width)
+        .min_by_key(|image| image_width(image))
+        .or_else(|| images.iter().max_by_key(|image| image_width(image)))
+}
+
+/// Writes to a temporary file beside `path` and renames it into place.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let temporary = path.with_file_name(format!(".{}.tmp", file_name));
+    fs::write(&temporary, contents)
+        .with_context(|| format!("Failed to write {}", temporary.display()))?;
+    fs::rename(&temporary, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))
+}
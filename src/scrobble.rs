@@ -0,0 +1,444 @@
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::Client;
+use rusqlite::params;
+use serde_json::{json, Value};
+use tokio::time::sleep;
+
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::history::{HistoryStore, ALBUM_ARTISTS, TRACK_ARTISTS};
+use crate::objects::{PlayableItem, Track};
+use crate::watcher::{PlayerEvent, PlayerWatcher};
+
+/// Tracks shorter than this are never scrobbled.
+const MINIMUM_TRACK_MS: u32 = 30_000;
+
+/// A track counts once half of it, or four minutes, has been heard.
+const MAXIMUM_REQUIRED_MS: u32 = 240_000;
+
+/// A seek back to within this much of the beginning plays the track again.
+const RESTART_WITHIN_MS: u32 = 5_000;
+
+/// How much of a track must be heard before it may be scrobbled.
+pub fn required_ms(duration_ms: u32) -> Option<u32> {
+    if duration_ms < MINIMUM_TRACK_MS {
+        None
+    } else {
+        Some((duration_ms / 2).min(MAXIMUM_REQUIRED_MS))
+    }
+}
+
+#[derive(Debug)]
+pub struct Listen {
+    pub listened_at: DateTime<Utc>,
+    pub artist_name: String,
+    pub track_name: String,
+    pub release_name: Option<String>,
+    pub release_artist_name: Option<String>,
+    pub duration_ms: u32,
+    pub track_uri: String,
+}
+
+impl Listen {
+    pub fn from_track(track: &Track, listened_at: DateTime<Utc>) -> Self {
+        Self {
+            listened_at,
+            artist_name: track.artist_names(),
+            track_name: track.name.clone(),
+            release_name: track.album.as_ref().map(|album| album.name.clone()),
+            release_artist_name: track.album.as_ref()
+                .map(|album| album.artist_names()),
+            duration_ms: track.duration_ms,
+            track_uri: track.uri.clone(),
+        }
+    }
+
+    /// The listen in the JSON shape ListenBrainz uses for submissions and exports.
+    pub fn to_listenbrainz(&self) -> Value {
+        let mut listen = self.track_metadata_json();
+        listen["listened_at"] = json!(self.listened_at.timestamp());
+        listen
+    }
+
+    fn track_metadata_json(&self) -> Value {
+        json!({
+            "track_metadata": {
+                "artist_name": self.artist_name,
+                "track_name": self.track_name,
+                "release_name": self.release_name,
+                "additional_info": {
+                    "duration_ms": self.duration_ms,
+                    "spotify_id": spotify_url(&self.track_uri),
+                    "origin_url": spotify_url(&self.track_uri),
+                    "music_service": "spotify.com",
+                    "submission_client": "spotifyexp",
+                    "submission_client_version": env!("CARGO_PKG_VERSION"),
+                },
+            },
+        })
+    }
+}
+
+fn spotify_url(uri: &str) -> String {
+    match uri.strip_prefix("spotify:track:") {
+        Some(id) => format!("https://open.spotify.com/track/{}", id),
+        None => uri.to_owned(),
+    }
+}
+
+/// Reads scrobble-worthy listens from the history store, oldest first.
+/// Plays without a known duration heard are assumed to have been heard in full.
+pub fn read_listens(store: &HistoryStore, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> Result<Vec<Listen>> {
+    let since = since.map_or(i64::MIN, |since| since.timestamp_millis());
+    let until = until.map_or(i64::MAX, |until| until.timestamp_millis());
+    let mut statement = store.connection().prepare(&format!(
+        "SELECT p.started_at, p.ms_played, t.name, t.uri, t.duration_ms, al.name, {}, {}
+         FROM plays p JOIN tracks t ON t.id = p.track_id LEFT JOIN albums al ON al.id = t.album_id
+         WHERE p.started_at >= ?1 AND p.started_at < ?2
+         ORDER BY p.started_at", TRACK_ARTISTS, ALBUM_ARTISTS),
+    )?;
+    let rows = statement.query_map(params![since, until], |row| {
+        let started_at: i64 = row.get(0)?;
+        let ms_played: Option<u32> = row.get(1)?;
+        let duration_ms: u32 = row.get(4)?;
+        let listen = Listen {
+            listened_at: Utc.timestamp_millis_opt(started_at).single().unwrap_or_else(Utc::now),
+            artist_name: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+            track_name: row.get(2)?,
+            release_name: row.get(5)?,
+            release_artist_name: row.get(7)?,
+            duration_ms,
+            track_uri: row.get(3)?,
+        };
+        Ok((listen, ms_played))
+    })?;
+
+    let mut listens = vec![];
+    for row in rows {
+        let (listen, ms_played) = row?;
+        let heard = ms_played.unwrap_or(listen.duration_ms);
+        if required_ms(listen.duration_ms).is_some_and(|required| heard >= required) {
+            listens.push(listen);
+        }
+    }
+    Ok(listens)
+}
+
+pub fn render_listenbrainz(listens: &[Listen]) -> Result<String> {
+    let listens = listens.iter()
+        .map(|listen| listen.to_listenbrainz())
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string_pretty(&listens)? + "\n")
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Renders listens as CSV with the columns Last.fm scrobble importers accept.
+pub fn render_lastfm_csv(listens: &[Listen]) -> String {
+    let mut out = String::from("Artist,Track,Album,Timestamp,Album Artist,Duration\n");
+    for listen in listens.iter() {
+        let _ = writeln!(out, "{},{},{},{},{},{}",
+            csv_field(&listen.artist_name),
+            csv_field(&listen.track_name),
+            csv_field(listen.release_name.as_deref().unwrap_or("")),
+            listen.listened_at.format("%Y-%m-%d %H:%M:%S"),
+            csv_field(listen.release_artist_name.as_deref().unwrap_or("")),
+            listen.duration_ms / 1000);
+    }
+    out
+}
+
+pub struct ListenBrainzClient {
+    endpoint: String,
+    token: String,
+}
+
+impl ListenBrainzClient {
+    /// `endpoint` is the API root, such as `https://api.listenbrainz.org`.
+    pub fn new(endpoint: &str, token: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            token: token.to_owned(),
+        }
+    }
+
+    pub async fn submit_listen(&self, listen: &Listen) -> Result<()> {
+        self.submit("single", json!([listen.to_listenbrainz()])).await
+    }
+
+    pub async fn submit_playing_now(&self, listen: &Listen) -> Result<()> {
+        self.submit("playing_now", json!([listen.track_metadata_json()])).await
+    }
+
+    async fn submit(&self, listen_type: &str, payload: Value) -> Result<()> {
+        let client = Client::new();
+        let body = json!({
+            "listen_type": listen_type,
+            "payload": payload,
+        });
+        let response = client.post(format!("{}/1/submit-listens", self.endpoint))
+            .header("Authorization", format!("Token {}", self.token))
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            bail!("Submission failed: {} {}", status, body)
+        }
+    }
+}
+
+struct CurrentTrack {
+    track: Track,
+    started_at: DateTime<Utc>,
+    heard: Duration,
+    submitted: bool,
+}
+
+/// Submits tracks as they are played, following the half-or-four-minutes rule.
+/// Each time a track starts counts as a separate play, so replaying or repeating it scrobbles it again.
+pub struct Scrobbler {
+    tokens: TokenKeeper,
+    client: ListenBrainzClient,
+    playing_now: bool,
+}
+
+impl Scrobbler {
+    pub fn new(config: &Rc<SpotifyConfig>, client: ListenBrainzClient, playing_now: bool) -> Self {
+        Self {
+            tokens: TokenKeeper::new(config),
+            client,
+            playing_now,
+        }
+    }
+
+    pub async fn run(&self) -> Result<()> {
+        let mut watcher = PlayerWatcher::new(&self.tokens.config().await);
+        let mut current: Option<CurrentTrack> = None;
+        let mut polled_at = Instant::now();
+
+        loop {
+            let was_playing = watcher.state().is_some_and(|state| state.is_playing);
+            watcher.set_config(&self.tokens.config().await);
+            let events = watcher.poll().await.unwrap_or_else(|e| {
+                eprintln!("Failed to fetch playback state: {:#}", e);
+                vec![]
+            });
+            let elapsed = polled_at.elapsed();
+            polled_at = Instant::now();
+
+            // The watcher reports a repeated track as changed, too.
+            let started = events.iter().any(|event| match event {
+                PlayerEvent::TrackChanged { .. } => true,
+                PlayerEvent::Seeked { to_ms, .. } => *to_ms < RESTART_WITHIN_MS,
+                _ => false,
+            });
+            let track = match watcher.state().and_then(|state| state.item.as_ref()) {
+                Some(PlayableItem::Track(track)) => Some(track),
+                _ => None,
+            };
+            match (&mut current, track) {
+                (Some(current), Some(track)) if current.track.uri == track.uri && !started => {
+                    if was_playing {
+                        current.heard += elapsed;
+                    }
+                }
+                (_, Some(track)) => {
+                    let progress = watcher.progress_ms().unwrap_or(0);
+                    current = Some(CurrentTrack {
+                        track: track.clone(),
+                        started_at: Utc::now() - chrono::Duration::milliseconds(progress as i64),
+                        heard: Duration::from_secs(0),
+                        submitted: false,
+                    });
+                    if self.playing_now {
+                        let listen = Listen::from_track(track, Utc::now());
+                        if let Err(e) = self.client.submit_playing_now(&listen).await {
+                            eprintln!("{:#}", e);
+                        }
+                    }
+                }
+                (_, None) => current = None,
+            }
+
+            if let Some(current) = current.as_mut() {
+                let due = required_ms(current.track.duration_ms)
+                    .is_some_and(|required| current.heard.as_millis() >= required as u128);
+                if due && !current.submitted {
+                    let listen = Listen::from_track(&current.track, current.started_at);
+                    match self.client.submit_listen(&listen).await {
+                        Ok(()) => current.submitted = true,
+                        Err(e) => eprintln!("{:#}", e),
+                    }
+                }
+            }
+
+            sleep(watcher.next_delay()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    use super::*;
+    use crate::history::{KnownPlay, PlaySource};
+
+    fn listen(track_name: &str, release_name: Option<&str>) -> Listen {
+        Listen {
+            listened_at: Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap(),
+            artist_name: String::from("Daft Punk"),
+            track_name: track_name.to_owned(),
+            release_name: release_name.map(|name| name.to_owned()),
+            release_artist_name: release_name.map(|_| String::from("Daft Punk")),
+            duration_ms: 320_357,
+            track_uri: String::from("spotify:track:0DiWol3AO6WpXZgp0goxAV"),
+        }
+    }
+
+    /// Answers one request with `status` and hands back the request as received.
+    async fn stub_server(status: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 4096];
+            loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head.lines()
+                        .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|value| value.trim().to_owned()))
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if read == 0 || body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}", status);
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+        (endpoint, handle)
+    }
+
+    #[test]
+    fn required_ms_is_half_or_four_minutes() {
+        assert_eq!(required_ms(29_999), None);
+        assert_eq!(required_ms(30_000), Some(15_000));
+        assert_eq!(required_ms(200_000), Some(100_000));
+        assert_eq!(required_ms(480_000), Some(240_000));
+        assert_eq!(required_ms(3_600_000), Some(240_000));
+    }
+
+    #[test]
+    fn lastfm_csv_quotes_fields() {
+        let listens = [
+            listen("Harder, Better, Faster, Stronger", Some("Discovery")),
+            listen("The \"Prime\" Time of Your Life", None),
+        ];
+        assert_eq!(render_lastfm_csv(&listens), concat!(
+            "Artist,Track,Album,Timestamp,Album Artist,Duration\n",
+            "Daft Punk,\"Harder, Better, Faster, Stronger\",Discovery,2024-03-01 12:30:05,Daft Punk,320\n",
+            "Daft Punk,\"The \"\"Prime\"\" Time of Your Life\",,2024-03-01 12:30:05,,320\n",
+        ));
+    }
+
+    #[test]
+    fn listens_are_read_from_history() {
+        let mut store = HistoryStore::open(std::path::Path::new(":memory:")).unwrap();
+        let track = serde_json::from_value::<Track>(json!({
+            "type": "track", "id": "t1", "linked_from": null, "href": null,
+            "album": {
+                "id": "a1", "href": null, "name": "Discovery", "release_date": "2001", "uri": "spotify:album:a1",
+                "artists": [{ "id": "r1", "href": null, "name": "Daft Punk", "uri": "spotify:artist:r1" }],
+            },
+            "artists": [
+                { "id": "r1", "href": null, "name": "Daft Punk", "uri": "spotify:artist:r1" },
+                { "id": "r2", "href": null, "name": "Romanthony", "uri": "spotify:artist:r2" },
+            ],
+            "name": "One More Time", "disc_number": 1, "track_number": 1, "duration_ms": 320_357, "uri": "spotify:track:t1",
+        })).unwrap();
+        store.store_tracks(&[track]).unwrap();
+        let play = |hour: u32, ms_played: Option<u32>| KnownPlay {
+            track_id: String::from("t1"),
+            started_at: Utc.with_ymd_and_hms(2024, 3, 1, hour, 0, 0).unwrap(),
+            ms_played,
+            context_uri: None,
+        };
+        let plays = [play(9, Some(10_000)), play(10, Some(200_000)), play(11, None)];
+        store.record_known_plays(&plays, PlaySource::RecentlyPlayed).unwrap();
+
+        let listens = read_listens(&store, None, None).unwrap();
+        let hours = listens.iter().map(|listen| listen.listened_at.hour()).collect::<Vec<_>>();
+        assert_eq!(hours, [10, 11]);
+        assert_eq!(listens[0].artist_name, "Daft Punk, Romanthony");
+        assert_eq!(listens[0].release_name.as_deref(), Some("Discovery"));
+        assert_eq!(listens[0].release_artist_name.as_deref(), Some("Daft Punk"));
+
+        let since = Utc.with_ymd_and_hms(2024, 3, 1, 11, 0, 0).unwrap();
+        assert_eq!(read_listens(&store, Some(since), None).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn submits_a_listen() {
+        let (endpoint, request) = stub_server("200 OK").await;
+        let client = ListenBrainzClient::new(&format!("{}/", endpoint), "secret");
+        client.submit_listen(&listen("One More Time", Some("Discovery"))).await.unwrap();
+
+        let request = request.await.unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /1/submit-listens HTTP/1.1"), "{}", head);
+        assert!(head.to_lowercase().contains("authorization: token secret"), "{}", head);
+        let body = serde_json::from_str::<Value>(body).unwrap();
+        assert_eq!(body["listen_type"], "single");
+        assert_eq!(body["payload"][0]["listened_at"], 1_709_296_205);
+        let metadata = &body["payload"][0]["track_metadata"];
+        assert_eq!(metadata["artist_name"], "Daft Punk");
+        assert_eq!(metadata["track_name"], "One More Time");
+        assert_eq!(metadata["release_name"], "Discovery");
+        assert_eq!(metadata["additional_info"]["origin_url"], "https://open.spotify.com/track/0DiWol3AO6WpXZgp0goxAV");
+    }
+
+    #[tokio::test]
+    async fn playing_now_has_no_timestamp() {
+        let (endpoint, request) = stub_server("200 OK").await;
+        let client = ListenBrainzClient::new(&endpoint, "secret");
+        client.submit_playing_now(&listen("One More Time", None)).await.unwrap();
+
+        let request = request.await.unwrap();
+        let body = serde_json::from_str::<Value>(request.split_once("\r\n\r\n").unwrap().1).unwrap();
+        assert_eq!(body["listen_type"], "playing_now");
+        assert!(body["payload"][0].get("listened_at").is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_submission_is_an_error() {
+        let (endpoint, _) = stub_server("401 Unauthorized").await;
+        let client = ListenBrainzClient::new(&endpoint, "wrong");
+        let error = client.submit_listen(&listen("One More Time", None)).await.unwrap_err();
+        assert!(error.to_string().starts_with("Submission failed: 401"), "{}", error);
+    }
+}
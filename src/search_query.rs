@@ -0,0 +1,450 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Error, Result, anyhow, bail};
+use structopt::StructOpt;
+
+/// A release year or an inclusive range of years, written `1990` or `1990-1999`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct YearRange {
+    pub from: u16,
+    pub to: u16,
+}
+
+impl YearRange {
+    pub fn new(from: u16, to: u16) -> Result<Self> {
+        if from > to {
+            bail!("Year range {}-{} ends before it starts", from, to)
+        }
+        Ok(Self { from, to })
+    }
+
+    pub fn single(year: u16) -> Self {
+        Self { from: year, to: year }
+    }
+}
+
+impl FromStr for YearRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_year = |year: &str| year.trim().parse::<u16>()
+            .map_err(|_| anyhow!("Invalid year \"{}\"; expected e.g. 1995 or 1990-1999", s));
+        match s.split_once('-') {
+            Some((from, to)) => YearRange::new(parse_year(from)?, parse_year(to)?),
+            None => Ok(YearRange::single(parse_year(s)?)),
+        }
+    }
+}
+
+impl fmt::Display for YearRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.from == self.to {
+            write!(f, "{}", self.from)
+        } else {
+            write!(f, "{}-{}", self.from, self.to)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchTag {
+    /// Albums released in the past two weeks.
+    New,
+    /// Albums in the lowest 10% of popularity.
+    Hipster,
+}
+
+impl SearchTag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchTag::New => "new",
+            SearchTag::Hipster => "hipster",
+        }
+    }
+}
+
+impl FromStr for SearchTag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "new" => Ok(SearchTag::New),
+            "hipster" => Ok(SearchTag::Hipster),
+            _ => Err(anyhow!("Unknown tag \"{}\"; expected new or hipster", s)),
+        }
+    }
+}
+
+/// A Spotify search query: free text plus field filters such as `artist:"Daft Punk" year:1990-1999`.
+/// Builds query strings with values quoted as needed, and parses existing ones back.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchQuery {
+    /// Free text as it appears in the query, with phrases in quotes.
+    terms: Vec<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    track: Option<String>,
+    year: Option<YearRange>,
+    genre: Option<String>,
+    isrc: Option<String>,
+    upc: Option<String>,
+    tags: Vec<SearchTag>,
+}
+
+/// Double quotes cannot be escaped in Spotify queries, so they are dropped from values.
+fn clean(value: &str) -> Option<String> {
+    let value = value.replace('"', "");
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    Some(value).filter(|value| !value.is_empty())
+}
+
+fn quote(value: &str) -> String {
+    if value.contains(char::is_whitespace) {
+        format!("\"{}\"", value)
+    } else {
+        value.to_owned()
+    }
+}
+
+impl SearchQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds free text, word by word.
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.terms.extend(text.split_whitespace().filter_map(clean));
+        self
+    }
+
+    /// Adds words that must appear together.
+    pub fn with_phrase(mut self, phrase: &str) -> Self {
+        self.terms.extend(clean(phrase).map(|phrase| quote(&phrase)));
+        self
+    }
+
+    pub fn with_artist(self, artist: &str) -> Self {
+        Self {
+            artist: clean(artist),
+            ..self
+        }
+    }
+
+    pub fn with_album(self, album: &str) -> Self {
+        Self {
+            album: clean(album),
+            ..self
+        }
+    }
+
+    pub fn with_track(self, track: &str) -> Self {
+        Self {
+            track: clean(track),
+            ..self
+        }
+    }
+
+    pub fn with_year(self, year: YearRange) -> Self {
+        Self {
+            year: Some(year),
+            ..self
+        }
+    }
+
+    pub fn with_genre(self, genre: &str) -> Self {
+        Self {
+            genre: clean(genre),
+            ..self
+        }
+    }
+
+    pub fn with_isrc(self, isrc: &str) -> Self {
+        Self {
+            isrc: clean(isrc),
+            ..self
+        }
+    }
+
+    pub fn with_upc(self, upc: &str) -> Self {
+        Self {
+            upc: clean(upc),
+            ..self
+        }
+    }
+
+    pub fn with_tag(mut self, tag: SearchTag) -> Self {
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for SearchQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = self.terms.clone();
+        let year = self.year.map(|year| year.to_string());
+        let fields = [
+            ("artist", &self.artist),
+            ("album", &self.album),
+            ("track", &self.track),
+            ("year", &year),
+            ("genre", &self.genre),
+            ("isrc", &self.isrc),
+            ("upc", &self.upc),
+        ];
+        for (name, value) in fields.iter() {
+            if let Some(value) = value {
+                parts.push(format!("{}:{}", name, quote(value)));
+            }
+        }
+        parts.extend(self.tags.iter().map(|tag| format!("tag:{}", tag.as_str())));
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Splits a query at whitespace outside double quotes, keeping the quotes.
+fn tokenize(query: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut in_quotes = false;
+    for c in query.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                token.push(c);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Sets a filter parsed from a query string, where each filter may appear only once.
+fn set_filter<T>(field: &mut Option<T>, name: &str, value: Option<T>) -> Result<()> {
+    if field.is_some() {
+        bail!("The {}: filter is given more than once", name)
+    }
+    *field = value;
+    Ok(())
+}
+
+impl FromStr for SearchQuery {
+    type Err = Error;
+
+    /// Parses a query string. Anything but a valid known filter is kept as written, to be passed on as free text.
+    fn from_str(query: &str) -> Result<Self> {
+        let mut search_query = SearchQuery::new();
+        for token in tokenize(query) {
+            let filter = token.split_once(':')
+                .filter(|(name, _)| !name.contains('"'))
+                .map(|(name, value)| (name.to_lowercase(), value));
+            let (name, value) = match filter {
+                Some(filter) => filter,
+                None => {
+                    search_query.terms.push(token);
+                    continue;
+                }
+            };
+            match name.as_str() {
+                "artist" => set_filter(&mut search_query.artist, &name, clean(value))?,
+                "album" => set_filter(&mut search_query.album, &name, clean(value))?,
+                "track" => set_filter(&mut search_query.track, &name, clean(value))?,
+                "genre" => set_filter(&mut search_query.genre, &name, clean(value))?,
+                "isrc" => set_filter(&mut search_query.isrc, &name, clean(value))?,
+                "upc" => set_filter(&mut search_query.upc, &name, clean(value))?,
+                // Text that merely looks like a year or tag filter, such as "year:zero", is searched for as is.
+                "year" => match value.replace('"', "").parse() {
+                    Ok(year) => set_filter(&mut search_query.year, &name, Some(year))?,
+                    Err(_) => search_query.terms.push(token),
+                },
+                "tag" => match value.replace('"', "").parse() {
+                    Ok(tag) => search_query = search_query.with_tag(tag),
+                    Err(_) => search_query.terms.push(token),
+                },
+                _ => search_query.terms.push(token),
+            }
+        }
+        Ok(search_query)
+    }
+}
+
+/// Command line flags for building a query, shared by the search commands.
+#[derive(StructOpt, Debug)]
+pub struct SearchQueryArguments {
+    /// Free text, which may itself contain filters such as artist:"Daft Punk" year:1990-1999
+    #[structopt(short, long)]
+    pub query: Option<String>,
+
+    /// Only items by this artist
+    #[structopt(long)]
+    pub artist: Option<String>,
+
+    /// Only items on this album
+    #[structopt(long)]
+    pub album: Option<String>,
+
+    /// Only tracks with this name
+    #[structopt(long)]
+    pub track: Option<String>,
+
+    /// Only items released in this year or range of years, e.g. 1990-1999
+    #[structopt(long)]
+    pub year: Option<YearRange>,
+
+    /// Only artists and tracks in this genre
+    #[structopt(long)]
+    pub genre: Option<String>,
+
+    /// Only the track with this International Standard Recording Code
+    #[structopt(long)]
+    pub isrc: Option<String>,
+
+    /// Only the album with this Universal Product Code
+    #[structopt(long)]
+    pub upc: Option<String>,
+
+    /// Only albums released in the past two weeks
+    #[structopt(long = "new")]
+    pub tag_new: bool,
+
+    /// Only albums in the lowest 10% of popularity
+    #[structopt(long)]
+    pub hipster: bool,
+}
+
+impl SearchQueryArguments {
+    /// Combines `--query` with the filter flags, which win over filters of the same name in the query.
+    pub fn to_query(&self) -> Result<SearchQuery> {
+        let mut query = self.query.as_deref().unwrap_or("").parse::<SearchQuery>()?;
+        if let Some(artist) = &self.artist {
+            query = query.with_artist(artist);
+        }
+        if let Some(album) = &self.album {
+            query = query.with_album(album);
+        }
+        if let Some(track) = &self.track {
+            query = query.with_track(track);
+        }
+        if let Some(year) = self.year {
+            query = query.with_year(year);
+        }
+        if let Some(genre) = &self.genre {
+            query = query.with_genre(genre);
+        }
+        if let Some(isrc) = &self.isrc {
+            query = query.with_isrc(isrc);
+        }
+        if let Some(upc) = &self.upc {
+            query = query.with_upc(upc);
+        }
+        if self.tag_new {
+            query = query.with_tag(SearchTag::New);
+        }
+        if self.hipster {
+            query = query.with_tag(SearchTag::Hipster);
+        }
+        if query.is_empty() {
+            bail!("Give a --query or at least one filter")
+        }
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> SearchQuery {
+        query.parse().unwrap()
+    }
+
+    fn assert_round_trip(query: &SearchQuery) {
+        assert_eq!(&parse(&query.to_string()), query, "{}", query);
+    }
+
+    #[test]
+    fn parsed_queries_round_trip() {
+        for query in [
+            "daft punk",
+            "\"around the world\" artist:\"Daft Punk\" year:1997",
+            "ARTIST:Queen album:\"A Night at the Opera\" track:Bohemian",
+            "genre:\"french house\" year:1990-1999 tag:new tag:hipster",
+            "isrc:GBAYE0601498 upc:724384960650",
+            "year:zero tag:retro love",
+        ] {
+            assert_round_trip(&parse(query));
+        }
+    }
+
+    #[test]
+    fn built_queries_round_trip() {
+        let query = SearchQuery::new()
+            .with_text("  around   the world ")
+            .with_phrase("one more time")
+            .with_artist("Daft Punk")
+            .with_album("Homework")
+            .with_year(YearRange::new(1990, 1999).unwrap())
+            .with_tag(SearchTag::Hipster);
+        assert_eq!(query.to_string(), "around the world \"one more time\" artist:\"Daft Punk\" album:Homework year:1990-1999 tag:hipster");
+        assert_round_trip(&query);
+    }
+
+    #[test]
+    fn values_are_quoted_only_when_needed() {
+        let query = SearchQuery::new()
+            .with_artist("  Daft \"The\"  Punk ")
+            .with_track("Revolution\"909\"")
+            .with_genre("\"\"");
+        assert_eq!(query.to_string(), "artist:\"Daft The Punk\" track:Revolution909");
+    }
+
+    #[test]
+    fn year_ranges() {
+        assert_eq!("1995".parse::<YearRange>().unwrap(), YearRange::single(1995));
+        assert_eq!(" 1990 - 1999 ".parse::<YearRange>().unwrap(), YearRange::new(1990, 1999).unwrap());
+        assert_eq!(YearRange::single(1995).to_string(), "1995");
+        assert_eq!(YearRange::new(1990, 1999).unwrap().to_string(), "1990-1999");
+        assert_eq!("1999-1990".parse::<YearRange>().unwrap_err().to_string(), "Year range 1999-1990 ends before it starts");
+        assert!("199x".parse::<YearRange>().is_err());
+        assert_eq!(parse("year:\"1990-1999\"").year, Some(YearRange::new(1990, 1999).unwrap()));
+    }
+
+    #[test]
+    fn invalid_year_and_tag_are_free_text() {
+        let query = parse("year:zero tag:retro year:1999-1990 Tag:NEW");
+        assert_eq!(query.terms, ["year:zero", "tag:retro", "year:1999-1990"]);
+        assert_eq!(query.year, None);
+        assert_eq!(query.tags, [SearchTag::New]);
+    }
+
+    #[test]
+    fn repeated_filters_are_rejected() {
+        assert_eq!(
+            "artist:Queen artist:\"David Bowie\"".parse::<SearchQuery>().unwrap_err().to_string(),
+            "The artist: filter is given more than once",
+        );
+        assert!("year:1990 year:1991".parse::<SearchQuery>().is_err());
+        assert_eq!(parse("tag:new tag:new").tags, [SearchTag::New]);
+    }
+
+    #[test]
+    fn quoted_colons_are_free_text() {
+        let query = parse("\"artist:Queen\" \"a b\":c");
+        assert_eq!(query.terms, ["\"artist:Queen\"", "\"a b\":c"]);
+        assert_eq!(query.artist, None);
+    }
+}
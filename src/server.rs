@@ -0,0 +1,433 @@
+mod feed;
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use anyhow::{Context, Result, bail};
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, HOST, ORIGIN};
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use tokio::task::LocalSet;
+
+use crate::api::{enqueue_tracks, get_playback_state, get_queue, pause, resolve_device_id, set_volume, skip_to_next, skip_to_previous, start_playback, start_playing, transfer_playback, DeviceNotResolved, DeviceResolver, PlaybackRequest, RequestFailed, SearchAlbums, SearchArtists, SearchTracks};
+use crate::config::{secrets_match, SpotifyConfig, TokenKeeper};
+use self::feed::Feed;
+
+/// Request bodies are small JSON objects; anything larger is refused before it is buffered.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+pub struct ServerOptions {
+    pub bind: SocketAddr,
+    /// Clients must send `Authorization: Bearer <api_key>`. It is required even on loopback,
+    /// since any web page the user visits can reach a loopback server.
+    pub api_key: String,
+}
+
+/// Runs futures on the current thread, since the API types are not `Send`.
+#[derive(Clone, Copy)]
+struct LocalExecutor;
+
+impl<F> hyper::rt::Executor<F> for LocalExecutor where F: Future + 'static {
+    fn execute(&self, future: F) {
+        tokio::task::spawn_local(future);
+    }
+}
+
+struct ServerState {
+    tokens: TokenKeeper,
+    api_key: String,
+    feed: Feed,
+}
+
+/// Serves the control API until the process is stopped.
+pub async fn serve(config: &Rc<SpotifyConfig>, options: ServerOptions) -> Result<()> {
+    if options.api_key.is_empty() {
+        bail!("The API key must not be empty")
+    }
+
+    let bind = options.bind;
+    let state = Rc::new(ServerState {
+        tokens: TokenKeeper::new(config),
+        api_key: options.api_key,
+        feed: Feed::new(),
+    });
+    let local = LocalSet::new();
+    local.run_until(async move {
+        let feed_state = state.clone();
+        tokio::task::spawn_local(async move {
+            feed_state.feed.run(&feed_state.tokens).await
+        });
+
+        let make_service = make_service_fn(move |_| {
+            let state = state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| handle(state.clone(), request)))
+            }
+        });
+        let server = Server::try_bind(&bind)
+            .with_context(|| format!("Failed to listen on {}", bind))?
+            .executor(LocalExecutor)
+            .serve(make_service);
+        println!("Listening on http://{}", bind);
+        server.await?;
+        Ok(())
+    }).await
+}
+
+async fn handle(state: Rc<ServerState>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if !is_authorized(&state.api_key, &request) {
+        return Ok(error_response(StatusCode::UNAUTHORIZED, "Missing or wrong API key"));
+    }
+    if is_cross_origin(&request) {
+        return Ok(error_response(StatusCode::FORBIDDEN, "Requests from other origins are not allowed"));
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    if method == Method::GET && path == "/events" {
+        eprintln!("{} {} stream", method, path);
+        return Ok(event_stream(state));
+    }
+
+    let response = match route(&state, request).await {
+        Ok(response) => response,
+        Err(e) => error_response(error_status(&e), &format!("{:#}", e)),
+    };
+    eprintln!("{} {} {}", method, path, response.status().as_u16());
+    Ok(response)
+}
+
+/// Errors caused by the request, or by the devices it targets, are the client's to fix;
+/// anything else is a failure talking to Spotify.
+fn error_status(e: &anyhow::Error) -> StatusCode {
+    if e.downcast_ref::<BadRequest>().is_some() {
+        return StatusCode::BAD_REQUEST;
+    }
+    if e.downcast_ref::<DeviceNotResolved>().is_some() {
+        return StatusCode::CONFLICT;
+    }
+    // Spotify rejecting our own token or rate limiting us is not the client's doing.
+    match e.downcast_ref::<RequestFailed>().map(|failed| failed.status) {
+        Some(status) if (400..500).contains(&status) && status != 401 && status != 429 => {
+            StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_REQUEST)
+        }
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn is_authorized(api_key: &str, request: &Request<Body>) -> bool {
+    // Browsers cannot set headers on an EventSource, so the feed also takes the key in the query.
+    let query_key = match request.uri().path() {
+        "/events" => request.uri().query()
+            .and_then(|query| serde_urlencoded::from_str::<Vec<(String, String)>>(query).ok())
+            .and_then(|parameters| parameters.into_iter().find(|(name, _)| name == "key"))
+            .map(|(_, value)| value),
+        _ => None,
+    };
+    let header_key = request.headers().get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let given = header_key.or(query_key.as_deref()).unwrap_or("");
+    secrets_match(given, api_key)
+}
+
+/// Browsers send `Origin` with cross-site requests; a page served from anywhere but this server has no business here.
+fn is_cross_origin(request: &Request<Body>) -> bool {
+    let origin = match request.headers().get(ORIGIN) {
+        Some(origin) => origin.to_str().unwrap_or(""),
+        None => return false,
+    };
+    let host = request.headers().get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("");
+    host.is_empty() || origin != format!("http://{}", host)
+}
+
+/// Answers with a Server-Sent Events stream fed by the shared player watcher.
+fn event_stream(state: Rc<ServerState>) -> Response<Body> {
+    let (sender, body) = Body::channel();
+    tokio::task::spawn_local(async move {
+        state.feed.stream_to(sender).await
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap_or_default()
+}
+
+/// A problem with the request itself, answered with 400.
+#[derive(Debug)]
+struct BadRequest(String);
+
+impl std::fmt::Display for BadRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BadRequest {}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PlayBody {
+    device: Option<String>,
+    context_uri: Option<String>,
+    uris: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DeviceBody {
+    device: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VolumeBody {
+    device: Option<String>,
+    volume_percent: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransferBody {
+    device: String,
+    #[serde(default)]
+    play: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueueBody {
+    device: Option<String>,
+    uris: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchParameters {
+    q: String,
+    #[serde(rename = "type", default = "default_search_type")]
+    search_type: String,
+}
+
+fn default_search_type() -> String {
+    String::from("track")
+}
+
+async fn route(state: &ServerState, request: Request<Body>) -> Result<Response<Body>> {
+    let config = state.tokens.config().await;
+    let method = request.method().clone();
+    let path = request.uri().path().trim_end_matches('/').to_owned();
+    let query = request.uri().query().unwrap_or("").to_owned();
+
+    match (method, path.as_str()) {
+        (Method::GET, "/player") => {
+            let playback = get_playback_state(&config).await?;
+            Ok(playback.map_or_else(no_content, |playback| json_response(&json!(playback))))
+        }
+        (Method::GET, "/now-playing") => {
+            let playback = get_playback_state(&config).await?;
+            Ok(playback.map_or_else(no_content, |playback| json_response(&json!({
+                "is_playing": playback.is_playing,
+                "progress_ms": playback.progress_ms,
+                "device": playback.device.name,
+                "item": playback.item,
+            }))))
+        }
+        (Method::POST, "/player/play") => {
+            let body = read_body::<PlayBody>(request).await?;
+            let device_id = resolve_device_id(&config, body.device.as_deref()).await?;
+            match (body.context_uri, body.uris.is_empty()) {
+                (None, true) => start_playing(&config, &device_id).await?,
+                (Some(context_uri), true) => start_playback(&config, &device_id, PlaybackRequest::context(&context_uri)).await?,
+                (None, false) => start_playback(&config, &device_id, PlaybackRequest::uris(body.uris)).await?,
+                (Some(_), false) => return Err(BadRequest(String::from("Give either context_uri or uris, not both")).into()),
+            }
+            Ok(no_content())
+        }
+        (Method::POST, "/player/pause") => {
+            let body = read_body::<DeviceBody>(request).await?;
+            pause(&config, &resolve_device_id(&config, body.device.as_deref()).await?).await?;
+            Ok(no_content())
+        }
+        (Method::POST, "/player/next") => {
+            let body = read_body::<DeviceBody>(request).await?;
+            skip_to_next(&config, &resolve_device_id(&config, body.device.as_deref()).await?).await?;
+            Ok(no_content())
+        }
+        (Method::POST, "/player/previous") => {
+            let body = read_body::<DeviceBody>(request).await?;
+            skip_to_previous(&config, &resolve_device_id(&config, body.device.as_deref()).await?).await?;
+            Ok(no_content())
+        }
+        (Method::PUT, "/player/volume") => {
+            let body = read_body::<VolumeBody>(request).await?;
+            if body.volume_percent > 100 {
+                return Err(BadRequest(String::from("volume_percent must be between 0 and 100")).into());
+            }
+            set_volume(&config, &resolve_device_id(&config, body.device.as_deref()).await?, body.volume_percent).await?;
+            Ok(no_content())
+        }
+        (Method::PUT, "/player/device") => {
+            let body = read_body::<TransferBody>(request).await?;
+            let device_id = resolve_device_id(&config, Some(&body.device)).await?;
+            transfer_playback(&config, &device_id, body.play).await?;
+            Ok(no_content())
+        }
+        (Method::GET, "/devices") => {
+            let resolver = DeviceResolver::fetch(&config).await?;
+            Ok(json_response(&json!({ "devices": resolver.devices() })))
+        }
+        (Method::GET, "/queue") => {
+            let queue = get_queue(&config).await?;
+            Ok(json_response(&json!({
+                "currently_playing": queue.currently_playing,
+                "queue": queue.queue,
+            })))
+        }
+        (Method::POST, "/queue") => {
+            let body = read_body::<QueueBody>(request).await?;
+            let device_id = resolve_device_id(&config, body.device.as_deref()).await?;
+            let report = enqueue_tracks(&config, &device_id, body.uris).await?;
+            Ok(json_response(&json!({
+                "queued": report.queued,
+                "already_queued": report.already_queued,
+            })))
+        }
+        (Method::GET, "/search") => {
+            let parameters = serde_urlencoded::from_str::<SearchParameters>(&query)
+                .map_err(|e| BadRequest(format!("Invalid query: {}", e)))?;
+            let results = match parameters.search_type.as_str() {
+                "track" => json!(SearchTracks::new(&config, &parameters.q).execute().await?.tracks.items),
+                "album" => json!(SearchAlbums::new(&config, &parameters.q).execute().await?.albums.items),
+                "artist" => json!(SearchArtists::new(&config, &parameters.q).execute().await?.artists.items),
+                other => return Err(BadRequest(format!("Unknown search type: {} (expected track, album or artist)", other)).into()),
+            };
+            Ok(json_response(&json!({ "items": results })))
+        }
+        _ => Ok(error_response(StatusCode::NOT_FOUND, "No such endpoint")),
+    }
+}
+
+/// Parses a JSON body, treating an empty body as `{}`.
+/// Bodies must be labelled as JSON, which browsers cannot do for a cross-site form or simple request.
+async fn read_body<T: DeserializeOwned>(request: Request<Body>) -> Result<T> {
+    let is_json = request.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"));
+    let mut body = request.into_body();
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(BadRequest(format!("Request body is larger than {} bytes", MAX_BODY_BYTES)).into());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let bytes = if bytes.iter().all(|b| b.is_ascii_whitespace()) {
+        &b"{}"[..]
+    } else if !is_json {
+        return Err(BadRequest(String::from("Request bodies must have Content-Type: application/json")).into());
+    } else {
+        &bytes[..]
+    };
+    serde_json::from_slice(bytes)
+        .map_err(|e| BadRequest(format!("Invalid request body: {}", e)).into())
+}
+
+fn json_response(value: &Value) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap_or_default()
+}
+
+fn no_content() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap_or_default()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = json_response(&json!({ "error": message }));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut builder = Request::builder().uri(uri);
+        for (name, value) in headers.iter() {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn key_in_the_authorization_header() {
+        assert!(is_authorized("secret", &request("/player", &[("Authorization", "Bearer secret")])));
+        assert!(!is_authorized("secret", &request("/player", &[("Authorization", "Bearer secreT")])));
+        assert!(!is_authorized("secret", &request("/player", &[("Authorization", "secret")])));
+        assert!(!is_authorized("secret", &request("/player", &[])));
+    }
+
+    #[test]
+    fn key_in_the_query_only_for_the_feed() {
+        assert!(is_authorized("se cret", &request("/events?key=se%20cret", &[])));
+        assert!(!is_authorized("secret", &request("/events?key=wrong", &[])));
+        assert!(!is_authorized("secret", &request("/events", &[])));
+        assert!(!is_authorized("secret", &request("/player?key=secret", &[])));
+    }
+
+    #[test]
+    fn only_same_origin_requests() {
+        assert!(!is_cross_origin(&request("/player/next", &[("Host", "127.0.0.1:8937")])));
+        assert!(!is_cross_origin(&request("/player/next", &[("Host", "127.0.0.1:8937"), ("Origin", "http://127.0.0.1:8937")])));
+        assert!(is_cross_origin(&request("/player/next", &[("Host", "127.0.0.1:8937"), ("Origin", "https://example.com")])));
+        assert!(is_cross_origin(&request("/player/next", &[("Host", "127.0.0.1:8937"), ("Origin", "null")])));
+        assert!(is_cross_origin(&request("/player/next", &[("Origin", "http://127.0.0.1:8937")])));
+    }
+
+    #[tokio::test]
+    async fn bodies_must_be_json() {
+        let post = |content_type: Option<&str>, body: &'static str| {
+            let mut builder = Request::builder().method(Method::POST).uri("/player/pause");
+            if let Some(content_type) = content_type {
+                builder = builder.header(CONTENT_TYPE, content_type);
+            }
+            builder.body(Body::from(body)).unwrap()
+        };
+        let device = read_body::<DeviceBody>(post(Some("application/json; charset=utf-8"), r#"{"device": "Kitchen"}"#)).await.unwrap();
+        assert_eq!(device.device.as_deref(), Some("Kitchen"));
+        assert!(read_body::<DeviceBody>(post(None, "")).await.unwrap().device.is_none());
+
+        let error = read_body::<DeviceBody>(post(Some("text/plain"), r#"{"device": "Kitchen"}"#)).await.unwrap_err();
+        assert_eq!(error_status(&error), StatusCode::BAD_REQUEST);
+        assert!(read_body::<DeviceBody>(post(None, "{}")).await.is_err());
+    }
+
+    #[test]
+    fn error_statuses() {
+        let failed = |status: u16| anyhow::Error::from(RequestFailed { status, message: String::new() });
+        assert_eq!(error_status(&BadRequest(String::new()).into()), StatusCode::BAD_REQUEST);
+        assert_eq!(error_status(&anyhow::Error::from(BadRequest(String::new())).context("Parsing")), StatusCode::BAD_REQUEST);
+        assert_eq!(error_status(&DeviceNotResolved(String::new()).into()), StatusCode::CONFLICT);
+        assert_eq!(error_status(&failed(404)), StatusCode::NOT_FOUND);
+        assert_eq!(error_status(&failed(403)), StatusCode::FORBIDDEN);
+        assert_eq!(error_status(&failed(401)), StatusCode::BAD_GATEWAY);
+        assert_eq!(error_status(&failed(429)), StatusCode::BAD_GATEWAY);
+        assert_eq!(error_status(&failed(503)), StatusCode::BAD_GATEWAY);
+        assert_eq!(error_status(&anyhow!("connection reset")), StatusCode::BAD_GATEWAY);
+    }
+}
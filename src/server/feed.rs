@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use hyper::body::{Bytes, Sender};
+use serde_json::{json, Value};
+use tokio::sync::broadcast::{self, Receiver};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{sleep, timeout};
+
+use crate::config::TokenKeeper;
+use crate::watcher::PlayerWatcher;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to check for subscribers while nobody is listening.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Comments sent to quiet connections, so that closed ones are noticed and dropped.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Fans one player watcher out to any number of Server-Sent Events clients.
+pub struct Feed {
+    sender: broadcast::Sender<String>,
+    /// The latest `state` message, sent to clients as soon as they connect.
+    latest_state: RefCell<Option<String>>,
+}
+
+impl Feed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            sender,
+            latest_state: RefCell::new(None),
+        }
+    }
+
+    /// Polls Spotify while at least one client is connected, broadcasting player events,
+    /// the full state after each change and progress ticks while playing.
+    pub async fn run(&self, tokens: &TokenKeeper) {
+        let mut watcher: Option<PlayerWatcher> = None;
+        let mut next_poll = Instant::now();
+
+        loop {
+            if self.sender.receiver_count() == 0 {
+                // Start afresh when someone connects, rather than diffing against a stale state.
+                watcher = None;
+                *self.latest_state.borrow_mut() = None;
+                sleep(IDLE_INTERVAL).await;
+                continue;
+            }
+
+            let config = tokens.config().await;
+            let watcher = watcher.get_or_insert_with(|| {
+                next_poll = Instant::now();
+                PlayerWatcher::new(&config)
+            });
+            watcher.set_config(&config);
+
+            if Instant::now() >= next_poll {
+                match watcher.poll().await {
+                    Ok(events) => {
+                        let first_poll = self.latest_state.borrow().is_none();
+                        for event in events.iter() {
+                            self.broadcast(event.name(), &json!(event));
+                        }
+                        if first_poll || !events.is_empty() {
+                            let state = message("state", &json!(watcher.state()));
+                            *self.latest_state.borrow_mut() = Some(state.clone());
+                            let _ = self.sender.send(state);
+                        }
+                    }
+                    Err(e) => self.broadcast("error", &json!({ "message": format!("{:#}", e) })),
+                }
+                next_poll = Instant::now() + watcher.next_delay();
+            } else if let Some(state) = watcher.state().filter(|state| state.is_playing) {
+                self.broadcast("progress", &json!({
+                    "progress_ms": watcher.progress_ms(),
+                    "duration_ms": state.item.as_ref().map(|item| item.duration_ms()),
+                    "item_uri": state.item.as_ref().map(|item| item.uri()),
+                }));
+            }
+
+            sleep(PROGRESS_INTERVAL.min(next_poll.saturating_duration_since(Instant::now()))).await;
+        }
+    }
+
+    /// Streams messages to a client until it disconnects.
+    pub async fn stream_to(&self, mut body: Sender) {
+        let mut receiver = self.subscribe();
+        let latest_state = self.latest_state.borrow().clone();
+        if let Some(state) = latest_state {
+            if body.send_data(Bytes::from(state)).await.is_err() {
+                return;
+            }
+        }
+
+        loop {
+            let data = match timeout(KEEPALIVE_INTERVAL, receiver.recv()).await {
+                Ok(Ok(data)) => data,
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => return,
+                Err(_) => String::from(": keepalive\n\n"),
+            };
+            if body.send_data(Bytes::from(data)).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn subscribe(&self) -> Receiver<String> {
+        self.sender.subscribe()
+    }
+
+    fn broadcast(&self, event: &str, data: &Value) {
+        // Sending only fails when nobody is subscribed, which the next loop notices.
+        let _ = self.sender.send(message(event, data));
+    }
+}
+
+fn message(event: &str, data: &Value) -> String {
+    format!("event: {}\ndata: {}\n\n", event, data)
+}
@@ -0,0 +1,414 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::{Result, anyhow, bail};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::api::{enqueue_tracks, expand_uris, format_device, get_playback_state, get_queue, list_tracks, pause, set_volume, skip_to_next, skip_to_previous, start_playback, start_playing, DeviceResolver, PlaybackRequest, SearchAlbums, SearchArtists, SearchTracks};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::PlayableItem;
+
+const COMMANDS: [&str; 17] = [
+    "album", "device", "devices", "exit", "help", "next", "now", "pause", "play",
+    "prev", "queue", "refresh", "results", "search", "set", "vars", "volume",
+];
+
+const HELP: &str = "\
+search [tracks|albums|artists] QUERY  search, numbering the results
+album REF                            list the tracks of an album as results
+results                              show the last results again
+play [REF...]                        play results, URIs or variables; resume without arguments
+queue [REF...]                       add to the queue; show the queue without arguments
+pause | next | prev                  control playback
+volume PERCENT                       set the volume
+now                                  show what is playing
+devices                              list devices
+device NAME                          send commands to a device (tab completes names)
+set NAME REF                         save a reference as $NAME
+vars                                 list variables
+refresh                              get a new access token
+exit                                 leave the shell
+
+A REF is a result number (3 or $3), $* for all results, $NAME, $current or a spotify: URI.";
+
+#[derive(Clone)]
+struct ShellResult {
+    uri: String,
+    label: String,
+}
+
+/// Completes command names, device names, result numbers and variables.
+#[derive(Default)]
+struct ShellHelper {
+    devices: Vec<String>,
+    results: Vec<String>,
+    variables: Vec<String>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let command = line.split_whitespace().next().unwrap_or("");
+        let command_end = line.find(command).map_or(0, |start| start + command.len());
+        if pos == command_end {
+            let candidates = COMMANDS.iter()
+                .filter(|name| name.starts_with(command))
+                .map(|name| pair(name.to_string(), name.to_string()))
+                .collect();
+            return Ok((pos - command.len(), candidates));
+        }
+
+        // Device names may contain spaces, so the rest of the line is the word.
+        if command == "device" {
+            let start = command_end + (line.len() - command_end - line[command_end..].trim_start().len());
+            let prefix = line[start..].to_lowercase();
+            let candidates = self.devices.iter()
+                .filter(|name| name.to_lowercase().starts_with(&prefix))
+                .map(|name| pair(name.clone(), name.clone()))
+                .collect();
+            return Ok((start, candidates));
+        }
+
+        let start = line.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+        let word = &line[start..];
+        if !["play", "queue", "album", "set"].contains(&command) || word.starts_with("spotify:") {
+            return Ok((pos, vec![]));
+        }
+        let mut candidates = vec![];
+        let number = word.trim_start_matches('$');
+        for (index, label) in self.results.iter().enumerate() {
+            let reference = (index + 1).to_string();
+            if reference.starts_with(number) {
+                let replacement = if word.starts_with('$') { format!("${}", reference) } else { reference.clone() };
+                candidates.push(pair(format!("{} {}", reference, label), replacement));
+            }
+        }
+        if word.starts_with('$') {
+            for name in self.variables.iter().map(|name| format!("${}", name)) {
+                if name.starts_with(word) {
+                    candidates.push(pair(name.clone(), name));
+                }
+            }
+        }
+        Ok((start, candidates))
+    }
+}
+
+fn pair(display: String, replacement: String) -> Pair {
+    Pair { display, replacement }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+pub struct Shell {
+    tokens: TokenKeeper,
+    config: Rc<SpotifyConfig>,
+    device_id: Option<String>,
+    device_names: Vec<String>,
+    results: Vec<ShellResult>,
+    variables: BTreeMap<String, String>,
+}
+
+impl Shell {
+    pub fn new(config: &Rc<SpotifyConfig>) -> Self {
+        Self {
+            tokens: TokenKeeper::new(config),
+            config: config.clone(),
+            device_id: None,
+            device_names: vec![],
+            results: vec![],
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// Reads and runs commands until end of input, keeping history in `history_path`.
+    pub async fn run(&mut self, history_path: &Path) -> Result<()> {
+        let mut editor = Editor::<ShellHelper, DefaultHistory>::new()?;
+        editor.set_helper(Some(ShellHelper::default()));
+        let _ = editor.load_history(history_path);
+
+        self.config = self.tokens.config().await;
+        if let Err(e) = self.update_devices().await {
+            eprintln!("{:#}", e);
+        }
+
+        loop {
+            if let Some(helper) = editor.helper_mut() {
+                helper.devices = self.device_names.clone();
+                helper.results = self.results.iter().map(|result| result.label.clone()).collect();
+                helper.variables = self.variables.keys().cloned().collect();
+            }
+
+            let line = match editor.readline("spotify> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            editor.add_history_entry(line)?;
+            if line == "exit" || line == "quit" {
+                break;
+            }
+
+            self.config = self.tokens.config().await;
+            if let Err(e) = self.execute(line).await {
+                eprintln!("{:#}", e);
+            }
+        }
+
+        editor.save_history(history_path)?;
+        Ok(())
+    }
+
+    async fn execute(&mut self, line: &str) -> Result<()> {
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (line, ""),
+        };
+        let arguments = rest.split_whitespace().collect::<Vec<_>>();
+
+        match command {
+            "help" => println!("{}", HELP),
+            "search" => self.search(rest).await?,
+            "album" => self.album(&arguments).await?,
+            "results" => self.show_results(),
+            "play" => self.play(&arguments).await?,
+            "queue" if arguments.is_empty() => self.show_queue().await?,
+            "queue" => {
+                let uris = self.resolve_references(&arguments).await?;
+                let device_id = self.device_id().await?;
+                let report = enqueue_tracks(&self.config, &device_id, uris).await?;
+                println!("Queued {}, {} of them already in the queue", report.queued.len(), report.already_queued.len());
+            }
+            "pause" => {
+                let device_id = self.device_id().await?;
+                pause(&self.config, &device_id).await?;
+            }
+            "next" => {
+                let device_id = self.device_id().await?;
+                skip_to_next(&self.config, &device_id).await?;
+            }
+            "prev" => {
+                let device_id = self.device_id().await?;
+                skip_to_previous(&self.config, &device_id).await?;
+            }
+            "volume" => {
+                let volume = rest.trim_end_matches('%').parse::<u32>()
+                    .map_err(|_| anyhow!("Usage: volume PERCENT"))?;
+                let device_id = self.device_id().await?;
+                set_volume(&self.config, &device_id, volume).await?;
+            }
+            "now" => self.show_now_playing().await?,
+            "devices" => {
+                let resolver = self.update_devices().await?;
+                for device in resolver.devices().iter() {
+                    println!("{}", format_device(device));
+                }
+            }
+            "device" => {
+                let resolver = self.update_devices().await?;
+                let device = resolver.resolve(Some(rest))?;
+                println!("Using {}", device.name);
+                self.device_id = device.id.clone();
+            }
+            "set" => match arguments.as_slice() {
+                [name, reference] => {
+                    let name = name.trim_start_matches('$');
+                    let uris = self.resolve_references(&[reference]).await?;
+                    self.variables.insert(name.to_owned(), uris.join(" "));
+                }
+                _ => bail!("Usage: set NAME REF"),
+            },
+            "vars" => {
+                for (name, value) in self.variables.iter() {
+                    println!("${} = {}", name, value);
+                }
+            }
+            "refresh" => {
+                self.tokens.refresh().await?;
+                self.config = self.tokens.config().await;
+                println!("Refreshed the access token");
+            }
+            _ => bail!("Unknown command: {} (try help)", command),
+        }
+        Ok(())
+    }
+
+    async fn update_devices(&mut self) -> Result<DeviceResolver> {
+        let resolver = DeviceResolver::fetch(&self.config).await?;
+        self.device_names = resolver.devices().iter().map(|device| device.name.clone()).collect();
+        Ok(resolver)
+    }
+
+    /// The device chosen with `device`, or else the one the resolver picks by default.
+    async fn device_id(&mut self) -> Result<String> {
+        if let Some(device_id) = &self.device_id {
+            return Ok(device_id.clone());
+        }
+        let resolver = self.update_devices().await?;
+        let device = resolver.resolve(None)?;
+        device.id.clone().ok_or_else(|| anyhow!("Device \"{}\" has no id", device.name))
+    }
+
+    /// Turns result numbers, variables and URIs into URIs.
+    async fn resolve_references(&self, references: &[&str]) -> Result<Vec<String>> {
+        let mut uris = vec![];
+        for reference in references.iter() {
+            if reference.starts_with("spotify:") {
+                uris.push(reference.to_string());
+                continue;
+            }
+            let name = reference.strip_prefix('$').unwrap_or(reference);
+            if name == "*" {
+                uris.extend(self.results.iter().map(|result| result.uri.clone()));
+            } else if name == "current" {
+                let state = get_playback_state(&self.config).await?;
+                let item = state.and_then(|state| state.item)
+                    .ok_or_else(|| anyhow!("Nothing is playing"))?;
+                uris.push(item.uri().to_owned());
+            } else if let Ok(number) = name.parse::<usize>() {
+                let result = number.checked_sub(1).and_then(|index| self.results.get(index))
+                    .ok_or_else(|| anyhow!("No result {} (there are {})", number, self.results.len()))?;
+                uris.push(result.uri.clone());
+            } else if let Some(value) = self.variables.get(name) {
+                uris.extend(value.split_whitespace().map(|uri| uri.to_owned()));
+            } else {
+                bail!("Unknown reference: {}", reference)
+            }
+        }
+        Ok(uris)
+    }
+
+    async fn search(&mut self, rest: &str) -> Result<()> {
+        let (kind, query) = match rest.split_once(char::is_whitespace) {
+            Some((kind, query)) if ["tracks", "albums", "artists"].contains(&kind) => (kind, query.trim()),
+            _ => ("tracks", rest),
+        };
+        if query.is_empty() {
+            bail!("Usage: search [tracks|albums|artists] QUERY")
+        }
+
+        self.results = match kind {
+            "albums" => SearchAlbums::new(&self.config, query).execute().await?
+                .albums.items.into_iter()
+                .map(|album| ShellResult {
+                    label: format!("{} - {} ({})", album.name, album.artist_names(), album.release_date),
+                    uri: album.uri,
+                })
+                .collect(),
+            "artists" => SearchArtists::new(&self.config, query).execute().await?
+                .artists.items.into_iter()
+                .map(|artist| ShellResult {
+                    label: artist.name,
+                    uri: artist.uri,
+                })
+                .collect(),
+            _ => SearchTracks::new(&self.config, query).execute().await?
+                .tracks.items.into_iter()
+                .map(|track| ShellResult {
+                    label: format!("{} - {}", track.name, track.artist_names()),
+                    uri: track.uri,
+                })
+                .collect(),
+        };
+        self.show_results();
+        Ok(())
+    }
+
+    async fn album(&mut self, arguments: &[&str]) -> Result<()> {
+        let uris = self.resolve_references(arguments).await?;
+        let album_id = match uris.as_slice() {
+            [uri] => uri.strip_prefix("spotify:album:")
+                .ok_or_else(|| anyhow!("Not an album: {}", uri))?,
+            _ => bail!("Usage: album REF"),
+        };
+
+        let response = list_tracks(&self.config, album_id).await?;
+        self.results = response.items.into_iter()
+            .map(|track| ShellResult {
+                label: format!("{} - {}", track.name, track.artist_names()),
+                uri: track.uri,
+            })
+            .collect();
+        self.show_results();
+        Ok(())
+    }
+
+    fn show_results(&self) {
+        for (index, result) in self.results.iter().enumerate() {
+            println!("{:>3}. {}  {}", index + 1, result.label, result.uri);
+        }
+    }
+
+    async fn play(&mut self, arguments: &[&str]) -> Result<()> {
+        let device_id = self.device_id().await?;
+        if arguments.is_empty() {
+            return start_playing(&self.config, &device_id).await;
+        }
+
+        let uris = self.resolve_references(arguments).await?;
+        let is_context = |uri: &str| ["album", "artist", "playlist", "show"].iter()
+            .any(|kind| uri.starts_with(&format!("spotify:{}:", kind)));
+        let request = match uris.as_slice() {
+            [uri] if is_context(uri) => PlaybackRequest::context(uri),
+            _ => PlaybackRequest::uris(expand_uris(&self.config, uris).await?),
+        };
+        start_playback(&self.config, &device_id, request).await
+    }
+
+    async fn show_queue(&self) -> Result<()> {
+        let response = get_queue(&self.config).await?;
+        if let Some(item) = &response.currently_playing {
+            println!("Now: {}", describe_item(item));
+        }
+        for (index, item) in response.queue.iter().enumerate() {
+            println!("{:>3}. {}", index + 1, describe_item(item));
+        }
+        Ok(())
+    }
+
+    async fn show_now_playing(&self) -> Result<()> {
+        match get_playback_state(&self.config).await? {
+            Some(state) => {
+                let item = state.item.as_ref().map_or(String::from("Unknown item"), describe_item);
+                let status = if state.is_playing { "Playing" } else { "Paused" };
+                println!("{} on {}: {}", status, state.device.name, item);
+            }
+            None => println!("Nothing is playing"),
+        }
+        Ok(())
+    }
+}
+
+fn describe_item(item: &PlayableItem) -> String {
+    match item {
+        PlayableItem::Track(track) => {
+            format!("{} - {}  {}", track.name, track.artist_names(), track.uri)
+        }
+        PlayableItem::Episode(episode) => {
+            let show = episode.show.as_ref().map_or("unknown", |show| &show.name);
+            format!("{} [{}]  {}", episode.name, show, episode.uri)
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use tokio::time::{sleep, sleep_until, Instant};
+
+use crate::api::{album_track_uris, get_playback_state, pause, set_volume};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::PlaybackState;
+use crate::timestamp::parse_duration;
+
+/// How often to check on the player while waiting for the fade to begin.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A track that changes within this long of its end has advanced on its own.
+const NATURAL_ADVANCE_SLACK_MS: u64 = 3000;
+
+#[derive(Clone, Copy, Debug)]
+pub enum SleepTrigger {
+    After(Duration),
+    EndOfTrack,
+    EndOfAlbum,
+}
+
+impl FromStr for SleepTrigger {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "track" => Ok(SleepTrigger::EndOfTrack),
+            "album" => Ok(SleepTrigger::EndOfAlbum),
+            _ => parse_duration(s).map(SleepTrigger::After)
+                .map_err(|_| anyhow!("Expected a duration such as 30m, \"track\" or \"album\": {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FadeCurve {
+    Linear,
+    /// Drops slowly at first, then faster; sounds even to the ear.
+    EaseOut,
+    /// Drops quickly at first, then lingers at low volume.
+    Exponential,
+}
+
+impl FadeCurve {
+    /// Fraction of the original volume left at `progress` (0.0 to 1.0) through the fade.
+    fn level(&self, progress: f64) -> f64 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => 1.0 - progress,
+            FadeCurve::EaseOut => 1.0 - progress * progress,
+            FadeCurve::Exponential => (1.0 - progress) * (-3.0 * progress).exp(),
+        }
+    }
+}
+
+impl FromStr for FadeCurve {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "linear" => Ok(FadeCurve::Linear),
+            "ease-out" => Ok(FadeCurve::EaseOut),
+            "exponential" => Ok(FadeCurve::Exponential),
+            _ => bail!("Unknown fade curve: {} (expected linear, ease-out or exponential)", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CancelReason {
+    PlaybackStopped,
+    PlaybackPaused,
+    DeviceChanged,
+    VolumeChanged,
+    TrackChanged,
+    AlbumChanged,
+}
+
+impl fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            CancelReason::PlaybackStopped => "playback stopped",
+            CancelReason::PlaybackPaused => "playback was paused",
+            CancelReason::DeviceChanged => "playback moved to another device",
+            CancelReason::VolumeChanged => "volume was changed manually",
+            CancelReason::TrackChanged => "track was changed manually",
+            CancelReason::AlbumChanged => "album was changed",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SleepOutcome {
+    Paused,
+    Cancelled(CancelReason),
+}
+
+impl fmt::Display for SleepOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SleepOutcome::Paused => write!(f, "Paused"),
+            SleepOutcome::Cancelled(reason) => write!(f, "Cancelled: {}", reason),
+        }
+    }
+}
+
+/// What the timer expects the player to look like while it is in charge.
+struct Expected {
+    device_id: String,
+    track_uri: Option<String>,
+    context_uri: Option<String>,
+    volume: u32,
+    /// When the track the timer stops after ends, once the fade towards it has begun.
+    boundary: Option<Instant>,
+}
+
+/// What a look at the player found.
+enum Check {
+    Unchanged,
+    /// Playback went on past the end of the track the timer stops after.
+    PastBoundary,
+    Cancel(CancelReason),
+}
+
+pub struct SleepTimer {
+    tokens: TokenKeeper,
+    device_id: String,
+    trigger: SleepTrigger,
+    fade: Duration,
+    curve: FadeCurve,
+    steps: u32,
+}
+
+impl SleepTimer {
+    pub fn new(config: &Rc<SpotifyConfig>, device_id: &str, trigger: SleepTrigger, fade: Duration, curve: FadeCurve) -> Self {
+        Self {
+            tokens: TokenKeeper::new(config),
+            device_id: device_id.to_owned(),
+            trigger,
+            fade,
+            curve,
+            steps: 20,
+        }
+    }
+
+    pub async fn run(&self) -> Result<SleepOutcome> {
+        let state = self.current_state().await?
+            .filter(|state| state.device.id.as_deref() == Some(self.device_id.as_str()))
+            .ok_or_else(|| anyhow!("Nothing is playing on the device"))?;
+        let original_volume = state.device.volume_percent
+            .ok_or_else(|| anyhow!("The device does not report its volume"))?;
+        let mut expected = Expected {
+            device_id: self.device_id.clone(),
+            track_uri: item_uri(&state),
+            context_uri: state.context.as_ref().map(|context| context.uri.clone()),
+            volume: original_volume,
+            boundary: None,
+        };
+
+        if let Some(reason) = self.wait_for_fade(&mut expected, state).await? {
+            return Ok(SleepOutcome::Cancelled(reason));
+        }
+
+        // With a track boundary, the fade ends there even when less time is left than the fade.
+        let fade_started = Instant::now();
+        let fade_ends = expected.boundary.unwrap_or(fade_started + self.fade);
+        let step = fade_ends.saturating_duration_since(fade_started) / self.steps;
+        let faded: Result<SleepOutcome> = async {
+            for index in 1..=self.steps {
+                let level = self.curve.level(index as f64 / self.steps as f64);
+                let volume = (original_volume as f64 * level).round() as u32;
+                set_volume(&self.tokens.config().await, &self.device_id, volume).await?;
+                expected.volume = volume;
+                // Sleeping until each step's deadline keeps request latency from stretching the fade.
+                sleep_until(fade_started + step * index).await;
+                if index == self.steps {
+                    break;
+                }
+
+                match self.check(&mut expected, step).await? {
+                    Check::Unchanged => {}
+                    Check::PastBoundary => break,
+                    Check::Cancel(reason) => return Ok(SleepOutcome::Cancelled(reason)),
+                }
+            }
+            pause(&self.tokens.config().await, &self.device_id).await?;
+            Ok(SleepOutcome::Paused)
+        }.await;
+
+        // Leave a volume the user chose alone; otherwise undo the fade, even when it failed part way.
+        if let Ok(SleepOutcome::Cancelled(CancelReason::VolumeChanged)) = faded {
+            return faded;
+        }
+        let restored = set_volume(&self.tokens.config().await, &self.device_id, original_volume).await;
+        let outcome = faded?;
+        restored?;
+        Ok(outcome)
+    }
+
+    /// Waits until the fade should begin, or returns why the timer was cancelled.
+    async fn wait_for_fade(&self, expected: &mut Expected, state: PlaybackState) -> Result<Option<CancelReason>> {
+        let started = Instant::now();
+        let last_track_uri = match self.trigger {
+            SleepTrigger::EndOfAlbum => Some(self.last_album_track(&state).await?),
+            _ => None,
+        };
+
+        let mut state = state;
+        let mut polled = Instant::now();
+        loop {
+            let remaining = match self.trigger {
+                SleepTrigger::After(duration) => {
+                    duration.saturating_sub(self.fade).saturating_sub(started.elapsed())
+                }
+                SleepTrigger::EndOfTrack => remaining_in_track(&state, polled).saturating_sub(self.fade),
+                SleepTrigger::EndOfAlbum => {
+                    if item_uri(&state) == last_track_uri {
+                        remaining_in_track(&state, polled).saturating_sub(self.fade)
+                    } else {
+                        WAIT_POLL_INTERVAL
+                    }
+                }
+            };
+            if remaining == Duration::from_secs(0) {
+                if !matches!(self.trigger, SleepTrigger::After(_)) {
+                    expected.boundary = Some(Instant::now() + remaining_in_track(&state, polled));
+                }
+                return Ok(None);
+            }
+
+            let interval = remaining.min(WAIT_POLL_INTERVAL);
+            sleep(interval).await;
+            if let Check::Cancel(reason) = self.check(expected, interval).await? {
+                return Ok(Some(reason));
+            }
+            state = match self.current_state().await? {
+                Some(state) => state,
+                None => return Ok(Some(CancelReason::PlaybackStopped)),
+            };
+            polled = Instant::now();
+        }
+    }
+
+    /// Compares the player to what the timer expects and tells whether it should stop.
+    async fn check(&self, expected: &mut Expected, elapsed: Duration) -> Result<Check> {
+        let state = match self.current_state().await? {
+            Some(state) => state,
+            None => return Ok(Check::Cancel(CancelReason::PlaybackStopped)),
+        };
+
+        if state.device.id.as_deref() != Some(expected.device_id.as_str()) {
+            return Ok(Check::Cancel(CancelReason::DeviceChanged));
+        }
+        if !state.is_playing {
+            return Ok(Check::Cancel(CancelReason::PlaybackPaused));
+        }
+        if state.device.volume_percent.is_some_and(|volume| volume.abs_diff(expected.volume) > 1) {
+            return Ok(Check::Cancel(CancelReason::VolumeChanged));
+        }
+
+        let context_uri = state.context.as_ref().map(|context| context.uri.clone());
+        let track_uri = item_uri(&state);
+        // Reaching the boundary a little early or late is the fade doing its job, not a manual change.
+        let moved_on = track_uri != expected.track_uri || context_uri != expected.context_uri;
+        let slack = Duration::from_millis(NATURAL_ADVANCE_SLACK_MS);
+        if moved_on && expected.boundary.is_some_and(|boundary| Instant::now() + slack >= boundary) {
+            return Ok(Check::PastBoundary);
+        }
+        if matches!(self.trigger, SleepTrigger::EndOfAlbum) && context_uri != expected.context_uri {
+            return Ok(Check::Cancel(CancelReason::AlbumChanged));
+        }
+        if track_uri != expected.track_uri {
+            let advanced_naturally = state.progress_ms
+                .is_some_and(|progress| (progress as u64) < elapsed.as_millis() as u64 + NATURAL_ADVANCE_SLACK_MS);
+            let track_trigger = matches!(self.trigger, SleepTrigger::EndOfTrack);
+            if track_trigger || !advanced_naturally {
+                return Ok(Check::Cancel(CancelReason::TrackChanged));
+            }
+            expected.track_uri = track_uri;
+        }
+        Ok(Check::Unchanged)
+    }
+
+    async fn current_state(&self) -> Result<Option<PlaybackState>> {
+        get_playback_state(&self.tokens.config().await).await
+    }
+
+    async fn last_album_track(&self, state: &PlaybackState) -> Result<String> {
+        if state.shuffle_state {
+            bail!("Cannot tell when the album ends while shuffle is on")
+        }
+        let album_id = state.context.as_ref()
+            .and_then(|context| context.uri.strip_prefix("spotify:album:"))
+            .ok_or_else(|| anyhow!("Not playing an album"))?;
+        let uris = album_track_uris(&self.tokens.config().await, album_id).await?;
+        uris.into_iter().last()
+            .ok_or_else(|| anyhow!("The album has no tracks"))
+    }
+}
+
+fn item_uri(state: &PlaybackState) -> Option<String> {
+    state.item.as_ref().map(|item| item.uri().to_owned())
+}
+
+fn remaining_in_track(state: &PlaybackState, polled: Instant) -> Duration {
+    let duration = state.item.as_ref().map_or(0, |item| item.duration_ms()) as u64;
+    let progress = state.progress_ms.unwrap_or(0) as u64 + polled.elapsed().as_millis() as u64;
+    Duration::from_millis(duration.saturating_sub(progress))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [FadeCurve; 3] = [FadeCurve::Linear, FadeCurve::EaseOut, FadeCurve::Exponential];
+
+    #[test]
+    fn curves_start_full_and_end_silent() {
+        for curve in CURVES.iter() {
+            assert_eq!(curve.level(0.0), 1.0, "{:?}", curve);
+            assert_eq!(curve.level(1.0), 0.0, "{:?}", curve);
+            // Progress outside the fade is clamped to it.
+            assert_eq!(curve.level(-0.5), 1.0, "{:?}", curve);
+            assert_eq!(curve.level(1.5), 0.0, "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn curves_only_go_down() {
+        for curve in CURVES.iter() {
+            let levels = (0..=100).map(|step| curve.level(step as f64 / 100.0)).collect::<Vec<_>>();
+            assert!(levels.windows(2).all(|pair| pair[1] < pair[0]), "{:?}: {:?}", curve, levels);
+        }
+    }
+
+    #[test]
+    fn curve_shapes() {
+        assert!(FadeCurve::EaseOut.level(0.5) > FadeCurve::Linear.level(0.5));
+        assert!(FadeCurve::Exponential.level(0.5) < FadeCurve::Linear.level(0.5));
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::api::{expand_uris, get_playback_state, pause, set_repeat, set_shuffle, set_volume, start_playback, DeviceResolver, PlaybackOffset, PlaybackRequest};
+use crate::config::SpotifyConfig;
+use crate::objects::{Device, PlayableItem, PlaybackState};
+
+/// Everything needed to put the player back the way it was.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub taken_at: DateTime<Utc>,
+    pub device_id: Option<String>,
+    pub device_name: String,
+    pub context_uri: Option<String>,
+    pub item_uri: String,
+    /// The track's URI in albums and playlists when Spotify relinked it to another version.
+    #[serde(default)]
+    pub linked_from_uri: Option<String>,
+    pub progress_ms: u32,
+    pub is_playing: bool,
+    pub shuffle: bool,
+    pub repeat: String,
+    pub volume_percent: Option<u32>,
+}
+
+impl Snapshot {
+    pub async fn take(config: &Rc<SpotifyConfig>) -> Result<Self> {
+        let state = get_playback_state(config).await?
+            .ok_or_else(|| anyhow!("Nothing is playing"))?;
+        Self::from_state(&state)
+    }
+
+    fn from_state(state: &PlaybackState) -> Result<Self> {
+        let item = state.item.as_ref()
+            .ok_or_else(|| anyhow!("The current item cannot be captured"))?;
+        let linked_from_uri = match item {
+            PlayableItem::Track(track) => track.linked_from.as_ref().map(|linked| linked.uri.clone()),
+            PlayableItem::Episode(_) => None,
+        };
+
+        Ok(Self {
+            taken_at: Utc::now(),
+            device_id: state.device.id.clone(),
+            device_name: state.device.name.clone(),
+            context_uri: state.context.as_ref().map(|context| context.uri.clone()),
+            item_uri: item.uri().to_owned(),
+            linked_from_uri,
+            progress_ms: state.progress_ms.unwrap_or(0),
+            is_playing: state.is_playing,
+            shuffle: state.shuffle_state,
+            repeat: state.repeat_state.clone(),
+            volume_percent: state.device.volume_percent,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)? + "\n";
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Plays the captured item from the captured position and reapplies the player settings.
+    /// Returns the device playback was restored on.
+    pub async fn restore(&self, config: &Rc<SpotifyConfig>) -> Result<Device> {
+        let device = self.find_device(config).await?;
+        let device_id = device.id.clone()
+            .ok_or_else(|| anyhow!("Device \"{}\" has no id", device.name))?;
+
+        let request = match self.context_holding_item(config).await? {
+            Some((context_uri, item_uri)) => PlaybackRequest::context(context_uri)
+                .with_offset(PlaybackOffset::Uri(item_uri.to_owned())),
+            None => PlaybackRequest::uris(vec![self.item_uri.clone()]),
+        };
+        start_playback(config, &device_id, request.with_position_ms(self.progress_ms)).await?;
+
+        // An inactive device rejects player settings until something plays on it.
+        set_shuffle(config, &device_id, self.shuffle).await?;
+        set_repeat(config, &device_id, &self.repeat).await?;
+        if let Some(volume_percent) = self.volume_percent {
+            set_volume(config, &device_id, volume_percent).await?;
+        }
+        if !self.is_playing {
+            pause(config, &device_id).await?;
+        }
+
+        Ok(device)
+    }
+
+    /// Returns the captured context and the item's URI in it if the item is still there; a track may have been
+    /// removed from a playlist since. Only albums and playlists can be listed; other contexts are assumed to still hold the item.
+    async fn context_holding_item(&self, config: &Rc<SpotifyConfig>) -> Result<Option<(&str, &str)>> {
+        let context_uri = match self.context_uri.as_deref() {
+            Some(context_uri) => context_uri,
+            None => return Ok(None),
+        };
+        if !context_uri.starts_with("spotify:album:") && !context_uri.starts_with("spotify:playlist:") {
+            let item_uri = self.linked_from_uri.as_deref().unwrap_or(&self.item_uri);
+            return Ok(Some((context_uri, item_uri)));
+        }
+        let uris = expand_uris(config, vec![context_uri.to_owned()]).await?;
+        Ok(self.uri_in(&uris).map(|item_uri| (context_uri, item_uri)))
+    }
+
+    /// The URI under which `uris` list the captured item, which for a relinked track is the one it was linked from.
+    fn uri_in(&self, uris: &[String]) -> Option<&str> {
+        [Some(&self.item_uri), self.linked_from_uri.as_ref()].iter()
+            .flatten()
+            .find(|uri| uris.contains(uri))
+            .map(|uri| uri.as_str())
+    }
+
+    /// Finds the captured device by id, or by name if it has reconnected with a new id.
+    async fn find_device(&self, config: &Rc<SpotifyConfig>) -> Result<Device> {
+        let resolver = DeviceResolver::fetch(config).await?;
+        let by_id = self.device_id.as_deref()
+            .and_then(|device_id| resolver.devices().iter().find(|device| device.id.as_deref() == Some(device_id)));
+        match by_id {
+            Some(device) => Ok(device.clone()),
+            None => resolver.resolve(Some(&self.device_name)).cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn state(linked_from: serde_json::Value) -> PlaybackState {
+        serde_json::from_value(json!({
+            "device": {
+                "id": "abc123", "is_active": true, "is_private_session": false, "is_restricted": false,
+                "name": "Kitchen", "type": "Speaker", "volume_percent": 50,
+            },
+            "repeat_state": "off",
+            "shuffle_state": false,
+            "context": { "uri": "spotify:playlist:p1", "type": "playlist", "href": null },
+            "timestamp": 0,
+            "progress_ms": 1_000,
+            "is_playing": true,
+            "item": {
+                "type": "track", "id": "t2", "linked_from": linked_from, "href": null,
+                "album": null, "artists": [],
+                "name": "Song", "disc_number": 1, "track_number": 1, "duration_ms": 200_000, "uri": "spotify:track:t2",
+            },
+            "currently_playing_type": "track",
+        })).unwrap()
+    }
+
+    #[test]
+    fn relinked_tracks_are_found_under_their_original_uri() {
+        let snapshot = Snapshot::from_state(&state(json!({ "id": "t1", "uri": "spotify:track:t1" }))).unwrap();
+        assert_eq!(snapshot.item_uri, "spotify:track:t2");
+        assert_eq!(snapshot.linked_from_uri.as_deref(), Some("spotify:track:t1"));
+
+        let playlist = ["spotify:track:t0", "spotify:track:t1"].map(String::from);
+        assert_eq!(snapshot.uri_in(&playlist), Some("spotify:track:t1"));
+        assert_eq!(snapshot.uri_in(&[String::from("spotify:track:t0")]), None);
+    }
+
+    #[test]
+    fn tracks_are_found_under_their_uri() {
+        let snapshot = Snapshot::from_state(&state(json!(null))).unwrap();
+        assert!(snapshot.linked_from_uri.is_none());
+        assert_eq!(snapshot.uri_in(&[String::from("spotify:track:t2")]), Some("spotify:track:t2"));
+        assert_eq!(snapshot.uri_in(&[String::from("spotify:track:t1")]), None);
+    }
+
+    #[test]
+    fn older_snapshots_load() {
+        let snapshot = serde_json::from_value::<Snapshot>(json!({
+            "taken_at": "2024-06-01T07:30:00Z", "device_id": "abc123", "device_name": "Kitchen",
+            "context_uri": null, "item_uri": "spotify:track:t1", "progress_ms": 0, "is_playing": true,
+            "shuffle": false, "repeat": "off", "volume_percent": null,
+        })).unwrap();
+        assert!(snapshot.linked_from_uri.is_none());
+    }
+}
@@ -0,0 +1,525 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use anyhow::{Error, Result, anyhow};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone, Utc};
+use rusqlite::{Connection, Row, params};
+use serde_derive::Serialize;
+
+use crate::history::{HistoryStore, ALBUM_ARTISTS, TRACK_ARTISTS};
+
+/// Tracks played at least this often count as favorites.
+const FAVORITE_MINIMUM_PLAYS: u32 = 5;
+
+/// Favorites not played for this many days count as forgotten.
+const FORGOTTEN_AFTER_DAYS: i64 = 90;
+
+const PLAYED_MS: &str = "COALESCE(p.ms_played, t.duration_ms)";
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Period {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Period {
+    pub fn last_days(days: i64) -> Self {
+        Self {
+            since: Some(Utc::now() - Duration::days(days)),
+            until: None,
+        }
+    }
+
+    pub fn all() -> Self {
+        Self {
+            since: None,
+            until: None,
+        }
+    }
+
+    fn bounds(&self) -> (i64, i64) {
+        (
+            self.since.map_or(i64::MIN, |since| since.timestamp_millis()),
+            self.until.map_or(i64::MAX, |until| until.timestamp_millis()),
+        )
+    }
+}
+
+/// A period ending now, as named on the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecentPeriod {
+    Day,
+    Week,
+    Month,
+    Year,
+    All,
+}
+
+impl RecentPeriod {
+    pub fn period(self) -> Period {
+        match self {
+            RecentPeriod::Day => Period::last_days(1),
+            RecentPeriod::Week => Period::last_days(7),
+            RecentPeriod::Month => Period::last_days(30),
+            RecentPeriod::Year => Period::last_days(365),
+            RecentPeriod::All => Period::all(),
+        }
+    }
+}
+
+impl FromStr for RecentPeriod {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "day" => Ok(RecentPeriod::Day),
+            "week" => Ok(RecentPeriod::Week),
+            "month" => Ok(RecentPeriod::Month),
+            "year" => Ok(RecentPeriod::Year),
+            "all" => Ok(RecentPeriod::All),
+            other => Err(anyhow!("Unknown period: {} (expected day, week, month, year or all)", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Table,
+    Json,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn render(self, report: &Report) -> Result<String> {
+        match self {
+            ReportFormat::Table => Ok(render_table(report)),
+            ReportFormat::Json => Ok(serde_json::to_string_pretty(report)?),
+            ReportFormat::Html => Ok(render_html(report)),
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "table" => Ok(ReportFormat::Table),
+            "json" => Ok(ReportFormat::Json),
+            "html" => Ok(ReportFormat::Html),
+            other => Err(anyhow!("Unknown format: {} (expected table, json or html)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ranked {
+    pub name: String,
+    pub detail: Option<String>,
+    pub plays: u32,
+    pub ms_played: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimeTotal {
+    pub label: String,
+    pub plays: u32,
+    pub ms_played: i64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Streaks {
+    pub current_days: u32,
+    pub longest_days: u32,
+    pub longest_from: Option<NaiveDate>,
+    pub longest_to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Dated {
+    pub name: String,
+    pub detail: Option<String>,
+    pub plays: u32,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub period: Period,
+    pub plays: u32,
+    pub ms_played: i64,
+    pub top_tracks: Vec<Ranked>,
+    pub top_artists: Vec<Ranked>,
+    pub top_albums: Vec<Ranked>,
+    pub top_genres: Vec<Ranked>,
+    pub per_day: Vec<TimeTotal>,
+    pub per_hour: Vec<TimeTotal>,
+    pub streaks: Streaks,
+    pub first_listens: Vec<Dated>,
+    pub forgotten_favorites: Vec<Dated>,
+}
+
+impl Report {
+    pub fn build(store: &HistoryStore, period: Period, limit: usize) -> Result<Self> {
+        let connection = store.connection();
+        let (since, until) = period.bounds();
+        let limit = limit as i64;
+
+        let (plays, ms_played) = connection.query_row(
+            &format!("SELECT COUNT(*), COALESCE(SUM({}), 0) FROM plays p JOIN tracks t ON t.id = p.track_id
+                      WHERE p.started_at >= ?1 AND p.started_at < ?2", PLAYED_MS),
+            params![since, until],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let top_tracks = query_ranked(connection, &format!(
+            "SELECT t.name, {}, COUNT(*) AS plays, SUM({}) AS ms FROM plays p JOIN tracks t ON t.id = p.track_id
+             WHERE p.started_at >= ?1 AND p.started_at < ?2
+             GROUP BY t.id ORDER BY plays DESC, ms DESC LIMIT ?3", TRACK_ARTISTS, PLAYED_MS), since, until, limit)?;
+        let top_artists = query_ranked(connection, &format!(
+            "SELECT a.name, NULL, COUNT(*) AS plays, SUM({}) AS ms FROM plays p
+             JOIN tracks t ON t.id = p.track_id
+             JOIN track_artists ta ON ta.track_id = t.id JOIN artists a ON a.id = ta.artist_id
+             WHERE p.started_at >= ?1 AND p.started_at < ?2
+             GROUP BY a.id ORDER BY plays DESC, ms DESC LIMIT ?3", PLAYED_MS), since, until, limit)?;
+        let top_albums = query_ranked(connection, &format!(
+            "SELECT al.name, {}, COUNT(*) AS plays, SUM({}) AS ms FROM plays p
+             JOIN tracks t ON t.id = p.track_id JOIN albums al ON al.id = t.album_id
+             WHERE p.started_at >= ?1 AND p.started_at < ?2
+             GROUP BY al.id ORDER BY plays DESC, ms DESC LIMIT ?3", ALBUM_ARTISTS, PLAYED_MS), since, until, limit)?;
+        let top_genres = query_ranked(connection, &format!(
+            "SELECT genre, NULL, COUNT(*) AS plays, SUM(ms) AS ms FROM (
+                 SELECT DISTINCT p.id, g.genre, {} AS ms FROM plays p
+                 JOIN tracks t ON t.id = p.track_id
+                 JOIN track_artists ta ON ta.track_id = t.id JOIN artist_genres g ON g.artist_id = ta.artist_id
+                 WHERE p.started_at >= ?1 AND p.started_at < ?2)
+             GROUP BY genre ORDER BY plays DESC, ms DESC LIMIT ?3", PLAYED_MS), since, until, limit)?;
+
+        let per_day = query_totals(connection, "%Y-%m-%d", since, until)?;
+        let mut per_hour = query_totals(connection, "%H", since, until)?;
+        for hour in 0..24 {
+            let label = format!("{:02}", hour);
+            if !per_hour.iter().any(|total| total.label == label) {
+                per_hour.push(TimeTotal { label, plays: 0, ms_played: 0 });
+            }
+        }
+        per_hour.sort_by(|a, b| a.label.cmp(&b.label));
+
+        // The current streak may have started before the period, so it is found among all days up to its end.
+        let days = query_totals(connection, "%Y-%m-%d", i64::MIN, until)?.iter()
+            .filter_map(|total| NaiveDate::parse_from_str(&total.label, "%Y-%m-%d").ok())
+            .collect::<Vec<_>>();
+        let first_day = period.since.map(|since| since.with_timezone(&Local).date_naive());
+        let last_day = period.until.map_or_else(Local::now, |until| until.with_timezone(&Local)).date_naive();
+        let streaks = compute_streaks(&days, first_day, last_day);
+
+        let first_listens = query_dated(connection, &format!(
+            "SELECT t.name, {}, (SELECT COUNT(*) FROM plays WHERE track_id = t.id), first FROM (
+                 SELECT track_id, MIN(started_at) AS first FROM plays GROUP BY track_id)
+             JOIN tracks t ON t.id = track_id
+             WHERE first >= ?1 AND first < ?2 ORDER BY first DESC LIMIT ?3", TRACK_ARTISTS),
+            params![since, until, limit])?;
+
+        let forgotten_before = period.until.unwrap_or_else(Utc::now) - Duration::days(FORGOTTEN_AFTER_DAYS);
+        let forgotten_favorites = query_dated(connection, &format!(
+            "SELECT t.name, {}, plays, last FROM (
+                 SELECT track_id, COUNT(*) AS plays, MAX(started_at) AS last FROM plays GROUP BY track_id)
+             JOIN tracks t ON t.id = track_id
+             WHERE plays >= ?1 AND last < ?2 ORDER BY plays DESC, last DESC LIMIT ?3", TRACK_ARTISTS),
+            params![FAVORITE_MINIMUM_PLAYS, forgotten_before.timestamp_millis(), limit])?;
+
+        Ok(Self {
+            period,
+            plays,
+            ms_played,
+            top_tracks,
+            top_artists,
+            top_albums,
+            top_genres,
+            per_day,
+            per_hour,
+            streaks,
+            first_listens,
+            forgotten_favorites,
+        })
+    }
+}
+
+fn query_ranked(connection: &Connection, sql: &str, since: i64, until: i64, limit: i64) -> Result<Vec<Ranked>> {
+    let mut statement = connection.prepare(sql)?;
+    let rows = statement.query_map(params![since, until, limit], |row| {
+        Ok(Ranked {
+            name: row.get(0)?,
+            detail: row.get(1)?,
+            plays: row.get(2)?,
+            ms_played: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn query_totals(connection: &Connection, format: &str, since: i64, until: i64) -> Result<Vec<TimeTotal>> {
+    let sql = format!(
+        "SELECT strftime(?3, p.started_at / 1000, 'unixepoch', 'localtime') AS label, COUNT(*), SUM({})
+         FROM plays p JOIN tracks t ON t.id = p.track_id
+         WHERE p.started_at >= ?1 AND p.started_at < ?2
+         GROUP BY label ORDER BY label", PLAYED_MS);
+    let mut statement = connection.prepare(&sql)?;
+    let rows = statement.query_map(params![since, until, format], |row| {
+        Ok(TimeTotal {
+            label: row.get(0)?,
+            plays: row.get(1)?,
+            ms_played: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn query_dated<P: rusqlite::Params>(connection: &Connection, sql: &str, parameters: P) -> Result<Vec<Dated>> {
+    let mut statement = connection.prepare(sql)?;
+    let rows = statement.query_map(parameters, |row: &Row| {
+        let at: i64 = row.get(3)?;
+        Ok(Dated {
+            name: row.get(0)?,
+            detail: row.get(1)?,
+            plays: row.get(2)?,
+            at: Utc.timestamp_millis_opt(at).single()
+                .ok_or(rusqlite::Error::IntegralValueOutOfRange(3, at))?,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+/// Finds runs of consecutive days in sorted, distinct dates, ignoring those after `last_day`.
+/// The longest streak only counts days from `first_day` on, while the current streak is the
+/// run up to `last_day`, however early it started. The current streak still counts if it ends
+/// the day before `last_day`, since that day may not be over.
+fn compute_streaks(days: &[NaiveDate], first_day: Option<NaiveDate>, last_day: NaiveDate) -> Streaks {
+    let mut streaks = Streaks::default();
+    let mut run_start = None;
+    let mut run_length = 0;
+    let mut run_length_in_period = 0;
+    let mut previous: Option<NaiveDate> = None;
+
+    for &day in days.iter().take_while(|day| **day <= last_day) {
+        if previous.is_some_and(|previous| previous.succ_opt() == Some(day)) {
+            run_length += 1;
+        } else {
+            run_length = 1;
+            run_length_in_period = 0;
+        }
+        if first_day.is_none_or(|first_day| day >= first_day) {
+            if run_length_in_period == 0 {
+                run_start = Some(day);
+            }
+            run_length_in_period += 1;
+        }
+        if run_length_in_period > streaks.longest_days {
+            streaks.longest_days = run_length_in_period;
+            streaks.longest_from = run_start;
+            streaks.longest_to = Some(day);
+        }
+        previous = Some(day);
+    }
+
+    if previous.is_some_and(|last| last == last_day || last.succ_opt() == Some(last_day)) {
+        streaks.current_days = run_length;
+    }
+    streaks
+}
+
+fn format_duration(ms: i64) -> String {
+    let minutes = ms / 60_000;
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+fn format_period(period: &Period) -> String {
+    let format = |time: Option<DateTime<Utc>>, default: &str| {
+        time.map_or(default.to_owned(), |time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+    };
+    format!("{} - {}", format(period.since, "beginning"), format(period.until, "now"))
+}
+
+fn format_name(name: &str, detail: &Option<String>) -> String {
+    match detail {
+        Some(detail) => format!("{} ({})", name, detail),
+        None => name.to_owned(),
+    }
+}
+
+pub fn render_table(report: &Report) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Period: {}", format_period(&report.period));
+    let _ = writeln!(out, "Plays: {}, listening time: {}", report.plays, format_duration(report.ms_played));
+
+    let sections = [
+        ("Top tracks", &report.top_tracks),
+        ("Top artists", &report.top_artists),
+        ("Top albums", &report.top_albums),
+        ("Top genres", &report.top_genres),
+    ];
+    for (title, ranking) in sections.iter() {
+        let _ = writeln!(out, "\n{}", title);
+        for (index, ranked) in ranking.iter().enumerate() {
+            let _ = writeln!(out, "{:>3}. {:>5} plays {:>8}  {}",
+                index + 1, ranked.plays, format_duration(ranked.ms_played), format_name(&ranked.name, &ranked.detail));
+        }
+    }
+
+    let totals = [("Listening per day", &report.per_day), ("Listening per hour", &report.per_hour)];
+    for (title, totals) in totals.iter() {
+        let _ = writeln!(out, "\n{}", title);
+        for total in totals.iter() {
+            let _ = writeln!(out, "{:>10} {:>5} plays {:>8}", total.label, total.plays, format_duration(total.ms_played));
+        }
+    }
+
+    let _ = writeln!(out, "\nStreaks");
+    let _ = writeln!(out, "  current: {} days", report.streaks.current_days);
+    let _ = write!(out, "  longest: {} days", report.streaks.longest_days);
+    if let (Some(from), Some(to)) = (report.streaks.longest_from, report.streaks.longest_to) {
+        let _ = write!(out, " ({} - {})", from, to);
+    }
+    let _ = writeln!(out);
+
+    let dated = [("First listens", &report.first_listens), ("Forgotten favorites", &report.forgotten_favorites)];
+    for (title, items) in dated.iter() {
+        let _ = writeln!(out, "\n{}", title);
+        for item in items.iter() {
+            let _ = writeln!(out, "  {} {:>5} plays  {}",
+                item.at.with_timezone(&Local).format("%Y-%m-%d"), item.plays, format_name(&item.name, &item.detail));
+        }
+    }
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const HTML_STYLE: &str = "
+body { font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #222; }
+h1 { font-size: 1.5em; } h2 { font-size: 1.2em; margin-top: 2em; }
+table { border-collapse: collapse; width: 100%; }
+td, th { padding: 0.2em 0.5em; text-align: left; border-bottom: 1px solid #eee; }
+td.number { text-align: right; white-space: nowrap; }
+.detail { color: #777; }
+.bar { background: #1db954; height: 0.8em; }
+";
+
+pub fn render_html(report: &Report) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Listening statistics</title><style>{}</style></head><body>\n", HTML_STYLE);
+    let _ = writeln!(out, "<h1>Listening statistics</h1>");
+    let _ = writeln!(out, "<p>{}<br>{} plays, {} of listening</p>",
+        escape_html(&format_period(&report.period)), report.plays, format_duration(report.ms_played));
+
+    let sections = [
+        ("Top tracks", &report.top_tracks),
+        ("Top artists", &report.top_artists),
+        ("Top albums", &report.top_albums),
+        ("Top genres", &report.top_genres),
+    ];
+    for (title, ranking) in sections.iter() {
+        let _ = writeln!(out, "<h2>{}</h2>\n<table>", title);
+        for (index, ranked) in ranking.iter().enumerate() {
+            let detail = ranked.detail.as_deref()
+                .map_or(String::new(), |detail| format!(" <span class=\"detail\">{}</span>", escape_html(detail)));
+            let _ = writeln!(out, "<tr><td class=\"number\">{}</td><td>{}{}</td><td class=\"number\">{} plays</td><td class=\"number\">{}</td></tr>",
+                index + 1, escape_html(&ranked.name), detail, ranked.plays, format_duration(ranked.ms_played));
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    let totals = [("Listening per day", &report.per_day), ("Listening per hour", &report.per_hour)];
+    for (title, totals) in totals.iter() {
+        let maximum = totals.iter().map(|total| total.ms_played).max().unwrap_or(0).max(1);
+        let _ = writeln!(out, "<h2>{}</h2>\n<table>", title);
+        for total in totals.iter() {
+            let _ = writeln!(out, "<tr><td class=\"number\">{}</td><td style=\"width: 70%\"><div class=\"bar\" style=\"width: {:.1}%\"></div></td><td class=\"number\">{}</td></tr>",
+                escape_html(&total.label), total.ms_played as f64 * 100.0 / maximum as f64, format_duration(total.ms_played));
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    let _ = writeln!(out, "<h2>Streaks</h2>\n<p>Current: {} days<br>Longest: {} days", report.streaks.current_days, report.streaks.longest_days);
+    if let (Some(from), Some(to)) = (report.streaks.longest_from, report.streaks.longest_to) {
+        let _ = write!(out, " ({} &ndash; {})", from, to);
+    }
+    let _ = writeln!(out, "</p>");
+
+    let dated = [("First listens", &report.first_listens), ("Forgotten favorites", &report.forgotten_favorites)];
+    for (title, items) in dated.iter() {
+        let _ = writeln!(out, "<h2>{}</h2>\n<table>", title);
+        for item in items.iter() {
+            let detail = item.detail.as_deref()
+                .map_or(String::new(), |detail| format!(" <span class=\"detail\">{}</span>", escape_html(detail)));
+            let _ = writeln!(out, "<tr><td class=\"number\">{}</td><td>{}{}</td><td class=\"number\">{} plays</td></tr>",
+                item.at.with_timezone(&Local).format("%Y-%m-%d"), escape_html(&item.name), detail, item.plays);
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    let _ = writeln!(out, "</body></html>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap()
+    }
+
+    fn dates(days: &[&str]) -> Vec<NaiveDate> {
+        days.iter().map(|day| date(day)).collect()
+    }
+
+    #[test]
+    fn no_listening() {
+        let streaks = compute_streaks(&[], None, date("2024-03-10"));
+        assert_eq!(streaks.current_days, 0);
+        assert_eq!(streaks.longest_days, 0);
+        assert_eq!(streaks.longest_from, None);
+    }
+
+    #[test]
+    fn longest_streak_and_its_dates() {
+        let days = dates(&["2024-03-01", "2024-03-02", "2024-03-03", "2024-03-05", "2024-03-06"]);
+        let streaks = compute_streaks(&days, None, date("2024-03-20"));
+        assert_eq!(streaks.longest_days, 3);
+        assert_eq!(streaks.longest_from, Some(date("2024-03-01")));
+        assert_eq!(streaks.longest_to, Some(date("2024-03-03")));
+        assert_eq!(streaks.current_days, 0);
+    }
+
+    #[test]
+    fn current_streak_may_end_today_or_yesterday() {
+        let days = dates(&["2024-03-08", "2024-03-09"]);
+        assert_eq!(compute_streaks(&days, None, date("2024-03-09")).current_days, 2);
+        assert_eq!(compute_streaks(&days, None, date("2024-03-10")).current_days, 2);
+        assert_eq!(compute_streaks(&days, None, date("2024-03-11")).current_days, 0);
+    }
+
+    #[test]
+    fn current_streak_reaches_back_before_the_period() {
+        let days = dates(&["2024-02-27", "2024-02-28", "2024-02-29", "2024-03-01", "2024-03-02"]);
+        let streaks = compute_streaks(&days, Some(date("2024-03-01")), date("2024-03-02"));
+        assert_eq!(streaks.current_days, 5);
+        assert_eq!(streaks.longest_days, 2);
+        assert_eq!(streaks.longest_from, Some(date("2024-03-01")));
+    }
+
+    #[test]
+    fn current_streak_is_measured_at_the_end_of_the_period() {
+        let days = dates(&["2024-01-10", "2024-01-11", "2024-01-12", "2024-03-01"]);
+        let streaks = compute_streaks(&days, Some(date("2024-01-01")), date("2024-01-12"));
+        assert_eq!(streaks.current_days, 3);
+        assert_eq!(streaks.longest_days, 3);
+        assert_eq!(streaks.longest_to, Some(date("2024-01-12")));
+    }
+}
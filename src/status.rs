@@ -0,0 +1,270 @@
+use std::env;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Error, Result, anyhow, bail};
+use serde_json::json;
+use tokio::time::{sleep, Instant};
+
+use crate::api::{get_playback_state, pause, resolve_device, set_volume, skip_to_next, skip_to_previous, start_playing};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::PlaybackState;
+use crate::template::Template;
+use crate::watcher::PlayerWatcher;
+
+/// How often to re-render in follow mode while playing, when the template shows progress.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time for Spotify to reflect a click before the status is fetched again.
+const CLICK_SETTLE: Duration = Duration::from_millis(500);
+
+const PLAYING_COLOR: &str = "#1db954";
+const PAUSED_COLOR: &str = "#888888";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatusFormat {
+    Plain,
+    /// JSON with `text`, `tooltip`, `class` and `percentage`, for `"return-type": "json"`.
+    Waybar,
+    Polybar,
+    /// `full_text`, `short_text` and `color` lines, or one line per update in follow mode.
+    I3blocks,
+    Tmux,
+}
+
+impl FromStr for StatusFormat {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "plain" => Ok(StatusFormat::Plain),
+            "waybar" => Ok(StatusFormat::Waybar),
+            "polybar" => Ok(StatusFormat::Polybar),
+            "i3blocks" => Ok(StatusFormat::I3blocks),
+            "tmux" => Ok(StatusFormat::Tmux),
+            other => Err(anyhow!("Unknown format \"{}\"; expected plain, waybar, polybar, i3blocks or tmux", other)),
+        }
+    }
+}
+
+pub struct StatusOptions {
+    pub format: StatusFormat,
+    pub template: Template,
+    /// Waybar tooltip; also the i3blocks short text.
+    pub tooltip: Template,
+    /// Shown instead of the template when nothing is playing.
+    pub stopped_text: String,
+}
+
+impl StatusOptions {
+    /// The status as one line, or several for one-shot i3blocks output.
+    pub fn render(&self, state: Option<&PlaybackState>, progress_ms: Option<u32>, follow: bool) -> String {
+        let class = match state {
+            Some(state) if state.is_playing => "playing",
+            Some(_) => "paused",
+            None => "stopped",
+        };
+        let escape = match self.format {
+            StatusFormat::Waybar => escape_pango,
+            StatusFormat::Tmux => escape_tmux,
+            StatusFormat::Polybar => escape_polybar,
+            StatusFormat::Plain | StatusFormat::I3blocks => single_line,
+        };
+        let text = match state {
+            Some(_) => self.template.render_escaped(state, progress_ms, escape),
+            None => self.stopped_text.clone(),
+        };
+
+        match self.format {
+            StatusFormat::Waybar => {
+                let tooltip = match state {
+                    Some(_) => self.tooltip.render_escaped(state, progress_ms, escape),
+                    None => String::new(),
+                };
+                let percentage = match (progress_ms, state.and_then(|state| state.item.as_ref())) {
+                    (Some(progress), Some(item)) if item.duration_ms() > 0 => progress as u64 * 100 / item.duration_ms() as u64,
+                    _ => 0,
+                };
+                json!({
+                    "text": text,
+                    "tooltip": tooltip,
+                    "class": class,
+                    "alt": class,
+                    "percentage": percentage,
+                }).to_string()
+            }
+            StatusFormat::I3blocks if !follow => {
+                let short_text = match state {
+                    Some(_) => self.tooltip.render_escaped(state, progress_ms, single_line),
+                    None => self.stopped_text.clone(),
+                };
+                let color = if class == "playing" { PLAYING_COLOR } else { PAUSED_COLOR };
+                format!("{}\n{}\n{}", single_line(&text), single_line(&short_text), color)
+            }
+            // Newlines in the template itself would split the status too.
+            _ => single_line(&text),
+        }
+    }
+}
+
+fn single_line(value: &str) -> String {
+    value.replace('\n', " ")
+}
+
+fn escape_pango(value: &str) -> String {
+    single_line(value)
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_tmux(value: &str) -> String {
+    single_line(value).replace('#', "##")
+}
+
+/// Polybar reads `%{...}` as formatting tags, so a title could otherwise inject actions.
+fn escape_polybar(value: &str) -> String {
+    single_line(value).replace('%', "%%")
+}
+
+/// Prints the status once. For i3blocks, a click passed in `BLOCK_BUTTON` is carried out first.
+pub async fn print_status(config: &Rc<SpotifyConfig>, options: &StatusOptions, device: Option<&str>) -> Result<()> {
+    if options.format == StatusFormat::I3blocks {
+        if let Some(action) = env::var("BLOCK_BUTTON").ok().and_then(|button| ClickAction::from_i3blocks_button(&button)) {
+            action.perform(config, device).await?;
+            sleep(CLICK_SETTLE).await;
+        }
+    }
+    let state = get_playback_state(config).await?;
+    let progress_ms = state.as_ref().and_then(|state| state.progress_ms);
+    println!("{}", options.render(state.as_ref(), progress_ms, false));
+    Ok(())
+}
+
+/// Prints a line whenever the status changes, from a single watcher, until stopped.
+/// Failed polls are logged and the last status is kept.
+pub async fn follow(config: &Rc<SpotifyConfig>, options: &StatusOptions) -> Result<()> {
+    let tokens = TokenKeeper::new(config);
+    let mut watcher = PlayerWatcher::new(config);
+    let mut next_poll = Instant::now();
+    let mut last_output = None;
+
+    loop {
+        if Instant::now() >= next_poll {
+            watcher.set_config(&tokens.config().await);
+            if let Err(e) = watcher.poll().await {
+                eprintln!("{:#}", e);
+            }
+            next_poll = Instant::now() + watcher.next_delay();
+        }
+
+        let output = options.render(watcher.state(), watcher.progress_ms(), true);
+        if last_output.as_ref() != Some(&output) {
+            println!("{}", output);
+            last_output = Some(output);
+        }
+
+        let ticking = options.template.uses_progress() && watcher.state().is_some_and(|state| state.is_playing);
+        let until_poll = next_poll.saturating_duration_since(Instant::now());
+        sleep(if ticking { until_poll.min(TICK_INTERVAL) } else { until_poll }).await;
+    }
+}
+
+/// What a click on the status bar does.
+#[derive(Clone, Copy, Debug)]
+pub enum ClickAction {
+    PlayPause,
+    Next,
+    Previous,
+    /// Change the volume by this many percentage points.
+    Volume(i32),
+}
+
+impl ClickAction {
+    /// Left click toggles playback, middle goes back, right skips and the wheel changes the volume.
+    pub fn from_i3blocks_button(button: &str) -> Option<Self> {
+        match button {
+            "1" => Some(ClickAction::PlayPause),
+            "2" => Some(ClickAction::Previous),
+            "3" => Some(ClickAction::Next),
+            "4" => Some(ClickAction::Volume(5)),
+            "5" => Some(ClickAction::Volume(-5)),
+            _ => None,
+        }
+    }
+
+    pub async fn perform(self, config: &Rc<SpotifyConfig>, device: Option<&str>) -> Result<()> {
+        let device = resolve_device(config, device).await?;
+        let device_id = device.id.as_deref()
+            .ok_or_else(|| anyhow!("Device \"{}\" has no id", device.name))?;
+        match self {
+            ClickAction::PlayPause => {
+                let playing = get_playback_state(config).await?
+                    .is_some_and(|state| state.is_playing && state.device.id.as_deref() == Some(device_id));
+                if playing {
+                    pause(config, device_id).await
+                } else {
+                    start_playing(config, device_id).await
+                }
+            }
+            ClickAction::Next => skip_to_next(config, device_id).await,
+            ClickAction::Previous => skip_to_previous(config, device_id).await,
+            ClickAction::Volume(change) => {
+                let volume = match device.volume_percent {
+                    Some(volume) => volume as i32,
+                    None => bail!("Device \"{}\" does not report its volume", device.name),
+                };
+                set_volume(config, device_id, (volume + change).clamp(0, 100) as u32).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn options(format: StatusFormat) -> StatusOptions {
+        StatusOptions {
+            format,
+            template: "%{{F#fff}}{title}%{{F-}}".parse().unwrap(),
+            tooltip: "{artist}".parse().unwrap(),
+            stopped_text: String::from("%{F#888}stopped%{F-}"),
+        }
+    }
+
+    fn state(title: &str) -> PlaybackState {
+        serde_json::from_value(json!({
+            "device": {
+                "id": "abc123", "is_active": true, "is_private_session": false, "is_restricted": false,
+                "name": "Kitchen", "type": "Speaker", "volume_percent": 50,
+            },
+            "repeat_state": "off",
+            "shuffle_state": false,
+            "context": null,
+            "timestamp": 0,
+            "progress_ms": 0,
+            "is_playing": true,
+            "item": {
+                "type": "track", "id": "t1", "linked_from": null, "href": null, "album": null, "artists": [],
+                "name": title, "disc_number": 1, "track_number": 1, "duration_ms": 200_000, "uri": "spotify:track:t1",
+            },
+            "currently_playing_type": "track",
+        })).unwrap()
+    }
+
+    #[test]
+    fn polybar_escapes_tags_in_values_only() {
+        let state = state("100% %{A1:rm -rf ~:}pwned%{A}\nnext");
+        let output = options(StatusFormat::Polybar).render(Some(&state), None, true);
+        assert_eq!(output, "%{F#fff}100%% %%{A1:rm -rf ~:}pwned%%{A} next%{F-}");
+    }
+
+    #[test]
+    fn stopped_text_is_not_escaped() {
+        assert_eq!(options(StatusFormat::Polybar).render(None, None, true), "%{F#888}stopped%{F-}");
+    }
+}
@@ -0,0 +1,268 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Error, Result, anyhow, bail};
+
+use crate::objects::{PlayableItem, PlaybackState};
+
+const FIELD_NAMES: [&str; 13] = [
+    "title", "artist", "album", "state", "icon", "progress", "duration",
+    "percent", "device", "volume", "shuffle", "repeat", "uri",
+];
+
+/// Text with `{field}` placeholders filled in from the playback state.
+/// `{field:N}` cuts the value to N characters; `{{` and `}}` are literal braces.
+#[derive(Clone, Debug)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Text(String),
+    Field {
+        name: &'static str,
+        max_width: Option<usize>,
+    },
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => bail!("Unclosed '{{' in template {:?}", source),
+                        }
+                    }
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(parse_field(&placeholder)?);
+                }
+                '}' => bail!("Unmatched '}}' in template {:?}; write '}}}}' for a literal brace", source),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self {
+            source: source.to_owned(),
+            segments,
+        })
+    }
+}
+
+fn parse_field(placeholder: &str) -> Result<Segment> {
+    let (name, max_width) = match placeholder.split_once(':') {
+        Some((name, width)) => {
+            let width = width.parse::<usize>()
+                .map_err(|_| anyhow!("Invalid width in {{{}}}", placeholder))?;
+            (name, Some(width))
+        }
+        None => (placeholder, None),
+    };
+    let name = FIELD_NAMES.iter()
+        .find(|field| **field == name)
+        .ok_or_else(|| anyhow!("Unknown template field {{{}}}; expected one of: {}", name, FIELD_NAMES.join(", ")))?;
+    Ok(Segment::Field { name, max_width })
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Template {
+    /// Whether the output changes as playback progresses, so that it needs re-rendering every second.
+    pub fn uses_progress(&self) -> bool {
+        self.segments.iter().any(|segment| matches!(segment, Segment::Field { name: "progress" | "percent", .. }))
+    }
+
+    pub fn render(&self, state: Option<&PlaybackState>, progress_ms: Option<u32>) -> String {
+        self.render_escaped(state, progress_ms, |value| value.to_owned())
+    }
+
+    /// Renders with `escape` applied to the field values only, so the template itself may contain markup.
+    pub fn render_escaped<F>(&self, state: Option<&PlaybackState>, progress_ms: Option<u32>, escape: F) -> String
+        where F: Fn(&str) -> String
+    {
+        let mut output = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Field { name, max_width } => {
+                    let value = field_value(name, state, progress_ms);
+                    let value = match max_width {
+                        Some(width) => truncate(&value, *width),
+                        None => value,
+                    };
+                    output.push_str(&escape(&value));
+                }
+            }
+        }
+        output
+    }
+}
+
+fn truncate(value: &str, width: usize) -> String {
+    if value.chars().count() <= width {
+        return value.to_owned();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut truncated = value.chars().take(width - 1).collect::<String>();
+    truncated.push('\u{2026}');
+    truncated
+}
+
+pub fn format_duration(ms: u32) -> String {
+    let seconds = ms / 1000;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn field_value(name: &str, state: Option<&PlaybackState>, progress_ms: Option<u32>) -> String {
+    let item = state.and_then(|state| state.item.as_ref());
+    match name {
+        "title" => item.map_or(String::new(), |item| item.name().to_owned()),
+        "artist" => match item {
+            Some(PlayableItem::Track(track)) => track.artist_names(),
+            Some(PlayableItem::Episode(episode)) => episode.show.as_ref().map_or(String::new(), |show| show.publisher.clone()),
+            None => String::new(),
+        },
+        "album" => match item {
+            Some(PlayableItem::Track(track)) => track.album.as_ref().map_or(String::new(), |album| album.name.clone()),
+            Some(PlayableItem::Episode(episode)) => episode.show.as_ref().map_or(String::new(), |show| show.name.clone()),
+            None => String::new(),
+        },
+        "state" => String::from(match state {
+            Some(state) if state.is_playing => "playing",
+            Some(_) => "paused",
+            None => "stopped",
+        }),
+        "icon" => String::from(match state {
+            Some(state) if state.is_playing => "\u{25b6}",
+            Some(_) => "\u{23f8}",
+            None => "\u{23f9}",
+        }),
+        "progress" => progress_ms.map_or(String::new(), format_duration),
+        "duration" => item.map_or(String::new(), |item| format_duration(item.duration_ms())),
+        "percent" => match (progress_ms, item.map(|item| item.duration_ms())) {
+            (Some(progress), Some(duration)) if duration > 0 => (progress as u64 * 100 / duration as u64).to_string(),
+            _ => String::new(),
+        },
+        "device" => state.map_or(String::new(), |state| state.device.name.clone()),
+        "volume" => state.and_then(|state| state.device.volume_percent).map_or(String::new(), |volume| volume.to_string()),
+        "shuffle" => state.map_or(String::new(), |state| String::from(if state.shuffle_state { "on" } else { "off" })),
+        "repeat" => state.map_or(String::new(), |state| state.repeat_state.clone()),
+        "uri" => item.map_or(String::new(), |item| item.uri().to_owned()),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn state(title: &str) -> PlaybackState {
+        serde_json::from_value(json!({
+            "device": {
+                "id": "abc123", "is_active": true, "is_private_session": false, "is_restricted": false,
+                "name": "Kitchen", "type": "Speaker", "volume_percent": 50,
+            },
+            "repeat_state": "off",
+            "shuffle_state": true,
+            "context": null,
+            "timestamp": 0,
+            "progress_ms": 0,
+            "is_playing": false,
+            "item": {
+                "type": "track", "id": "t1", "linked_from": null, "href": null, "album": null,
+                "artists": [
+                    { "id": "r1", "href": null, "name": "Singer", "uri": "spotify:artist:r1" },
+                    { "id": "r2", "href": null, "name": "Band", "uri": "spotify:artist:r2" },
+                ],
+                "name": title, "disc_number": 1, "track_number": 1, "duration_ms": 3_725_000, "uri": "spotify:track:t1",
+            },
+            "currently_playing_type": "track",
+        })).unwrap()
+    }
+
+    fn render(template: &str, state: Option<&PlaybackState>, progress_ms: Option<u32>) -> String {
+        template.parse::<Template>().unwrap().render(state, progress_ms)
+    }
+
+    fn parse_error(template: &str) -> String {
+        template.parse::<Template>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn fields_are_filled_in() {
+        let state = state("Song");
+        assert_eq!(
+            render("{icon} {artist} - {title} [{progress}/{duration} {percent}%] {device} {volume} {shuffle} {repeat}", Some(&state), Some(62_000)),
+            "\u{23f8} Singer, Band - Song [1:02/1:02:05 1%] Kitchen 50 on off",
+        );
+        assert_eq!(render("{title}{state}", None, None), "stopped");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let state = state("Song");
+        assert_eq!(render("{{{title}}} {{title}}", Some(&state), None), "{Song} {title}");
+    }
+
+    #[test]
+    fn width_cuts_values_with_an_ellipsis() {
+        let state = state("Très longue chanson");
+        assert_eq!(render("{title:5}|{title:19}|{title:0}", Some(&state), None), "Très\u{2026}|Très longue chanson|");
+    }
+
+    #[test]
+    fn escape_applies_to_values_only() {
+        let state = state("<b>");
+        let template = "<i>{title}</i>".parse::<Template>().unwrap();
+        assert_eq!(template.render_escaped(Some(&state), None, |value| value.replace('<', "&lt;")), "<i>&lt;b></i>");
+    }
+
+    #[test]
+    fn uses_progress() {
+        assert!("{percent}".parse::<Template>().unwrap().uses_progress());
+        assert!(!"{title} {duration}".parse::<Template>().unwrap().uses_progress());
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert_eq!(parse_error("{title"), "Unclosed '{' in template \"{title\"");
+        assert_eq!(parse_error("title}"), "Unmatched '}' in template \"title}\"; write '}}' for a literal brace");
+        assert_eq!(parse_error("{title:x}"), "Invalid width in {title:x}");
+        assert!(parse_error("{name}").starts_with("Unknown template field {name}; expected one of: title, artist"));
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+/// Parses an RFC 3339 timestamp, or a local "YYYY-MM-DD HH:MM[:SS]" or "YYYY-MM-DD" time.
+pub fn parse_timestamp(s: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| anyhow!("Unrecognized time: {}", s))?;
+
+    Local.from_local_datetime(&naive)
+        .earliest()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok_or_else(|| anyhow!("Time does not exist in the local time zone: {}", s))
+}
+
+/// Parses a duration such as "90", "90s", "45m" or "1h30m"; bare numbers are seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    if s.is_empty() {
+        bail!("Empty duration")
+    }
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut seconds = 0u64;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value = number.parse::<u64>()
+            .map_err(|_| anyhow!("Invalid duration: {}", s))?;
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => bail!("Invalid duration: {}", s),
+        };
+        seconds = value.checked_mul(unit)
+            .and_then(|value| seconds.checked_add(value))
+            .ok_or_else(|| anyhow!("Duration is too long: {}", s))?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        bail!("Invalid duration: {} (missing unit after {})", s, number)
+    }
+    Ok(Duration::from_secs(seconds))
+}
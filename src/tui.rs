@@ -0,0 +1,18 @@
+mod app;
+mod ui;
+
+use std::rc::Rc;
+
+use anyhow::Result;
+use tokio::task::LocalSet;
+
+use crate::config::SpotifyConfig;
+use self::app::App;
+
+/// Takes over the terminal until the user quits, restoring it afterwards.
+pub async fn run(config: &Rc<SpotifyConfig>) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = LocalSet::new().run_until(App::run(config, &mut terminal)).await;
+    ratatui::restore();
+    result
+}
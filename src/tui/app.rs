@@ -0,0 +1,435 @@
+use std::future::Future;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow, bail};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use ratatui::DefaultTerminal;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::spawn_local;
+use tokio::time::{interval, sleep};
+
+use crate::api::{enqueue_tracks, get_playback_state, get_queue, pause, seek, set_volume, skip_to_next, skip_to_previous, start_playback, start_playing, transfer_playback, DeviceResolver, PlaybackRequest, SearchAlbums, SearchArtists, SearchTracks};
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::{Device, PlayableItem, PlaybackState};
+use super::ui;
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+const PLAYBACK_REFRESH: Duration = Duration::from_secs(2);
+const LIST_REFRESH: Duration = Duration::from_secs(10);
+
+/// Spotify takes a moment to reflect a command in the playback state.
+const ACTION_SETTLE: Duration = Duration::from_millis(300);
+
+const SEEK_STEP_MS: i64 = 10_000;
+const VOLUME_STEP: i32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pane {
+    Devices,
+    Queue,
+    Search,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchKind {
+    Tracks,
+    Albums,
+    Artists,
+}
+
+impl SearchKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SearchKind::Tracks => "Tracks",
+            SearchKind::Albums => "Albums",
+            SearchKind::Artists => "Artists",
+        }
+    }
+
+    fn next(&self) -> Self {
+        match self {
+            SearchKind::Tracks => SearchKind::Albums,
+            SearchKind::Albums => SearchKind::Artists,
+            SearchKind::Artists => SearchKind::Tracks,
+        }
+    }
+}
+
+pub struct SearchResult {
+    pub uri: String,
+    pub label: String,
+}
+
+/// The outcome of network work that ran in the background, applied when it arrives.
+enum Update {
+    Playback(Option<Box<PlaybackState>>),
+    Lists(Vec<Device>, Vec<PlayableItem>),
+    Results(Vec<SearchResult>),
+    /// A player command went through, with an optional message for the status line.
+    Done(Option<String>),
+    Failed(anyhow::Error),
+}
+
+pub struct App {
+    tokens: Rc<TokenKeeper>,
+    updates: UnboundedSender<Update>,
+    pub playback: Option<PlaybackState>,
+    fetched_at: Instant,
+    pub devices: Vec<Device>,
+    pub queue: Vec<PlayableItem>,
+    pub results: Vec<SearchResult>,
+    pub search_kind: SearchKind,
+    pub query: String,
+    pub editing: bool,
+    pub focus: Pane,
+    pub device_index: usize,
+    pub queue_index: usize,
+    pub result_index: usize,
+    pub status: Option<String>,
+    quit: bool,
+}
+
+impl App {
+    /// Must run on a `LocalSet`, as network work is spawned onto it.
+    pub async fn run(config: &Rc<SpotifyConfig>, terminal: &mut DefaultTerminal) -> Result<()> {
+        let (updates, mut received) = unbounded_channel();
+        let mut app = Self {
+            tokens: Rc::new(TokenKeeper::new(config)),
+            updates,
+            playback: None,
+            fetched_at: Instant::now(),
+            devices: vec![],
+            queue: vec![],
+            results: vec![],
+            search_kind: SearchKind::Tracks,
+            query: String::new(),
+            editing: false,
+            focus: Pane::Search,
+            device_index: 0,
+            queue_index: 0,
+            result_index: 0,
+            status: None,
+            quit: false,
+        };
+
+        let mut events = EventStream::new();
+        let mut redraw = interval(REDRAW_INTERVAL);
+        let mut playback_refresh = interval(PLAYBACK_REFRESH);
+        let mut list_refresh = interval(LIST_REFRESH);
+
+        loop {
+            terminal.draw(|frame| ui::draw(frame, &app))?;
+            if app.quit {
+                return Ok(());
+            }
+
+            tokio::select! {
+                event = events.next() => match event {
+                    Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                        let result = app.handle_key(key);
+                        app.report(result);
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.into()),
+                    None => return Ok(()),
+                },
+                Some(update) = received.recv() => app.apply(update),
+                _ = redraw.tick() => {}
+                _ = playback_refresh.tick() => app.refresh_playback(),
+                _ = list_refresh.tick() => app.refresh_lists(),
+            }
+        }
+    }
+
+    /// Position in the current item, extrapolated from the last fetch while playing.
+    pub fn progress_ms(&self) -> Option<u32> {
+        let state = self.playback.as_ref()?;
+        let progress = state.progress_ms?;
+        let elapsed = if state.is_playing { self.fetched_at.elapsed().as_millis() as u32 } else { 0 };
+        let duration = state.item.as_ref().map_or(u32::MAX, |item| item.duration_ms());
+        Some((progress + elapsed).min(duration))
+    }
+
+    fn report(&mut self, result: Result<()>) {
+        if let Err(e) = result {
+            self.status = Some(format!("{:#}", e));
+        }
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Playback(playback) => {
+                self.playback = playback.map(|state| *state);
+                self.fetched_at = Instant::now();
+            }
+            Update::Lists(devices, queue) => {
+                self.devices = devices;
+                self.device_index = self.device_index.min(self.devices.len().saturating_sub(1));
+                self.queue = queue;
+                self.queue_index = self.queue_index.min(self.queue.len().saturating_sub(1));
+            }
+            Update::Results(results) => {
+                self.results = results;
+                self.result_index = 0;
+            }
+            Update::Done(status) => {
+                if status.is_some() {
+                    self.status = status;
+                }
+                self.refresh_after_action();
+            }
+            Update::Failed(e) => self.report(Err(e)),
+        }
+    }
+
+    /// Runs network work in the background so that input and redraws never wait on it.
+    fn spawn(&self, work: impl Future<Output = Result<Update>> + 'static) {
+        let updates = self.updates.clone();
+        spawn_local(async move {
+            let update = work.await.unwrap_or_else(Update::Failed);
+            let _ = updates.send(update);
+        });
+    }
+
+    /// Sends a player command to the target device and refreshes once it went through.
+    fn command<F>(&self, status: Option<String>, command: impl FnOnce(Rc<SpotifyConfig>, String) -> F + 'static) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let device_id = self.target_device_id()?;
+        let tokens = self.tokens.clone();
+        self.spawn(async move {
+            command(tokens.config().await, device_id).await?;
+            Ok(Update::Done(status))
+        });
+        Ok(())
+    }
+
+    fn refresh_playback(&self) {
+        self.spawn(fetch_playback(self.tokens.clone()));
+    }
+
+    fn refresh_lists(&self) {
+        self.spawn(fetch_lists(self.tokens.clone()));
+    }
+
+    fn refresh_after_action(&self) {
+        let tokens = self.tokens.clone();
+        self.spawn(async move {
+            sleep(ACTION_SETTLE).await;
+            fetch_playback(tokens).await
+        });
+        let tokens = self.tokens.clone();
+        self.spawn(async move {
+            sleep(ACTION_SETTLE).await;
+            fetch_lists(tokens).await
+        });
+    }
+
+    /// The device commands go to: the one playing, or else the one selected in the device list.
+    fn target_device_id(&self) -> Result<String> {
+        self.playback.as_ref()
+            .and_then(|state| state.device.id.clone())
+            .or_else(|| self.devices.get(self.device_index).and_then(|device| device.id.clone()))
+            .ok_or_else(|| anyhow!("No active device; select one in the device list"))
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Result<()> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return Ok(());
+        }
+        if self.editing {
+            self.handle_search_input(key);
+            return Ok(());
+        }
+
+        self.status = None;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab => self.focus = match self.focus {
+                Pane::Devices => Pane::Queue,
+                Pane::Queue => Pane::Search,
+                Pane::Search => Pane::Devices,
+            },
+            KeyCode::BackTab => self.focus = match self.focus {
+                Pane::Devices => Pane::Search,
+                Pane::Queue => Pane::Devices,
+                Pane::Search => Pane::Queue,
+            },
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Char('/') => {
+                self.focus = Pane::Search;
+                self.editing = true;
+            }
+            KeyCode::Char('t') => {
+                self.search_kind = self.search_kind.next();
+                self.search();
+            }
+            KeyCode::Char(' ') => {
+                if self.playback.as_ref().is_some_and(|state| state.is_playing) {
+                    self.command(None, |config, device_id| async move { pause(&config, &device_id).await })?;
+                } else {
+                    self.command(None, |config, device_id| async move { start_playing(&config, &device_id).await })?;
+                }
+            }
+            KeyCode::Char('n') => self.command(None, |config, device_id| async move { skip_to_next(&config, &device_id).await })?,
+            KeyCode::Char('p') => self.command(None, |config, device_id| async move { skip_to_previous(&config, &device_id).await })?,
+            KeyCode::Left => self.seek_by(-SEEK_STEP_MS)?,
+            KeyCode::Right => self.seek_by(SEEK_STEP_MS)?,
+            KeyCode::Char('+') | KeyCode::Char('=') => self.change_volume(VOLUME_STEP)?,
+            KeyCode::Char('-') => self.change_volume(-VOLUME_STEP)?,
+            KeyCode::Char('r') => {
+                self.refresh_playback();
+                self.refresh_lists();
+            }
+            KeyCode::Char('a') if self.focus == Pane::Search => self.enqueue_selected()?,
+            KeyCode::Enter => self.activate_selected()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_search_input(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Esc => self.editing = false,
+            KeyCode::Enter => {
+                self.editing = false;
+                self.search();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+            }
+            KeyCode::Char(c) => self.query.push(c),
+            _ => {}
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let (index, len) = match self.focus {
+            Pane::Devices => (&mut self.device_index, self.devices.len()),
+            Pane::Queue => (&mut self.queue_index, self.queue.len()),
+            Pane::Search => (&mut self.result_index, self.results.len()),
+        };
+        if len > 0 {
+            *index = (*index as isize + delta).clamp(0, len as isize - 1) as usize;
+        }
+    }
+
+    fn seek_by(&self, delta_ms: i64) -> Result<()> {
+        let progress = self.progress_ms()
+            .ok_or_else(|| anyhow!("Nothing is playing"))?;
+        let position = (progress as i64 + delta_ms).max(0) as u32;
+        self.command(None, move |config, device_id| async move { seek(&config, &device_id, position).await })
+    }
+
+    fn change_volume(&self, delta: i32) -> Result<()> {
+        let volume = self.playback.as_ref()
+            .and_then(|state| state.device.volume_percent)
+            .ok_or_else(|| anyhow!("The device does not report its volume"))?;
+        let volume = (volume as i32 + delta).clamp(0, 100) as u32;
+        self.command(None, move |config, device_id| async move { set_volume(&config, &device_id, volume).await })
+    }
+
+    fn activate_selected(&self) -> Result<()> {
+        match self.focus {
+            Pane::Devices => {
+                let device = self.devices.get(self.device_index)
+                    .ok_or_else(|| anyhow!("No device selected"))?;
+                let device_id = device.id.clone()
+                    .ok_or_else(|| anyhow!("Device \"{}\" cannot be controlled through the Web API", device.name))?;
+                let playing = self.playback.as_ref().is_some_and(|state| state.is_playing);
+                let status = format!("Moved playback to {}", device.name);
+                let tokens = self.tokens.clone();
+                self.spawn(async move {
+                    transfer_playback(&tokens.config().await, &device_id, playing).await?;
+                    Ok(Update::Done(Some(status)))
+                });
+                Ok(())
+            }
+            Pane::Queue => Ok(()),
+            Pane::Search => {
+                let result = self.results.get(self.result_index)
+                    .ok_or_else(|| anyhow!("No search result selected"))?;
+                let request = match self.search_kind {
+                    SearchKind::Tracks => PlaybackRequest::uris(vec![result.uri.clone()]),
+                    SearchKind::Albums | SearchKind::Artists => PlaybackRequest::context(&result.uri),
+                };
+                self.command(None, |config, device_id| async move { start_playback(&config, &device_id, request).await })
+            }
+        }
+    }
+
+    fn enqueue_selected(&self) -> Result<()> {
+        if self.search_kind == SearchKind::Artists {
+            bail!("Artists cannot be queued; play them instead")
+        }
+        let result = self.results.get(self.result_index)
+            .ok_or_else(|| anyhow!("No search result selected"))?;
+        let uri = result.uri.clone();
+        let device_id = self.target_device_id()?;
+        let tokens = self.tokens.clone();
+        self.spawn(async move {
+            let report = enqueue_tracks(&tokens.config().await, &device_id, vec![uri]).await?;
+            let status = format!("Queued {} track(s), {} of them already in the queue", report.queued.len(), report.already_queued.len());
+            Ok(Update::Done(Some(status)))
+        });
+        Ok(())
+    }
+
+    fn search(&self) {
+        if self.query.trim().is_empty() {
+            return;
+        }
+        self.spawn(search(self.tokens.clone(), self.search_kind, self.query.clone()));
+    }
+}
+
+async fn fetch_playback(tokens: Rc<TokenKeeper>) -> Result<Update> {
+    let playback = get_playback_state(&tokens.config().await).await?;
+    Ok(Update::Playback(playback.map(Box::new)))
+}
+
+async fn fetch_lists(tokens: Rc<TokenKeeper>) -> Result<Update> {
+    let config = tokens.config().await;
+    let devices = DeviceResolver::fetch(&config).await?.devices().to_vec();
+    let queue = get_queue(&config).await?.queue;
+    Ok(Update::Lists(devices, queue))
+}
+
+async fn search(tokens: Rc<TokenKeeper>, kind: SearchKind, query: String) -> Result<Update> {
+    let config = tokens.config().await;
+    let results = match kind {
+        SearchKind::Tracks => {
+            let response = SearchTracks::new(&config, &query).execute().await?;
+            response.tracks.items.into_iter()
+                .map(|track| SearchResult {
+                    label: format!("{} - {}", track.name, track.artist_names()),
+                    uri: track.uri,
+                })
+                .collect()
+        }
+        SearchKind::Albums => {
+            let response = SearchAlbums::new(&config, &query).execute().await?;
+            response.albums.items.into_iter()
+                .map(|album| SearchResult {
+                    label: format!("{} - {} ({})", album.name, album.artist_names(), album.release_date),
+                    uri: album.uri,
+                })
+                .collect()
+        }
+        SearchKind::Artists => {
+            let response = SearchArtists::new(&config, &query).execute().await?;
+            response.artists.items.into_iter()
+                .map(|artist| SearchResult {
+                    label: artist.name,
+                    uri: artist.uri,
+                })
+                .collect()
+        }
+    };
+    Ok(Update::Results(results))
+}
@@ -0,0 +1,144 @@
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, ListState, Paragraph};
+
+use crate::objects::PlayableItem;
+use super::app::{App, Pane};
+
+const HELP: &str = "space play/pause  n/p next/prev  \u{2190}/\u{2192} seek  +/- volume  tab pane  / search  t type  enter select  a queue  q quit";
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [now_playing, lists, status] = Layout::vertical([
+        Constraint::Length(6),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ]).areas(frame.area());
+    let [left, search] = Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(lists);
+    let [devices, queue] = Layout::vertical([Constraint::Length(8), Constraint::Min(0)]).areas(left);
+
+    draw_now_playing(frame, app, now_playing);
+    draw_devices(frame, app, devices);
+    draw_queue(frame, app, queue);
+    draw_search(frame, app, search);
+
+    let status_line = match &app.status {
+        Some(status) => Line::styled(status.as_str(), Style::default().fg(Color::Yellow)),
+        None => Line::styled(HELP, Style::default().fg(Color::DarkGray)),
+    };
+    frame.render_widget(Paragraph::new(status_line), status);
+}
+
+fn pane_block(title: String, focused: bool) -> Block<'static> {
+    let style = if focused { Style::default().fg(Color::Green) } else { Style::default() };
+    Block::bordered().title(title).border_style(style)
+}
+
+fn highlight() -> Style {
+    Style::default().add_modifier(Modifier::REVERSED)
+}
+
+fn format_ms(ms: u32) -> String {
+    let seconds = ms / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn describe_item(item: &PlayableItem) -> (String, String) {
+    match item {
+        PlayableItem::Track(track) => {
+            let artists = track.artist_names();
+            let album = track.album.as_ref().map_or(String::new(), |album| format!(" \u{2014} {}", album.name));
+            (track.name.clone(), artists + &album)
+        }
+        PlayableItem::Episode(episode) => {
+            let show = episode.show.as_ref().map_or(String::new(), |show| show.name.clone());
+            (episode.name.clone(), show)
+        }
+    }
+}
+
+fn draw_now_playing(frame: &mut Frame, app: &App, area: Rect) {
+    let block = Block::bordered().title("Now playing");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let state = match &app.playback {
+        Some(state) => state,
+        None => {
+            frame.render_widget(Paragraph::new("Nothing is playing"), inner);
+            return;
+        }
+    };
+    let [text, gauge] = Layout::vertical([Constraint::Length(3), Constraint::Length(1)]).areas(inner);
+
+    let (title, subtitle) = state.item.as_ref().map_or((String::from("Unknown item"), String::new()), describe_item);
+    let volume = state.device.volume_percent.map_or(String::from("?"), |volume| volume.to_string());
+    let settings = format!("{} on {}  volume {}%  shuffle {}  repeat {}",
+        if state.is_playing { "\u{25b6} Playing" } else { "\u{23f8} Paused" },
+        state.device.name,
+        volume,
+        if state.shuffle_state { "on" } else { "off" },
+        state.repeat_state);
+    let lines = vec![
+        Line::styled(title, Style::default().add_modifier(Modifier::BOLD)),
+        Line::from(subtitle),
+        Line::styled(settings, Style::default().fg(Color::DarkGray)),
+    ];
+    frame.render_widget(Paragraph::new(lines), text);
+
+    let duration = state.item.as_ref().map_or(0, |item| item.duration_ms());
+    let progress = app.progress_ms().unwrap_or(0);
+    let ratio = if duration > 0 { progress as f64 / duration as f64 } else { 0.0 };
+    let gauge_widget = Gauge::default()
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio(ratio.clamp(0.0, 1.0))
+        .label(format!("{} / {}", format_ms(progress), format_ms(duration)));
+    frame.render_widget(gauge_widget, gauge);
+}
+
+fn draw_devices(frame: &mut Frame, app: &App, area: Rect) {
+    let items = app.devices.iter()
+        .map(|device| {
+            let marker = if device.is_active { "\u{25cf} " } else { "  " };
+            ListItem::new(format!("{}{} ({})", marker, device.name, device.device_type))
+        })
+        .collect::<Vec<_>>();
+    let list = List::new(items)
+        .block(pane_block(String::from("Devices"), app.focus == Pane::Devices))
+        .highlight_style(highlight());
+    let mut state = ListState::default().with_selected(Some(app.device_index).filter(|_| app.focus == Pane::Devices));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn draw_queue(frame: &mut Frame, app: &App, area: Rect) {
+    let items = app.queue.iter()
+        .map(|item| {
+            let (title, subtitle) = describe_item(item);
+            ListItem::new(format!("{} - {}", title, subtitle))
+        })
+        .collect::<Vec<_>>();
+    let list = List::new(items)
+        .block(pane_block(String::from("Queue"), app.focus == Pane::Queue))
+        .highlight_style(highlight());
+    let mut state = ListState::default().with_selected(Some(app.queue_index).filter(|_| app.focus == Pane::Queue));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn draw_search(frame: &mut Frame, app: &App, area: Rect) {
+    let block = pane_block(format!("Search {} (t to change)", app.search_kind.label()), app.focus == Pane::Search);
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let [input, results] = Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+
+    let cursor = if app.editing { "\u{2588}" } else { "" };
+    let input_style = if app.editing { Style::default().fg(Color::Yellow) } else { Style::default() };
+    frame.render_widget(Paragraph::new(Line::styled(format!("/ {}{}", app.query, cursor), input_style)), input);
+
+    let items = app.results.iter()
+        .map(|result| ListItem::new(result.label.as_str()))
+        .collect::<Vec<_>>();
+    let list = List::new(items).highlight_style(highlight());
+    let mut state = ListState::default().with_selected(Some(app.result_index).filter(|_| app.focus == Pane::Search));
+    frame.render_stateful_widget(list, results, &mut state);
+}
@@ -0,0 +1,340 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures::stream::{self, Stream};
+use serde_derive::Serialize;
+use tokio::time::sleep;
+
+use crate::api::get_playback_state;
+use crate::config::{SpotifyConfig, TokenKeeper};
+use crate::objects::{Device, PlayableItem, PlaybackState};
+
+/// Progress drift beyond this is reported as a seek rather than polling jitter.
+const SEEK_TOLERANCE_MS: i64 = 3000;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PlayerEvent {
+    /// Also sent when a track starts over, as with repeat-one, in which case `previous_uri` is its own URI.
+    TrackChanged {
+        previous_uri: Option<String>,
+        item: Option<Box<PlayableItem>>,
+    },
+    Resumed,
+    Paused,
+    Stopped,
+    Seeked {
+        from_ms: u32,
+        to_ms: u32,
+    },
+    DeviceChanged {
+        previous_name: Option<String>,
+        device: Device,
+    },
+    VolumeChanged {
+        previous: Option<u32>,
+        volume: Option<u32>,
+    },
+    ShuffleChanged {
+        shuffle: bool,
+    },
+    RepeatChanged {
+        repeat: String,
+    },
+    ContextChanged {
+        previous_uri: Option<String>,
+        uri: Option<String>,
+    },
+}
+
+impl PlayerEvent {
+    /// The event name as it appears in the serialized `event` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlayerEvent::TrackChanged { .. } => "track_changed",
+            PlayerEvent::Resumed => "resumed",
+            PlayerEvent::Paused => "paused",
+            PlayerEvent::Stopped => "stopped",
+            PlayerEvent::Seeked { .. } => "seeked",
+            PlayerEvent::DeviceChanged { .. } => "device_changed",
+            PlayerEvent::VolumeChanged { .. } => "volume_changed",
+            PlayerEvent::ShuffleChanged { .. } => "shuffle_changed",
+            PlayerEvent::RepeatChanged { .. } => "repeat_changed",
+            PlayerEvent::ContextChanged { .. } => "context_changed",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchIntervals {
+    pub playing: Duration,
+    pub paused: Duration,
+    pub idle: Duration,
+    pub minimum: Duration,
+}
+
+impl Default for WatchIntervals {
+    fn default() -> Self {
+        Self {
+            playing: Duration::from_secs(5),
+            paused: Duration::from_secs(15),
+            idle: Duration::from_secs(30),
+            minimum: Duration::from_secs(1),
+        }
+    }
+}
+
+pub struct PlayerWatcher {
+    config: Rc<SpotifyConfig>,
+    intervals: WatchIntervals,
+    state: Option<PlaybackState>,
+    polled_at: Option<Instant>,
+    pending: VecDeque<PlayerEvent>,
+}
+
+impl PlayerWatcher {
+    pub fn new(config: &Rc<SpotifyConfig>) -> Self {
+        Self {
+            config: config.clone(),
+            intervals: WatchIntervals::default(),
+            state: None,
+            polled_at: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn with_intervals(self, intervals: WatchIntervals) -> Self {
+        Self {
+            intervals,
+            ..self
+        }
+    }
+
+    /// Swaps in a configuration with a fresh access token.
+    pub fn set_config(&mut self, config: &Rc<SpotifyConfig>) {
+        self.config = config.clone();
+    }
+
+    /// The playback state seen by the latest successful poll.
+    pub fn state(&self) -> Option<&PlaybackState> {
+        self.state.as_ref()
+    }
+
+    /// Estimated playback position now, extrapolated from the latest poll.
+    pub fn progress_ms(&self) -> Option<u32> {
+        let state = self.state.as_ref()?;
+        let progress = state.progress_ms?;
+        if !state.is_playing {
+            return Some(progress);
+        }
+        let elapsed = self.polled_at.map_or(0, |polled_at| polled_at.elapsed().as_millis() as u32);
+        let duration = state.item.as_ref().map_or(u32::MAX, |item| item.duration_ms());
+        Some(progress.saturating_add(elapsed).min(duration))
+    }
+
+    /// Fetches the playback state once and returns the changes since the previous poll.
+    pub async fn poll(&mut self) -> Result<Vec<PlayerEvent>> {
+        let previous_polled_at = self.polled_at.replace(Instant::now());
+        let current = get_playback_state(&self.config).await?;
+        let elapsed = previous_polled_at.map_or(Duration::from_secs(0), |polled_at| polled_at.elapsed());
+        let events = diff(self.state.as_ref(), current.as_ref(), elapsed);
+        self.state = current;
+        Ok(events)
+    }
+
+    /// How long to wait before the next poll: short near the end of a track, long when nothing plays.
+    pub fn next_delay(&self) -> Duration {
+        let state = match &self.state {
+            Some(state) => state,
+            None => return self.intervals.idle,
+        };
+        if !state.is_playing {
+            return self.intervals.paused;
+        }
+
+        let remaining = match (self.progress_ms(), &state.item) {
+            (Some(progress), Some(item)) => item.duration_ms().saturating_sub(progress),
+            _ => return self.intervals.playing,
+        };
+        let until_end = Duration::from_millis(remaining as u64 + 500);
+        until_end.max(self.intervals.minimum).min(self.intervals.playing)
+    }
+
+    /// Polls until something changes, with the access token from `tokens` kept fresh for every poll.
+    pub async fn next_event(&mut self, tokens: &TokenKeeper) -> Result<PlayerEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            if self.polled_at.is_some() {
+                sleep(self.next_delay()).await;
+            }
+            self.set_config(&tokens.config().await);
+            let events = self.poll().await?;
+            self.pending.extend(events);
+        }
+    }
+
+    pub fn into_stream(self, tokens: TokenKeeper) -> impl Stream<Item = Result<PlayerEvent>> {
+        stream::unfold((self, tokens), |(mut watcher, tokens)| async move {
+            let event = watcher.next_event(&tokens).await;
+            Some((event, (watcher, tokens)))
+        })
+    }
+}
+
+fn item_uri(state: Option<&PlaybackState>) -> Option<&str> {
+    state.and_then(|state| state.item.as_ref())
+        .map(|item| item.uri())
+}
+
+fn context_uri(state: Option<&PlaybackState>) -> Option<&str> {
+    state.and_then(|state| state.context.as_ref())
+        .map(|context| context.uri.as_str())
+}
+
+fn same_device(a: &Device, b: &Device) -> bool {
+    match (&a.id, &b.id) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.name == b.name,
+    }
+}
+
+fn diff(previous: Option<&PlaybackState>, current: Option<&PlaybackState>, elapsed: Duration) -> Vec<PlayerEvent> {
+    let mut events = vec![];
+
+    if item_uri(previous) != item_uri(current) {
+        events.push(PlayerEvent::TrackChanged {
+            previous_uri: item_uri(previous).map(|uri| uri.to_owned()),
+            item: current.and_then(|state| state.item.clone()).map(Box::new),
+        });
+    }
+    if context_uri(previous) != context_uri(current) {
+        events.push(PlayerEvent::ContextChanged {
+            previous_uri: context_uri(previous).map(|uri| uri.to_owned()),
+            uri: context_uri(current).map(|uri| uri.to_owned()),
+        });
+    }
+
+    let current = match current {
+        Some(current) => current,
+        None => {
+            if previous.is_some() {
+                events.push(PlayerEvent::Stopped);
+            }
+            return events;
+        }
+    };
+
+    let was_playing = previous.is_some_and(|previous| previous.is_playing);
+    match (was_playing, current.is_playing) {
+        (false, true) => events.push(PlayerEvent::Resumed),
+        (true, false) => events.push(PlayerEvent::Paused),
+        _ => {}
+    }
+
+    let previous = match previous {
+        Some(previous) => previous,
+        None => {
+            events.push(PlayerEvent::DeviceChanged {
+                previous_name: None,
+                device: current.device.clone(),
+            });
+            return events;
+        }
+    };
+
+    if same_device(&previous.device, &current.device) {
+        if previous.device.volume_percent != current.device.volume_percent {
+            events.push(PlayerEvent::VolumeChanged {
+                previous: previous.device.volume_percent,
+                volume: current.device.volume_percent,
+            });
+        }
+    } else {
+        events.push(PlayerEvent::DeviceChanged {
+            previous_name: Some(previous.device.name.clone()),
+            device: current.device.clone(),
+        });
+    }
+
+    if previous.shuffle_state != current.shuffle_state {
+        events.push(PlayerEvent::ShuffleChanged {
+            shuffle: current.shuffle_state,
+        });
+    }
+    if previous.repeat_state != current.repeat_state {
+        events.push(PlayerEvent::RepeatChanged {
+            repeat: current.repeat_state.clone(),
+        });
+    }
+
+    if item_uri(Some(previous)) == item_uri(Some(current)) && previous.is_playing == current.is_playing {
+        if let (Some(from_ms), Some(to_ms)) = (previous.progress_ms, current.progress_ms) {
+            let advanced = if previous.is_playing { elapsed.as_millis() as i64 } else { 0 };
+            let expected = from_ms as i64 + advanced;
+            let duration = current.item.as_ref().map_or(i64::MAX, |item| item.duration_ms() as i64);
+            // Having run past its end and started again, the track is as far in as the overrun.
+            let restarted = expected >= duration - SEEK_TOLERANCE_MS
+                && (to_ms as i64) <= (expected - duration).max(0) + SEEK_TOLERANCE_MS;
+            if restarted {
+                events.push(PlayerEvent::TrackChanged {
+                    previous_uri: item_uri(Some(previous)).map(|uri| uri.to_owned()),
+                    item: current.item.clone().map(Box::new),
+                });
+            } else if (to_ms as i64 - expected).abs() > SEEK_TOLERANCE_MS {
+                events.push(PlayerEvent::Seeked { from_ms, to_ms });
+            }
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn playing(progress_ms: u32) -> PlaybackState {
+        serde_json::from_value(json!({
+            "device": {
+                "id": "abc123", "is_active": true, "is_private_session": false, "is_restricted": false,
+                "name": "Kitchen", "type": "Speaker", "volume_percent": 50,
+            },
+            "repeat_state": "track",
+            "shuffle_state": false,
+            "context": null,
+            "timestamp": 0,
+            "progress_ms": progress_ms,
+            "is_playing": true,
+            "item": {
+                "type": "track", "id": "t1", "linked_from": null, "href": null, "album": null, "artists": [],
+                "name": "Song", "disc_number": 1, "track_number": 1, "duration_ms": 200_000, "uri": "spotify:track:t1",
+            },
+            "currently_playing_type": "track",
+        })).unwrap()
+    }
+
+    #[test]
+    fn repeat_one_is_a_track_change() {
+        let events = diff(Some(&playing(195_000)), Some(&playing(2_000)), Duration::from_secs(5));
+        match events.as_slice() {
+            [PlayerEvent::TrackChanged { previous_uri, item }] => {
+                assert_eq!(previous_uri.as_deref(), Some("spotify:track:t1"));
+                assert_eq!(item.as_ref().map(|item| item.uri()), Some("spotify:track:t1"));
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn jumping_back_mid_track_is_a_seek() {
+        let events = diff(Some(&playing(100_000)), Some(&playing(2_000)), Duration::from_secs(5));
+        assert!(matches!(events.as_slice(), [PlayerEvent::Seeked { from_ms: 100_000, to_ms: 2_000 }]));
+        assert!(diff(Some(&playing(100_000)), Some(&playing(105_500)), Duration::from_secs(5)).is_empty());
+    }
+}